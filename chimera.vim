@@ -9,6 +9,8 @@ endif
 
 syn keyword chiTodo      contained TODO FIXME HACK NOTE
 syn match   chiComment   "--.*$" contains=chiTodo
+syn region  chiComment   start="{-" end="-}" contains=chiComment,chiTodo
+syn match   chiDocComment "^\s*---\([^-].*\)\?$" contains=chiTodo
 
 syn match   chiNumber    '\d\+'
 syn match   chiNumber    '[-+]\d\+'
@@ -26,14 +28,18 @@ syn match   chiTypeName  '[A-Z][a-zA-Z0-9]*'
 
 syn keyword chiKeywords  let do end
 syn keyword chiKeywords  data forall
+syn keyword chiKeywords  infixl infixr infix
 syn keyword chiKeywords  if then elif else
 syn keyword chiKeywords  loop break
+syn keyword chiKeywords  effect handle with
+syn keyword chiKeywords  try catch match
 
 let b:current_syntax = "chimera"
 
 hi def link chiTodo      Todo
 hi def link chiKeywords  Keyword
 hi def link chiComment   Comment
+hi def link chiDocComment SpecialComment
 hi def link chiNumber    Number
 hi def link chiString    String
 hi def link chiChar      Character
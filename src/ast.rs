@@ -1,6 +1,63 @@
 /// Chimera's abstract syntax tree.
 /// The layout is highly inspired by rustc's own ast.
-use polytype::TypeSchema;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::slice;
+
+use polytype::{Type, TypeSchema};
+
+/// The parameter of the lambdas that operator sections are desugared into,
+/// it cannot clash with user-defined names as it isn't a valid name.
+pub const SECTION_PARAM: &str = "section#";
+
+/// The parameter that binds the operand of a right section, see `SECTION_PARAM`.
+pub const OPERAND_PARAM: &str = "operand#";
+
+thread_local! {
+    static NAMES: RefCell<HashSet<&'static str>> = RefCell::new(HashSet::new());
+}
+
+/// Interns the type name `name`. Polytype only implements `Name` for
+/// `&'static str`, so every distinct name is leaked, but only once.
+pub fn intern(name: &str) -> &'static str {
+    NAMES.with(|names| {
+        let mut names = names.borrow_mut();
+        match names.get(name) {
+            Some(interned) => *interned,
+            None => {
+                let interned: &'static str = Box::leak(name.to_string().into_boxed_str());
+                names.insert(interned);
+                interned
+            }
+        }
+    })
+}
+
+/// A region of the source code, along with the line and column it starts at.
+/// The source is the `file`th one of the program.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub file: usize,
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    pub fn new(source: &str, file: usize, start: usize, end: usize) -> Self {
+        let before = &source[..start];
+        let line = before.matches('\n').count() + 1;
+        let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+        Span { file, start, end, line, column }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Attr {
@@ -10,6 +67,8 @@ pub struct Attr {
 
 #[derive(Debug, PartialEq, Clone)]
 pub struct Item {
+    /// The `---` doc comment lines preceding the item, joined by newlines.
+    pub doc: Option<String>,
     pub attr: Option<Attr>,
     pub kind: ItemKind,
 }
@@ -25,10 +84,36 @@ pub enum ItemKind {
         schema: TypeSchema,
         variants: Vec<(String, Vec<(String, TypeSchema)>)>,
     },
+    Effect {
+        schema: TypeSchema,
+        operations: Vec<Operation>,
+    },
     Module {
         name: String,
         items: Vec<Item>,
     },
+    Fixity {
+        assoc: Assoc,
+        precedence: i64,
+        operators: Vec<String>,
+    },
+}
+
+/// An operation of an effect declaration, such as `get : Void -> s`.
+/// Its annotation doesn't mention the effect itself.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Operation {
+    pub doc: Option<String>,
+    pub attr: Option<Attr>,
+    pub name: String,
+    pub ann: TypeSchema,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Assoc {
+    Left,
+    Right,
+    None,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -40,7 +125,9 @@ pub enum Stmt {
 #[derive(Debug, PartialEq, Clone)]
 pub enum Expr {
     // Primitives
-    Ellipsis,
+    // A typed hole, unless it is the body of an intrinsic.
+    Ellipsis(Span),
+    // ...
     Void,
     // ()
     Int(i64),
@@ -54,18 +141,75 @@ pub enum Expr {
     // Functions
     Name(String),
     List(Vec<Expr>),
+    Array(Vec<Expr>),
+    // #[1, 2, 3]
     // coolName
     // TODO: enforce the fact that the last Instr in a code-block
     // should be an Expr by baking it into the parser, producing
     // a seperate `expr` field here. This will simplify the
     // type-checker somewhat.
-    Lambda { param: String, expr: Box<Expr> },
+    Lambda { param: String, ann: Option<Type>, expr: Box<Expr> },
     // |x| x + 1
+    // |x: Int, y| x + y
     Block { body: Vec<Stmt> },
+    // A chain of infix operators `head op1 e1 op2 e2 ...`, this is resolved
+    // into nested `Apply`s by the fixity pass right after parsing.
+    Infix { head: Box<Expr>, tail: Vec<(String, Expr)> },
     Apply { left: Box<Expr>, right: Box<Expr> },
     // f x
+    Negate { expr: Box<Expr> },
+    // -x
+    Ascribe { expr: Box<Expr>, ann: Type },
+    // (xs : List Int)
     Branch { paths: Vec<(Expr, Vec<Stmt>)> },
     // if cond then 0 else 42 end
     Field { expr: Box<Expr>, name: String },
     Assign { left: Box<Expr>, right: Box<Expr> }, // i = 0
+    Handle { expr: Box<Expr>, clauses: Vec<Clause> },
+    // handle get () with
+    //     get _ resume -> resume 42
+    // end
+    Try { expr: Box<Expr>, pattern: Pattern, handler: Box<Expr> },
+    // try head xs catch e -> 0 end
+    Match { expr: Box<Expr>, arms: Vec<Arm> },
+    // match xs with
+    //     Some x -> x
+    //     None -> 0
+    // end
+    // Where `expr` is found in the source, for the errors of the checker.
+    Located { span: Span, expr: Box<Expr> },
+}
+
+/// The arm `pattern -> body` of a match expression.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Expr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    /// A constructor, whose fields are bound to names in order: `Some x`.
+    Constructor { name: String, fields: Vec<String> },
+    /// Matches anything, which is bound to the name.
+    Name(String),
+}
+
+impl Pattern {
+    /// The names the pattern binds, in order.
+    pub fn names(&self) -> &[String] {
+        match self {
+            Pattern::Constructor { fields, .. } => fields,
+            Pattern::Name(name) => slice::from_ref(name),
+        }
+    }
+}
+
+/// The clause `operation param resume -> body` of a handler.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Clause {
+    pub operation: String,
+    pub param: String,
+    pub resume: String,
+    pub body: Expr,
 }
@@ -1,212 +1,621 @@
-use std::cell::RefCell;
-use std::io::{self, Read};
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
 use std::rc::Rc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::code::CompiledCode;
+use crate::code::{Outcome, Unwind};
+use crate::registry::Registry;
+use crate::sandbox;
+use crate::tree::{self, Tree};
 use crate::value::{List, Value, WoValue};
-use crate::Env;
-
-pub fn intrinsic(name: &str) -> Value {
-    match name {
-        "print" => Value::Lambda {
-            param: "value".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                let value = Env::get_name(env, "value");
-                print!("{}", value.borrow());
-                Value::Void.into()
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
-        },
-        "read" => Value::Lambda {
-            param: "_".to_string(),
-            body: Rc::new(CompiledCode::new(move |_env| {
-                let mut buffer = String::new();
-                io::stdin()
-                    .read_to_string(&mut buffer)
-                    .expect("chimera: error reading from stdin. You are on your own.");
-                let string = buffer
-                    .chars()
-                    .map(|c| Value::Char(c).into())
-                    .collect::<Vec<WoValue>>()
-                    .into();
-                Value::List(string).into()
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
-        },
-        "cmp" => Value::Lambda {
-            param: "x".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                Value::Lambda {
-                    param: "y".to_string(),
-                    body: Rc::new(CompiledCode::new(move |env| {
-                        let x = Env::get_name(env.clone(), "x");
-                        let y = Env::get_name(env, "y");
-                        Value::Bool(x == y).into()
-                    })),
-                    closure: env,
-                }
-                .into()
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
-        },
-        "add" => Value::Lambda {
-            param: "x".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                Value::Lambda {
-                    param: "y".to_string(),
-                    body: Rc::new(CompiledCode::new(move |env| {
-                        if let Value::Int(l) = *Env::get_name(env.clone(), "x").borrow() {
-                            if let Value::Int(r) = *Env::get_name(env, "y").borrow() {
-                                Value::Int(l + r).into()
-                            } else {
-                                unreachable!()
-                            }
-                        } else {
-                            unreachable!()
-                        }
-                    })),
-                    closure: env,
-                }
-                .into()
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
-        },
-        "sub" => Value::Lambda {
-            param: "x".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                Value::Lambda {
-                    param: "y".to_string(),
-                    body: Rc::new(CompiledCode::new(move |env| {
-                        if let Value::Int(l) = *Env::get_name(env.clone(), "x").borrow() {
-                            if let Value::Int(r) = *Env::get_name(env, "y").borrow() {
-                                Value::Int(l - r).into()
-                            } else {
-                                unreachable!()
-                            }
-                        } else {
-                            unreachable!()
-                        }
-                    })),
-                    closure: env,
-                }
-                .into()
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
-        },
-        "mul" => Value::Lambda {
-            param: "x".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                Value::Lambda {
-                    param: "y".to_string(),
-                    body: Rc::new(CompiledCode::new(move |env| {
-                        if let Value::Int(l) = *Env::get_name(env.clone(), "x").borrow() {
-                            if let Value::Int(r) = *Env::get_name(env, "y").borrow() {
-                                Value::Int(l * r).into()
-                            } else {
-                                unreachable!()
-                            }
-                        } else {
-                            unreachable!()
-                        }
-                    })),
-                    closure: env,
-                }
-                .into()
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
-        },
-        "div" => Value::Lambda {
-            param: "x".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                Value::Lambda {
-                    param: "y".to_string(),
-                    body: Rc::new(CompiledCode::new(move |env| {
-                        if let Value::Int(l) = *Env::get_name(env.clone(), "x").borrow() {
-                            if let Value::Int(r) = *Env::get_name(env, "y").borrow() {
-                                Value::Int(l / r).into()
-                            } else {
-                                unreachable!()
-                            }
-                        } else {
-                            unreachable!()
-                        }
-                    })),
-                    closure: env,
-                }
-                .into()
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
-        },
-        "modulus" => Value::Lambda {
-            param: "x".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                Value::Lambda {
-                    param: "y".to_string(),
-                    body: Rc::new(CompiledCode::new(move |env| {
-                        if let Value::Int(l) = *Env::get_name(env.clone(), "x").borrow() {
-                            if let Value::Int(r) = *Env::get_name(env, "y").borrow() {
-                                Value::Int(l % r).into()
-                            } else {
-                                unreachable!()
-                            }
-                        } else {
-                            unreachable!()
-                        }
-                    })),
-                    closure: env,
-                }
-                .into()
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
-        },
-        "cons" => Value::Lambda {
-            param: "elem".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                Value::Lambda {
-                    param: "list".to_string(),
-                    body: Rc::new(CompiledCode::new(move |env| {
-                        let elem = Env::get_name(env.clone(), "elem");
-                        if let Value::List(list) = &*Env::get_name(env, "list").borrow() {
-                            Value::List(List::Cons(elem, Box::new(list.clone()))).into()
-                        } else {
-                            panic!("chimera: can only call cons on a list.");
-                        }
-                    })),
-                    closure: env,
+
+thread_local! {
+    // The arguments given to the program after `--` on the command line.
+    static ARGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    // The state of the random number generator, which interpreters install
+    // for the code they run, see `Interpreter::set_seed`.
+    static SEED: Cell<u64> = Cell::new(clock_seed());
+    // When the program started, for the monotonic clock.
+    static START: Instant = Instant::now();
+}
+
+/// Sets the arguments the `args` intrinsic returns.
+pub fn set_args(args: Vec<String>) {
+    ARGS.with(|a| *a.borrow_mut() = args);
+}
+
+/// Seeds the random number generator, so that runs are reproducible.
+pub fn set_seed(seed: u64) {
+    SEED.with(|s| s.set(seed));
+}
+
+/// The state of the random number generator, to carry it over to later runs.
+pub fn seed() -> u64 {
+    SEED.with(Cell::get)
+}
+
+/// A seed taken from the clock, for runs that needn't be reproducible.
+pub fn clock_seed() -> u64 {
+    now().as_nanos() as u64
+}
+
+/// Defines the natives the core library is bound to.
+pub fn builtins(registry: &mut Registry) {
+    registry.define("print", 1, |args| {
+        stdio("print", write!(io::stdout().lock(), "{}", args[0].borrow()))?;
+        Ok(Value::Void.into())
+    });
+    // Invalid UTF-8 is read as U+FFFD, and failing to read raises an exception.
+    registry.define_io("read", 1, |_| {
+        let mut bytes = Vec::new();
+        stdio("read", io::stdin().read_to_end(&mut bytes))?;
+        Ok(chars(&String::from_utf8_lossy(&bytes)).into())
+    });
+    registry.define("flush", 1, |_| {
+        stdio("flush", io::stdout().flush())?;
+        Ok(Value::Void.into())
+    });
+    // Stdin is buffered, so the following read no more than they need.
+    registry.define_io("read_line", 1, |_| {
+        let mut bytes = Vec::new();
+        let read = stdio("read_line", io::stdin().lock().read_until(b'\n', &mut bytes))?;
+        if bytes.ends_with(b"\n") {
+            bytes.pop();
+            if bytes.ends_with(b"\r") {
+                bytes.pop();
+            }
+        }
+        Ok(option((read > 0).then(|| chars(&String::from_utf8_lossy(&bytes)))))
+    });
+    registry.define_io("read_char", 1, |_| {
+        let c = stdio("read_char", read_char(&mut io::stdin().lock()))?;
+        Ok(option(c.map(Value::Char)))
+    });
+    registry.define_io("read_bytes", 1, |args| {
+        let mut bytes = Vec::new();
+        let mut input = io::stdin().lock().take(int(&args[0]).max(0) as u64);
+        stdio("read_bytes", input.read_to_end(&mut bytes))?;
+        let bytes = bytes
+            .into_iter()
+            .map(|b| Value::Int(b.into()).into())
+            .collect::<Vec<WoValue>>();
+        Ok(Value::List(bytes.into()).into())
+    });
+    registry.define_io("end_of_input", 1, |_| {
+        let ended = stdio("end_of_input", io::stdin().lock().fill_buf().map(|b| b.is_empty()))?;
+        Ok(Value::Bool(ended).into())
+    });
+    registry.define("cmp", 2, |args| Ok(Value::Bool(args[0] == args[1]).into()));
+    registry.define("add", 2, |args| arithmetic(int(&args[0]).checked_add(int(&args[1]))));
+    registry.define("sub", 2, |args| arithmetic(int(&args[0]).checked_sub(int(&args[1]))));
+    registry.define("mul", 2, |args| arithmetic(int(&args[0]).checked_mul(int(&args[1]))));
+    registry.define("div", 2, |args| match (int(&args[0]), int(&args[1])) {
+        (_, 0) => raise("DivisionByZero"),
+        (x, y) => arithmetic(x.checked_div(y)),
+    });
+    // Unlike the quotient, the remainder of `i64::MIN` by -1 is defined.
+    registry.define("modulus", 2, |args| match (int(&args[0]), int(&args[1])) {
+        (_, 0) => raise("DivisionByZero"),
+        (x, y) => Ok(Value::Int(x.wrapping_rem(y)).into()),
+    });
+    registry.define("cons", 2, |args| {
+        let list = list(&args[1]);
+        Ok(Value::List(List::Cons(args[0].clone(), Box::new(list))).into())
+    });
+    registry.define("head", 1, |args| match list(&args[0]) {
+        List::Nil => fault("head: empty list"),
+        List::Cons(h, _) => Ok(h),
+    });
+    registry.define("tail", 1, |args| match list(&args[0]) {
+        List::Nil => fault("tail: empty list"),
+        List::Cons(_, t) => Ok(Value::List(*t).into()),
+    });
+    registry.define("raise", 1, |args| {
+        Err(Unwind::Raise {
+            exception: args[0].clone(),
+        })
+    });
+    registry.define("message", 1, |args| Ok(chars(&message(&args[0])).into()));
+    registry.define_io("read_file", 1, |args| {
+        let path = string(&args[0]);
+        Ok(result(fs::read_to_string(path).map(|s| chars(&s))))
+    });
+    registry.define_io("write_file", 2, |args| {
+        let (path, contents) = (string(&args[0]), string(&args[1]));
+        Ok(result(fs::write(path, contents).map(|_| Value::Void)))
+    });
+    registry.define_io("append_file", 2, |args| {
+        let (path, contents) = (string(&args[0]), string(&args[1]));
+        let appended = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(contents.as_bytes()));
+        Ok(result(appended.map(|_| Value::Void)))
+    });
+    registry.define_io("file_exists", 1, |args| {
+        let path = string(&args[0]);
+        Ok(Value::Bool(Path::new(&path).exists()).into())
+    });
+    registry.define_io("list_dir", 1, |args| {
+        let path = string(&args[0]);
+        let entries = fs::read_dir(path).and_then(|entries| {
+            let mut names = entries
+                .map(|entry| Ok(entry?.file_name().to_string_lossy().into_owned()))
+                .collect::<io::Result<Vec<_>>>()?;
+            // The order of the entries is up to the platform otherwise.
+            names.sort();
+            let names = names.iter().map(|name| chars(name).into()).collect::<Vec<_>>();
+            Ok(Value::List(names.into()))
+        });
+        Ok(result(entries))
+    });
+    registry.define_io("remove_file", 1, |args| {
+        let path = string(&args[0]);
+        Ok(result(fs::remove_file(path).map(|_| Value::Void)))
+    });
+    registry.define_io("create_dir", 1, |args| {
+        let path = string(&args[0]);
+        Ok(result(fs::create_dir_all(path).map(|_| Value::Void)))
+    });
+    registry.define("args", 1, |_| {
+        let args = ARGS.with(|args| {
+            args.borrow()
+                .iter()
+                .map(|arg| chars(arg).into())
+                .collect::<Vec<WoValue>>()
+        });
+        Ok(Value::List(args.into()).into())
+    });
+    registry.define_io("get_env", 1, |args| {
+        let name = string(&args[0]);
+        Ok(option(env::var(name).ok().map(|value| chars(&value))))
+    });
+    // `env::set_var` panics on what the platform can't represent.
+    registry.define_io("set_env", 2, |args| {
+        let (name, value) = (string(&args[0]), string(&args[1]));
+        if name.is_empty() || name.contains(['=', '\0']) {
+            return fault(&format!("set_env: invalid variable name `{}`", name.escape_debug()));
+        }
+        if value.contains('\0') {
+            return fault("set_env: the value contains a NUL character");
+        }
+        env::set_var(name, value);
+        Ok(Value::Void.into())
+    });
+    registry.define("exit", 1, |args| {
+        Err(Unwind::Exit {
+            code: exit_status(int(&args[0]))?,
+        })
+    });
+    registry.define("random_int", 2, |args| match (int(&args[0]), int(&args[1])) {
+        (lo, hi) if lo > hi => fault("random_int: empty range"),
+        (lo, hi) => Ok(Value::Int(random_int(lo, hi)).into()),
+    });
+    registry.define("shuffle", 1, |args| {
+        let mut elems = Vec::from(list(&args[0]));
+        // See: https://en.wikipedia.org/wiki/Fisher%E2%80%93Yates_shuffle
+        for i in (1..elems.len()).rev() {
+            elems.swap(i, random_int(0, i as i64) as usize);
+        }
+        Ok(Value::List(elems.into()).into())
+    });
+    registry.define("now_millis", 1, |_| {
+        Ok(Value::Int(now().as_millis() as i64).into())
+    });
+    registry.define("monotonic_millis", 1, |_| {
+        let elapsed = START.with(|start| start.elapsed());
+        Ok(Value::Int(elapsed.as_millis() as i64).into())
+    });
+    registry.define("sleep", 1, |args| {
+        sandbox::sleep(Duration::from_millis(int(&args[0]).max(0) as u64))?;
+        Ok(Value::Void.into())
+    });
+    registry.define("array_get", 2, |args| {
+        let array = array_of(&args[1]);
+        match index(&args[0], array.len()) {
+            Some(i) => Ok(array[i].clone()),
+            None => fault("array_get: index out of bounds"),
+        }
+    });
+    registry.define("array_set", 3, |args| {
+        let mut array = array_of(&args[2]).to_vec();
+        match index(&args[0], array.len()) {
+            Some(i) => array[i] = args[1].clone(),
+            None => return fault("array_set: index out of bounds"),
+        }
+        Ok(Value::Array(array.into()).into())
+    });
+    registry.define("array_push", 2, |args| {
+        let mut array = array_of(&args[1]).to_vec();
+        array.push(args[0].clone());
+        Ok(Value::Array(array.into()).into())
+    });
+    registry.define("array_slice", 3, |args| {
+        let array = array_of(&args[2]);
+        let start = index(&args[0], array.len() + 1);
+        let end = index(&args[1], array.len() + 1);
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => {
+                Ok(Value::Array(array[start..end].into()).into())
+            }
+            _ => fault("array_slice: index out of bounds"),
+        }
+    });
+    registry.define("array_len", 1, |args| {
+        Ok(Value::Int(array_of(&args[0]).len() as i64).into())
+    });
+    registry.define("array_of_list", 1, |args| {
+        Ok(Value::Array(Vec::from(list(&args[0])).into()).into())
+    });
+    registry.define("array_to_list", 1, |args| {
+        Ok(Value::List(array_of(&args[0]).to_vec().into()).into())
+    });
+    registry.define("empty_map", 0, |_| Ok(Value::Map(Tree::default()).into()));
+    registry.define("map_insert", 3, |args| {
+        key("map_insert", &args[0])?;
+        let map = tree_of(&args[2]).insert(args[0].clone(), args[1].clone());
+        Ok(Value::Map(map).into())
+    });
+    registry.define("map_remove", 2, |args| {
+        key("map_remove", &args[0])?;
+        let map = tree_of(&args[1]).remove(&args[0].borrow());
+        Ok(Value::Map(map).into())
+    });
+    registry.define("map_get", 2, |args| {
+        key("map_get", &args[0])?;
+        let value = tree_of(&args[1]).get(&args[0].borrow()).cloned();
+        Ok(option(value))
+    });
+    registry.define("map_contains", 2, |args| {
+        key("map_contains", &args[0])?;
+        let found = tree_of(&args[1]).get(&args[0].borrow()).is_some();
+        Ok(Value::Bool(found).into())
+    });
+    registry.define("map_keys", 1, |args| {
+        Ok(Value::List(tree_of(&args[0]).keys()).into())
+    });
+    registry.define("map_values", 1, |args| {
+        Ok(Value::List(tree_of(&args[0]).values()).into())
+    });
+    registry.define("map_size", 1, |args| {
+        Ok(Value::Int(tree_of(&args[0]).len() as i64).into())
+    });
+    registry.define("map_union", 2, |args| {
+        Ok(Value::Map(tree_of(&args[0]).union(&tree_of(&args[1]))).into())
+    });
+    registry.define("empty_set", 0, |_| Ok(Value::Set(Tree::default()).into()));
+    registry.define("set_insert", 2, |args| {
+        key("set_insert", &args[0])?;
+        let set = tree_of(&args[1]).insert(args[0].clone(), Value::Void.into());
+        Ok(Value::Set(set).into())
+    });
+    registry.define("set_remove", 2, |args| {
+        key("set_remove", &args[0])?;
+        let set = tree_of(&args[1]).remove(&args[0].borrow());
+        Ok(Value::Set(set).into())
+    });
+    registry.define("set_contains", 2, |args| {
+        key("set_contains", &args[0])?;
+        let found = tree_of(&args[1]).get(&args[0].borrow()).is_some();
+        Ok(Value::Bool(found).into())
+    });
+    registry.define("set_elements", 1, |args| {
+        Ok(Value::List(tree_of(&args[0]).keys()).into())
+    });
+    registry.define("set_size", 1, |args| {
+        Ok(Value::Int(tree_of(&args[0]).len() as i64).into())
+    });
+    registry.define("set_union", 2, |args| {
+        Ok(Value::Set(tree_of(&args[0]).union(&tree_of(&args[1]))).into())
+    });
+}
+
+/// The string a `List Char` value stands for.
+fn string(value: &WoValue) -> String {
+    match &*value.borrow() {
+        Value::List(list) => Vec::from(list.clone())
+            .iter()
+            .map(|c| c.borrow().to_string())
+            .collect(),
+        _ => unreachable!(),
+    }
+}
+
+/// The `List Char` value of `s`.
+fn chars(s: &str) -> Value {
+    let list = s.chars().map(|c| Value::Char(c).into()).collect::<Vec<WoValue>>();
+    Value::List(list.into())
+}
+
+/// The time elapsed since the Unix epoch.
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("chimera: the clock is set before 1970. You are on your own.")
+}
+
+/// The next number of the random sequence, generated with SplitMix64.
+/// See: https://prng.di.unimi.it/splitmix64.c
+fn random() -> u64 {
+    let seed = SEED.with(|s| {
+        let seed = s.get().wrapping_add(0x9e3779b97f4a7c15);
+        s.set(seed);
+        seed
+    });
+    let mut z = seed;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// A random integer between `lo` and `hi`, both included, which must not
+/// be in the wrong order. The range is scaled rather than taken modulo,
+/// which would favor the low numbers.
+fn random_int(lo: i64, hi: i64) -> i64 {
+    let span = hi.wrapping_sub(lo) as u64 as u128 + 1;
+    let offset = (random() as u128 * span) >> 64;
+    lo.wrapping_add(offset as i64)
+}
+
+/// Reads a single UTF-8 encoded character from `input`, if there is one.
+/// Bytes that don't encode a character are an error, and are skipped.
+fn read_char(input: &mut impl BufRead) -> io::Result<Option<char>> {
+    let mut bytes = Vec::with_capacity(4);
+    loop {
+        match input.fill_buf()?.first() {
+            Some(byte) => bytes.push(*byte),
+            None if bytes.is_empty() => return Ok(None),
+            None => {
+                let message = "incomplete UTF-8 character";
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        }
+        match std::str::from_utf8(&bytes) {
+            Ok(decoded) => {
+                input.consume(1);
+                return Ok(decoded.chars().next());
+            }
+            // The character is longer than what was read so far.
+            Err(error) if error.error_len().is_none() => input.consume(1),
+            Err(error) => {
+                // A byte that cuts a character short may start the next one.
+                if bytes.len() == 1 {
+                    input.consume(1);
                 }
-                .into()
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
+                return Err(io::Error::new(io::ErrorKind::InvalidData, error));
+            }
+        }
+    }
+}
+
+/// An `Option a` value, as declared in the prelude.
+fn option(value: Option<impl Into<WoValue>>) -> WoValue {
+    match value {
+        Some(value) => Value::Data {
+            constructor: "Some".to_string(),
+            fields: vec![value.into()],
         },
-        "head" => Value::Lambda {
-            param: "list".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                if let Value::List(list) = &*Env::get_name(env, "list").borrow() {
-                    match list {
-                        List::Nil => panic!("chimera: head: empty list."),
-                        List::Cons(h, _) => h.clone(),
-                    }
-                } else {
-                    panic!("chimera: can only get the head of a list.");
-                }
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
+        None => Value::Data {
+            constructor: "None".to_string(),
+            fields: Vec::new(),
         },
-        "tail" => Value::Lambda {
-            param: "list".to_string(),
-            body: Rc::new(CompiledCode::new(move |env| {
-                if let Value::List(list) = &*Env::get_name(env, "list").borrow() {
-                    match list {
-                        List::Nil => panic!("chimera: tail: empty list."),
-                        List::Cons(_, t) => Value::List(*t.clone()).into(),
-                    }
-                } else {
-                    panic!("chimera: can only get the tail of a list.");
-                }
-            })),
-            closure: Rc::new(RefCell::new(Env::default())),
+    }
+    .into()
+}
+
+/// A `Result (List Char) a` value, as declared in the prelude, whose error
+/// is the description of what went wrong.
+fn result(outcome: io::Result<Value>) -> WoValue {
+    let (constructor, field) = match outcome {
+        Ok(value) => ("Ok", value),
+        Err(error) => ("Err", chars(&error.to_string())),
+    };
+    Value::Data {
+        constructor: constructor.to_string(),
+        fields: vec![field.into()],
+    }
+    .into()
+}
+
+/// The `Int` value of `value`.
+fn int(value: &WoValue) -> i64 {
+    match *value.borrow() {
+        Value::Int(i) => i,
+        _ => unreachable!(),
+    }
+}
+
+/// The `List` value of `value`.
+fn list(value: &WoValue) -> List {
+    match &*value.borrow() {
+        Value::List(list) => list.clone(),
+        _ => unreachable!(),
+    }
+}
+
+/// The elements of an `Array` value.
+fn array_of(value: &WoValue) -> Rc<[WoValue]> {
+    match &*value.borrow() {
+        Value::Array(elems) => elems.clone(),
+        _ => unreachable!(),
+    }
+}
+
+/// The `Int` value `index`, if it is below `len`.
+fn index(index: &WoValue, len: usize) -> Option<usize> {
+    match *index.borrow() {
+        Value::Int(i) => usize::try_from(i).ok().filter(|i| *i < len),
+        _ => unreachable!(),
+    }
+}
+
+/// The tree of a `Map` or `Set` value.
+fn tree_of(value: &WoValue) -> Tree {
+    match &*value.borrow() {
+        Value::Map(tree) | Value::Set(tree) => tree.clone(),
+        _ => unreachable!(),
+    }
+}
+
+/// Checks that `value` can be the key of a map or the element of a set,
+/// which must be ordered.
+fn key(intrinsic: &str, value: &WoValue) -> Result<(), Unwind> {
+    if tree::comparable(&value.borrow()) {
+        Ok(())
+    } else {
+        fault(&format!("{}: functions cannot be compared", intrinsic)).map(|_| ())
+    }
+}
+
+/// The result of reading standard input, or writing standard output,
+/// for `intrinsic`, raising an exception if it failed.
+fn stdio<T>(intrinsic: &str, result: io::Result<T>) -> Result<T, Unwind> {
+    result.map_err(|error| Unwind::Raise {
+        exception: failure(&format!("{}: {}", intrinsic, error)),
+    })
+}
+
+/// The result of an integer operation, which raises `Overflow` without one.
+pub(crate) fn arithmetic(result: Option<i64>) -> Outcome {
+    match result {
+        Some(int) => Ok(Value::Int(int).into()),
+        None => raise("Overflow"),
+    }
+}
+
+/// The status a program exits with, which must fit in the byte of it that
+/// every system keeps.
+pub(crate) fn exit_status(code: i64) -> Result<i32, Unwind> {
+    match u8::try_from(code) {
+        Ok(code) => Ok(code.into()),
+        Err(_) => Err(Unwind::Raise {
+            exception: failure(&format!("the exit status {} is not between 0 and 255", code)),
+        }),
+    }
+}
+
+/// A `Failure` exception, as declared in the prelude, described by `message`.
+pub(crate) fn failure(message: &str) -> WoValue {
+    Value::Data {
+        constructor: "Failure".to_string(),
+        fields: vec![chars(message).into()],
+    }
+    .into()
+}
+
+/// Raises a `Failure` from within the interpreter, for runtime faults
+/// a program may recover from.
+pub(crate) fn fault(message: &str) -> Outcome {
+    Err(Unwind::Raise {
+        exception: failure(message),
+    })
+}
+
+/// Raises the exception built by `constructor`, one of the variants
+/// of `Exception` without fields.
+pub(crate) fn raise(constructor: &str) -> Outcome {
+    let exception = Value::Data {
+        constructor: constructor.to_string(),
+        fields: Vec::new(),
+    };
+    Err(Unwind::Raise {
+        exception: exception.into(),
+    })
+}
+
+/// The message describing `exception`, as by the prelude's `message`.
+pub(crate) fn message(exception: &WoValue) -> String {
+    match &*exception.borrow() {
+        Value::Data { constructor, fields } => match constructor.as_str() {
+            "Failure" => string(&fields[0]),
+            "DivisionByZero" => "division by zero".to_string(),
+            "Overflow" => "integer overflow".to_string(),
+            _ => exception.borrow().to_string(),
         },
-        _ => panic!("chimera: unknown intrinsic attribute {}", name),
+        value => value.to_string(),
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+    use crate::compiler::apply;
+    use crate::registry::intrinsic;
+
+    fn call(name: &str, args: &[&str]) -> WoValue {
+        args.iter().fold(intrinsic(name).unwrap(), |f, arg| {
+            apply(&f, chars(arg).into()).unwrap()
+        })
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let dir = env::temp_dir().join(format!("chimera-fs-{}", process::id()));
+        let dir = dir.to_string_lossy().into_owned();
+        let file = format!("{}/notes.txt", dir);
+        assert_eq!(call("create_dir", &[&dir]).borrow().to_string(), "Ok ()");
+        call("write_file", &[&file, "to do"]);
+        call("append_file", &[&file, ": nothing"]);
+        assert_eq!(call("read_file", &[&file]).borrow().to_string(), "Ok to do: nothing");
+        assert_eq!(call("list_dir", &[&dir]).borrow().to_string(), "Ok notes.txt");
+        call("remove_file", &[&file]);
+        assert_eq!(*call("file_exists", &[&file]).borrow(), Value::Bool(false));
+        match &*call("read_file", &[&file]).borrow() {
+            Value::Data { constructor, .. } => assert_eq!(constructor, "Err"),
+            _ => unreachable!(),
+        }
+        fs::remove_dir(dir).unwrap();
+    }
+
+    #[test]
+    fn environment() {
+        call("set_env", &["CHIMERA_TEST", "1"]);
+        assert_eq!(call("get_env", &["CHIMERA_TEST"]).borrow().to_string(), "Some 1");
+        assert_eq!(call("get_env", &[""]).borrow().to_string(), "None");
+        let set = |name: &str, value: &str| {
+            let set_env = apply(&intrinsic("set_env").unwrap(), chars(name).into()).unwrap();
+            apply(&set_env, chars(value).into())
+        };
+        for (name, value) in [("", "1"), ("A=B", "1"), ("A\0", "1"), ("CHIMERA_TEST", "\0")] {
+            assert!(matches!(set(name, value), Err(Unwind::Raise { .. })));
+        }
+        assert_eq!(call("get_env", &["CHIMERA_TEST"]).borrow().to_string(), "Some 1");
+    }
+
+    #[test]
+    fn read_chars() {
+        let mut input = io::Cursor::new("aé€😀".as_bytes());
+        let mut chars = Vec::new();
+        while let Some(c) = read_char(&mut input).unwrap() {
+            chars.push(c);
+        }
+        assert_eq!(chars, vec!['a', 'é', '€', '😀']);
+        let mut input = io::Cursor::new(b"\xffa\xe2\x82b\xe2\x82");
+        assert!(read_char(&mut input).is_err());
+        assert_eq!(read_char(&mut input).unwrap(), Some('a'));
+        assert!(read_char(&mut input).is_err());
+        assert_eq!(read_char(&mut input).unwrap(), Some('b'));
+        assert!(read_char(&mut input).is_err());
+        assert_eq!(read_char(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn seeded_random() {
+        set_seed(42);
+        let first = (0..8).map(|_| random_int(-3, 3)).collect::<Vec<_>>();
+        set_seed(42);
+        let second = (0..8).map(|_| random_int(-3, 3)).collect::<Vec<_>>();
+        assert_eq!(first, second);
+        assert!(first.iter().all(|n| (-3..=3).contains(n)));
+        assert_eq!(random_int(5, 5), 5);
+        // The whole range of `Int` doesn't overflow.
+        random_int(i64::MIN, i64::MAX);
+    }
+}
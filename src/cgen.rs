@@ -0,0 +1,430 @@
+/// The C backend, used by `chimera build --target c`. A type-checked program
+/// is lowered by `lift`, then every lifted function becomes a C function of
+/// the frame it runs in, which returns an `Outcome` like compiled code does
+/// in the interpreter. The result is a single C file holding the runtime of
+/// `runtime.c` followed by the program, which only needs a C compiler.
+use std::fmt::Write;
+
+use anyhow::Result;
+
+use crate::ast::Item;
+use crate::lift::{self, Binder, Kind, Term};
+
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// The C source of `program`.
+pub fn generate(program: &[Item]) -> Result<String> {
+    let program = lift::lift(program)?;
+    let mut generator = Generator {
+        functions: &program.functions,
+        tables: String::new(),
+        next_table: 0,
+    };
+    let mut bodies = String::new();
+    for (index, function) in program.functions.iter().enumerate() {
+        let mut body = Body::default();
+        match function.kind {
+            Kind::Lambda { slots } => {
+                body.line(&format!("Frame *env = frame(closure, {});", slots));
+                body.line("env->slots[0] = arg;");
+            }
+            Kind::Clause { slots, resume } => {
+                body.line(&format!("Frame *env = frame(closure, {});", slots));
+                body.line("env->slots[0] = param;");
+                body.line(&format!("env->slots[{}] = resume;", resume));
+            }
+            Kind::Scope => {}
+        }
+        let value = generator.term(&mut body, &function.body);
+        body.line(&format!("return ok({});", value));
+        writeln!(bodies, "\n{} {{\n{}}}", signature(index, &function.kind), body.code).unwrap();
+    }
+    let mut items = Body::default();
+    let value = generator.term(&mut items, &program.items);
+    items.line(&format!("return ok({});", value));
+    let mut c = String::from(RUNTIME);
+    for (index, function) in program.functions.iter().enumerate() {
+        writeln!(c, "{};", signature(index, &function.kind)).unwrap();
+    }
+    c.push_str(&generator.tables);
+    c.push_str(&bodies);
+    writeln!(c, "\nstatic Outcome items(Frame *env) {{\n{}}}", items.code).unwrap();
+    let main = program.main.map_or(-1, |slot| slot as i64);
+    writeln!(
+        c,
+        "\nint main(int argc, char **argv) {{\n    return start(argc, argv, items, {}, {});\n}}",
+        program.globals, main
+    )
+    .unwrap();
+    Ok(c)
+}
+
+fn name(index: usize, kind: &Kind) -> String {
+    match kind {
+        Kind::Lambda { .. } => format!("lambda_{}", index),
+        Kind::Clause { .. } => format!("clause_{}", index),
+        Kind::Scope => format!("scope_{}", index),
+    }
+}
+
+fn signature(index: usize, kind: &Kind) -> String {
+    let params = match kind {
+        Kind::Lambda { .. } => "Frame *closure, Value *arg",
+        Kind::Clause { .. } => "Frame *closure, Value *param, Value *resume",
+        Kind::Scope => "Frame *env",
+    };
+    format!("static Outcome {}({})", name(index, kind), params)
+}
+
+struct Generator<'p> {
+    functions: &'p [lift::Function],
+    // The clauses of every handler, declared before the functions.
+    tables: String,
+    next_table: usize,
+}
+
+/// The body of a C function, where every value is held by a variable of
+/// its own so that they are computed in order.
+#[derive(Default)]
+struct Body {
+    code: String,
+    depth: usize,
+    next_var: usize,
+}
+
+impl Body {
+    fn line(&mut self, line: &str) {
+        writeln!(self.code, "{:width$}{}", "", line, width = 4 * (self.depth + 1)).unwrap();
+    }
+
+    fn var(&mut self) -> String {
+        self.next_var += 1;
+        format!("v{}", self.next_var)
+    }
+
+    /// Binds `value` to a new variable, whose name is returned.
+    fn bind(&mut self, value: &str) -> String {
+        let var = self.var();
+        self.line(&format!("Value *{} = {};", var, value));
+        var
+    }
+
+    /// Binds the value of the outcome `outcome` to a new variable, unless
+    /// it is an unwind which is returned.
+    fn try_bind(&mut self, outcome: &str) -> String {
+        let var = self.var();
+        self.line(&format!("TRY({}, {});", var, outcome));
+        var
+    }
+}
+
+impl Generator<'_> {
+    /// Emits the code of `term` into `body`, and returns the C expression
+    /// of its value.
+    fn term(&mut self, body: &mut Body, term: &Term) -> String {
+        match term {
+            Term::Void => "UNIT".to_string(),
+            Term::Int(i) if *i >= 0 => body.bind(&format!("integer(INT64_C({}))", i)),
+            Term::Int(i) => body.bind(&format!("integer((int64_t)UINT64_C({}))", *i as u64)),
+            Term::Bool(b) => body.bind(&format!("boolean({})", *b as u8)),
+            Term::Char(c) => body.bind(&format!("character({:#x})", *c as u32)),
+            Term::Name { name, slots } => {
+                let slot = slots.iter().rev().fold(None, |outer, (depth, index)| {
+                    let slot = format!("env{}->slots[{}]", "->outer".repeat(*depth), index);
+                    Some(match outer {
+                        None => slot,
+                        Some(outer) => format!("either({}, {})", slot, outer),
+                    })
+                });
+                let slot = slot.unwrap_or_else(|| "NULL".to_string());
+                body.try_bind(&format!("need({}, {})", slot, literal(name)))
+            }
+            Term::List(elems) | Term::Array(elems) => {
+                let elems = elems
+                    .iter()
+                    .map(|elem| self.term(body, elem))
+                    .collect::<Vec<_>>();
+                let build = if matches!(term, Term::List(_)) { "list_of" } else { "array_of" };
+                if elems.is_empty() {
+                    body.bind(&format!("{}(0, NULL)", build))
+                } else {
+                    let (len, elems) = (elems.len(), elems.join(", "));
+                    body.bind(&format!("{}({}, (Value *[]){{{}}})", build, len, elems))
+                }
+            }
+            Term::Closure(index) => {
+                let kind = &self.functions[*index].kind;
+                body.bind(&format!("lambda({}, env)", name(*index, kind)))
+            }
+            Term::Apply(function, arg) => {
+                let function = self.term(body, function);
+                let arg = self.term(body, arg);
+                body.try_bind(&format!("apply({}, {})", function, arg))
+            }
+            Term::Negate(term) => {
+                let value = self.term(body, term);
+                body.try_bind(&format!("negate({})", value))
+            }
+            Term::Sequence(terms) => terms
+                .iter()
+                .fold("UNIT".to_string(), |_, term| self.term(body, term)),
+            Term::Define(slot, term) => {
+                let value = self.term(body, term);
+                body.line(&format!("env->slots[{}] = {};", slot, value));
+                "UNIT".to_string()
+            }
+            Term::Intrinsic(name) => body.try_bind(&format!("intrinsic({})", literal(name))),
+            Term::Operation { name, default } => {
+                let default = default.as_deref().map_or("NULL".to_string(), literal);
+                body.try_bind(&format!("operation({}, {})", literal(name), default))
+            }
+            Term::Constructor { name, arity } => {
+                body.bind(&format!("constructor({}, {})", literal(name), arity))
+            }
+            Term::Branch(paths) => {
+                let result = body.bind("UNIT");
+                self.paths(body, &result, paths);
+                result
+            }
+            Term::Try { body: code, handler } => {
+                let var = body.var();
+                let raised = format!("{}_.value", var);
+                body.line(&format!("Outcome {}_ = {}(env);", var, name(*code, &Kind::Scope)));
+                let caught = match matches(&handler.pattern, &raised) {
+                    Some(test) => format!("{}_.status == RAISE && {}", var, test),
+                    None => format!("{}_.status == RAISE", var),
+                };
+                body.line(&format!("if ({}) {{", caught));
+                body.depth += 1;
+                bind(body, handler, &raised);
+                body.line(&format!("{}_ = {}(scope);", var, name(handler.body, &Kind::Scope)));
+                body.depth -= 1;
+                body.line("}");
+                body.line(&format!("if ({}_.status != OK) {{", var));
+                body.line(&format!("    return {}_;", var));
+                body.line("}");
+                body.line(&format!("Value *{} = {}_.value;", var, var));
+                var
+            }
+            Term::Handle { body: code, clauses } => {
+                self.next_table += 1;
+                let table = format!("clauses_{}", self.next_table);
+                let entries = clauses
+                    .iter()
+                    .map(|(operation, index)| {
+                        let clause = name(*index, &self.functions[*index].kind);
+                        format!("    {{ {}, {} }},\n", literal(operation), clause)
+                    })
+                    .collect::<String>();
+                writeln!(self.tables, "static const Clause {}[] = {{\n{}}};", table, entries)
+                    .unwrap();
+                let code = name(*code, &Kind::Scope);
+                let handle = format!("handle({}, {}, env, {})", table, clauses.len(), code);
+                body.try_bind(&handle)
+            }
+            Term::Match { value, arms } => {
+                let value = self.term(body, value);
+                let result = body.bind("NULL");
+                let mut exhaustive = false;
+                for (i, arm) in arms.iter().enumerate() {
+                    let opening = if i == 0 { "if" } else { "} else if" };
+                    match matches(&arm.pattern, &value) {
+                        Some(test) => body.line(&format!("{} ({}) {{", opening, test)),
+                        None if i == 0 => body.line("{"),
+                        None => body.line("} else {"),
+                    }
+                    body.depth += 1;
+                    bind(body, arm, &value);
+                    let scope = name(arm.body, &Kind::Scope);
+                    let arm_value = body.try_bind(&format!("{}(scope)", scope));
+                    body.line(&format!("{} = {};", result, arm_value));
+                    body.depth -= 1;
+                    if let Binder::Name(_) = arm.pattern {
+                        exhaustive = true;
+                        break;
+                    }
+                }
+                if !exhaustive {
+                    body.line("} else {");
+                    body.line(&format!("    return no_arm({});", value));
+                }
+                body.line("}");
+                result
+            }
+            Term::Hole(span) => body.try_bind(&format!("hole({})", literal(&span.to_string()))),
+            Term::Unsupported(message) => body.try_bind(&format!("fault({})", literal(message))),
+        }
+    }
+
+    /// Sets `result` to the value of the first path whose condition holds,
+    /// which are only evaluated as long as the previous ones don't.
+    fn paths(&mut self, body: &mut Body, result: &str, paths: &[(Term, Term)]) {
+        let ((cond, path), rest) = match paths.split_first() {
+            Some(first) => first,
+            None => return,
+        };
+        let always = matches!(cond, Term::Bool(true));
+        if !always {
+            let cond = self.term(body, cond);
+            body.line(&format!("if ({}->as.b) {{", cond));
+            body.depth += 1;
+        }
+        let value = self.term(body, path);
+        body.line(&format!("{} = {};", result, value));
+        if !always {
+            body.depth -= 1;
+            if rest.is_empty() {
+                body.line("}");
+            } else {
+                body.line("} else {");
+                body.depth += 1;
+                self.paths(body, result, rest);
+                body.depth -= 1;
+                body.line("}");
+            }
+        }
+    }
+}
+
+/// The C condition under which `pattern` matches `value`, unless it
+/// matches anything.
+fn matches(pattern: &Binder, value: &str) -> Option<String> {
+    match pattern {
+        Binder::Constructor { name, .. } => Some(format!("is({}, {})", value, literal(name))),
+        Binder::Name(_) => None,
+    }
+}
+
+/// Makes the frame `scope` of `arm`, holding what its pattern binds of `value`.
+fn bind(body: &mut Body, arm: &lift::Arm, value: &str) {
+    body.line(&format!("Frame *scope = frame(env, {});", arm.slots));
+    match &arm.pattern {
+        Binder::Constructor { fields, .. } => {
+            for (field, slot) in fields.iter().enumerate() {
+                let field = format!("{}->as.data.fields[{}]", value, field);
+                body.line(&format!("scope->slots[{}] = {};", slot, field));
+            }
+        }
+        Binder::Name(slot) => body.line(&format!("scope->slots[{}] = {};", slot, value)),
+    }
+}
+
+/// The C string literal of `s`.
+fn literal(s: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in s.bytes() {
+        match byte {
+            // A `?` could start a trigraph.
+            b'"' | b'\\' | b'?' => write!(literal, "\\{}", byte as char).unwrap(),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => write!(literal, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::env;
+    use std::fs;
+    use std::process::{self, Command};
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::fixity::Fixities;
+    use crate::parser::parse;
+    use crate::registry;
+    use crate::value::Value;
+    use crate::{Interpreter, PRELUDE};
+
+    fn items(source: &str) -> Vec<Item> {
+        let mut fixities = Fixities::default();
+        let mut items = parse(PRELUDE, &mut fixities).unwrap();
+        items.extend(parse(source, &mut fixities).unwrap());
+        items
+    }
+
+    /// Runs `program` with the interpreter, returning what it printed along
+    /// with its exit status.
+    fn interpret(program: &str) -> (String, i32) {
+        let printed = Rc::new(RefCell::new(String::new()));
+        let output = printed.clone();
+        registry::replace("print", 1, move |args| {
+            write!(output.borrow_mut(), "{}", args[0].borrow()).unwrap();
+            Ok(Value::Void.into())
+        });
+        registry::replace("flush", 1, |_| Ok(Value::Void.into()));
+        let mut chimera = Interpreter::new().unwrap();
+        chimera.load(program).unwrap();
+        let code = chimera.run_main().unwrap();
+        let printed = printed.borrow().clone();
+        (printed, code)
+    }
+
+    #[test]
+    fn compiled_program() {
+        let program = "let main = |_| do\n\
+                       \x20   tests ()\n\
+                       \x20   println (map (|x| x * x) [1, 2, 3])\n\
+                       \x20   println (try [1 / 0] catch e -> [len (message e)] end)\n\
+                       \x20   let div = |_| try 1 / 0 catch Failure _ -> 0 end\n\
+                       \x20   println (try div () catch DivisionByZero -> 2 end)\n\
+                       \x20   println (try ... catch Failure _ -> 5 end)\n\
+                       \x20   println (foldl (+) 0 (1..100))\n\
+                       \x20   println (set_of ['c', 'a', 'b'])\n\
+                       \x20   3\n\
+                       end\n";
+        let c = generate(&items(program)).unwrap();
+        let dir = env::temp_dir().join(format!("chimera-cgen-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (source, binary) = (dir.join("program.c"), dir.join("program"));
+        fs::write(&source, c).unwrap();
+        let status = Command::new("cc")
+            .arg("-pthread")
+            .arg("-o")
+            .arg(&binary)
+            .arg(&source)
+            .status()
+            .expect("the C compiler `cc` is needed to run the generated code");
+        assert!(status.success());
+        let output = Command::new(&binary).output().unwrap();
+        fs::remove_dir_all(dir).unwrap();
+        // The compiled program does what the interpreted one does.
+        let (printed, code) = interpret(program);
+        assert_eq!(printed, "149\n16\n2\n5\n4950\n{a, b, c}\n");
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), printed);
+        assert_eq!(output.status.code(), Some(code));
+    }
+
+    #[test]
+    fn assignments() {
+        let error = generate(&items("let f = |x| x.y = 1\n")).unwrap_err();
+        assert_eq!(error.to_string(), "fields and assignments can't be compiled to C");
+    }
+
+    #[test]
+    fn resumed_early() {
+        let source = "effect Ask\n\
+                      \x20   ask : Void -> Int\n\
+                      end\n\
+                      let main = |_| handle ask () with\n\
+                      \x20   ask _ k -> k 1 + 1\n\
+                      end\n";
+        let error = generate(&items(source)).unwrap_err();
+        assert_eq!(error.to_string(), "the handler of `ask` must resume last to be compiled to C");
+        // Functions that are never called don't need to be compiled.
+        let source = "effect Ask\n\
+                      \x20   ask : Void -> Int\n\
+                      end\n\
+                      let unused = |_| handle ask () with\n\
+                      \x20   ask _ k -> k 1 + 1\n\
+                      end\n\
+                      let main = |_| 0\n";
+        let c = generate(&items(source)).unwrap();
+        assert!(c.contains("fault(\"the handler of `ask` must resume last to be compiled to C\")"));
+        let called = source.replace("|_| 0", "|_| unused ()");
+        assert!(generate(&items(&called)).is_err());
+    }
+}
@@ -3,6 +3,8 @@ use std::rc::Rc;
 
 use fnv::FnvHashMap;
 
+use crate::attribute;
+use crate::error::Limit;
 use crate::value::WoValue;
 
 /// A data-type is `Code` if it can produce a function from `(Env, Cont)` to `Value`,
@@ -26,21 +28,120 @@ pub trait Code {
 // FIXME: This is a bad model of `Code` as Instr's are not supposed
 // to have values nor types, it would make more sense to return am
 // optional WoValue, but would it cause more overhead to check it?
-pub struct CompiledCode(Box<dyn Fn(WoEnv) -> WoValue>);
+pub struct CompiledCode(Box<dyn Fn(WoEnv) -> Outcome>);
 
 impl CompiledCode {
-    pub fn new(closure: impl 'static + Fn(WoEnv) -> WoValue) -> Self {
+    pub fn new(closure: impl 'static + Fn(WoEnv) -> Outcome) -> Self {
         Self(Box::new(closure))
     }
 
-    pub fn execute(&self, env: WoEnv) -> WoValue {
+    pub fn execute(&self, env: WoEnv) -> Outcome {
         self.0(env)
     }
 }
 
 impl Default for CompiledCode {
     fn default() -> Self {
-        CompiledCode::new(|_env| WoValue::default())
+        CompiledCode::new(|_env| Ok(WoValue::default()))
+    }
+}
+
+/// The result of executing some code: either a value, or a non-local exit
+/// which unwinds the compiled closures it goes through, by way of `?`,
+/// until it reaches the one that expects it.
+pub type Outcome = Result<WoValue, Unwind>;
+
+/// The rest of the computation, from an operation that was performed up to
+/// the `handle` expression it unwinds to, which the handler's clause may
+/// resume with the result of the operation, once. See `effect`.
+pub struct Continuation(Box<dyn FnOnce(WoValue) -> Outcome>);
+
+impl Continuation {
+    pub fn new(closure: impl 'static + FnOnce(WoValue) -> Outcome) -> Self {
+        Self(Box::new(closure))
+    }
+
+    pub fn resume(self, value: WoValue) -> Outcome {
+        self.0(value)
+    }
+}
+
+#[derive(Debug)]
+pub enum Unwind {
+    /// Unwinds from the operation performed under the handler numbered
+    /// `handler` to that handler, the compiled closures it goes through
+    /// adding what they have left to do to the continuation `resume`.
+    Perform {
+        handler: usize,
+        operation: String,
+        value: WoValue,
+        resume: Continuation,
+    },
+    /// Resumes the operation performed under the handler numbered `handler`,
+    /// which then produces `value`, for a clause that runs where the operation
+    /// is performed. See `effect::perform`.
+    Resume { handler: usize, value: WoValue },
+    /// Returns `value` from the handler numbered `handler`, once one of its
+    /// clauses is done without resuming. See `effect::handle`.
+    Abort { handler: usize, value: WoValue },
+    /// Raises `exception` up to the innermost `try` expression.
+    Raise { exception: WoValue },
+    /// Ends the program with the exit status `code`.
+    Exit { code: i32 },
+    /// Stops the program, which went over `limit`. Unlike an exception,
+    /// it can't be caught. See `sandbox`.
+    Halt { limit: Limit },
+}
+
+impl Unwind {
+    /// Replaces the continuation of a performed operation by `f` of it.
+    pub fn around(self, f: impl FnOnce(Continuation) -> Continuation) -> Unwind {
+        match self {
+            Unwind::Perform {
+                handler,
+                operation,
+                value,
+                resume,
+            } => Unwind::Perform {
+                handler,
+                operation,
+                value,
+                resume: f(resume),
+            },
+            unwind => unwind,
+        }
+    }
+
+    /// Adds `rest` to the continuation of a performed operation, which is
+    /// what the code it unwinds through does with the value it was expecting.
+    pub fn then(self, rest: impl 'static + FnOnce(WoValue) -> Outcome) -> Unwind {
+        self.around(|resume| Continuation::new(move |value| and_then(resume.resume(value), rest)))
+    }
+}
+
+/// Runs `rest` on the value of `outcome`, or adds it to the continuation of
+/// the operation being performed, see `Unwind::then`.
+pub fn and_then(outcome: Outcome, rest: impl 'static + FnOnce(WoValue) -> Outcome) -> Outcome {
+    match outcome {
+        Ok(value) => rest(value),
+        Err(unwind) => Err(unwind.then(rest)),
+    }
+}
+
+impl std::fmt::Display for Unwind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Unwind::Perform { operation, .. } => {
+                write!(f, "performed `{}` outside of its handler", operation)
+            }
+            Unwind::Resume { .. } => write!(f, "resumed an operation outside of its handler"),
+            Unwind::Abort { .. } => write!(f, "returned from a handler that is not running"),
+            Unwind::Raise { exception } => {
+                write!(f, "uncaught exception: {}", attribute::message(exception))
+            }
+            Unwind::Exit { code } => write!(f, "exited with status {}", code),
+            Unwind::Halt { limit } => write!(f, "{}", limit),
+        }
     }
 }
 
@@ -51,6 +152,12 @@ impl std::fmt::Debug for CompiledCode {
     }
 }
 
+impl std::fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[Continuation]")
+    }
+}
+
 pub type WoEnv = Rc<RefCell<Env>>;
 
 #[derive(Debug, PartialEq, Clone, Default)]
@@ -61,41 +168,48 @@ pub struct Env {
 }
 
 impl Env {
-    /// Get the first `Env` containing `name`.
-    pub fn get_var_env(env: WoEnv, name: &str) -> WoEnv {
+    /// A new Env nested in `outer`.
+    pub fn new(outer: Option<WoEnv>) -> WoEnv {
+        Rc::new(RefCell::new(Env {
+            outer,
+            ..Env::default()
+        }))
+    }
+
+    /// Get the first `Env` containing `name`, it raises when there is none.
+    pub fn get_var_env(env: WoEnv, name: &str) -> Result<WoEnv, Unwind> {
         if !env.borrow().vars.contains_key(name) {
             match env.borrow().outer.clone() {
-                None => {
-                    panic!("chimera: `{}` is not a defined mutable name.", name)
-                }
+                None => Err(Self::undefined(name, "mutable name")),
                 Some(oenv) => Self::get_var_env(oenv, name),
             }
         } else {
-            env
+            Ok(env)
         }
     }
 
     /// Get the value corresponding to `name` in the chain of `Env`s.
-    pub fn get_name(env: WoEnv, name: &str) -> WoValue {
+    pub fn get_name(env: WoEnv, name: &str) -> Outcome {
         if !env.borrow().names.contains_key(name) {
             if !env.borrow().vars.contains_key(name) {
                 match env.borrow().outer.clone() {
-                    // TODO: This panic is no longer necessary as the typechecker
-                    // is supposed to catch them beforehand. The same goes for all
-                    // the `if let ... {} else {}` blocks in compiler.rs
-                    // It would make more sense to do an `unreachable_unchecked()`
-                    // in this case. That function however is unsafe and complete UB.
-                    // I would argue it's acceptable since Algorithm J is mathematically
-                    // proven to always correcly infer the most general type for an expr,
-                    // of course, I can't same say the same for my implementation of it :^).
-                    None => panic!("chimera: `{}` is not a defined (mutable) name.", name),
+                    // The typechecker rejects names that are undefined, or used
+                    // before their definition has run, so this only guards
+                    // against a bug in it.
+                    None => Err(Self::undefined(name, "(mutable) name")),
                     Some(oenv) => Self::get_name(oenv, name),
                 }
             } else {
-                env.borrow().vars.get(name).unwrap().clone()
+                Ok(env.borrow().vars.get(name).unwrap().clone())
             }
         } else {
-            env.borrow().names.get(name).unwrap().clone()
+            Ok(env.borrow().names.get(name).unwrap().clone())
+        }
+    }
+
+    fn undefined(name: &str, what: &str) -> Unwind {
+        Unwind::Raise {
+            exception: attribute::failure(&format!("`{}` is not a defined {}", name, what)),
         }
     }
 }
@@ -1,81 +1,64 @@
-use std::cell::RefCell;
 // use std::io::{self, Read};
 use std::rc::Rc;
 
-use crate::ast::{Expr, Item, ItemKind, Stmt};
-use crate::attribute::intrinsic;
-use crate::code::{Code, CompiledCode, Env};
-use crate::value::Value;
+use crate::ast::{Expr, Item, ItemKind, Pattern, Stmt};
+use crate::attribute;
+use crate::registry::intrinsic;
+use crate::code::{and_then, Code, CompiledCode, Continuation, Env, Outcome, Unwind, WoEnv};
+use crate::effect::{self, CompiledClause};
+use crate::gc;
+use crate::sandbox;
+use crate::value::{Value, WoValue};
 
 impl Code for Expr {
     fn compile(self) -> CompiledCode {
         match self {
-            Expr::Ellipsis => CompiledCode::new(move |_env| Value::Void.into()),
-            Expr::Void => CompiledCode::new(move |_env| Value::Void.into()),
-            Expr::Int(int) => CompiledCode::new(move |_env| Value::Int(int).into()),
-            Expr::Bool(boolean) => CompiledCode::new(move |_env| Value::Bool(boolean).into()),
-            Expr::Char(ch) => CompiledCode::new(move |_env| Value::Char(ch).into()),
+            Expr::Ellipsis(span) => CompiledCode::new(move |_env| {
+                attribute::fault(&format!("reached unimplemented hole at {}", span))
+            }),
+            Expr::Void => CompiledCode::new(move |_env| Ok(Value::Void.into())),
+            Expr::Int(int) => CompiledCode::new(move |_env| Ok(Value::Int(int).into())),
+            Expr::Bool(boolean) => CompiledCode::new(move |_env| Ok(Value::Bool(boolean).into())),
+            Expr::Char(ch) => CompiledCode::new(move |_env| Ok(Value::Char(ch).into())),
             Expr::Name(name) => CompiledCode::new(move |env| Env::get_name(env, &name)),
-            Expr::List(list) => {
-                let compiled_list = list.into_iter().map(Code::compile).collect::<Vec<_>>();
+            Expr::Located { expr, .. } => expr.compile(),
+            Expr::Array(array) => {
+                let compiled_array = array.into_iter().map(Code::compile).collect::<Rc<[_]>>();
                 CompiledCode::new(move |env| {
-                    Rc::new(RefCell::new(Value::List({
-                        compiled_list
-                            .iter()
-                            .map(|i| i.execute(env.clone()))
-                            .collect::<Vec<_>>()
-                            .into()
-                    })))
+                    elements(compiled_array.clone(), env, Vec::new(), |elems| {
+                        Ok(Value::Array(elems.into()).into())
+                    })
                 })
             }
-            Expr::Block { mut body } => {
-                // NOTE: the parser should've already ensured
-                // the body is not empty, so unwrap away!
-                let last = body.pop().unwrap();
-                let compiled_block = body.into_iter().map(Code::compile).collect::<Vec<_>>();
-                let compiled_expr = last.compile();
+            Expr::List(list) => {
+                let compiled_list = list.into_iter().map(Code::compile).collect::<Rc<[_]>>();
                 CompiledCode::new(move |env| {
-                    for instr in compiled_block.iter() {
-                        instr.execute(env.clone());
-                    }
-                    compiled_expr.execute(env)
+                    elements(compiled_list.clone(), env, Vec::new(), |elems| {
+                        Ok(Value::List(elems.into()).into())
+                    })
                 })
             }
+            // NOTE: the parser should've already ensured
+            // the body is not empty.
+            Expr::Block { body } => {
+                let compiled_block = body.into_iter().map(Code::compile).collect::<Rc<[_]>>();
+                CompiledCode::new(move |env| block(compiled_block.clone(), 0, env))
+            }
             Expr::Branch { paths } => {
                 let compiled_branch = paths
                     .into_iter()
-                    .map(|(c, b)| {
-                        (
-                            c.compile(),
-                            b.into_iter().map(Code::compile).collect::<Vec<_>>(),
-                        )
+                    .map(|(cond, body)| {
+                        let body = body.into_iter().map(Code::compile).collect::<Rc<[_]>>();
+                        (cond.compile(), body)
                     })
-                    .collect::<Vec<_>>();
-                CompiledCode::new(move |env| {
-                    let mut result = Value::Void.into();
-                    for p in &compiled_branch {
-                        // FIXME: it's not very clear that p.0 is the condition and
-                        // p.1 the corresponding code.
-                        if let Value::Bool(b) = *p.0.execute(env.clone()).borrow() {
-                            if b {
-                                let (last, init) = p.1.split_last().unwrap();
-                                for i in init {
-                                    i.execute(env.clone());
-                                }
-                                result = last.execute(env);
-                                break;
-                            }
-                        } else {
-                            unreachable!()
-                        }
-                    }
-                    result
-                })
+                    .collect::<Rc<[_]>>();
+                CompiledCode::new(move |env| branch(compiled_branch.clone(), 0, env))
             }
-            Expr::Lambda { param, expr } => {
+            Expr::Lambda { param, expr, .. } => {
                 let compiled_body = Rc::new(expr.compile());
                 CompiledCode::new(move |env| {
-                    Value::Lambda {
+                    gc::track(&env);
+                    Ok(Value::Lambda {
                         param: param.clone(),
                         // The function's body is compiled the first time we come
                         // across its expression, then its expression itself
@@ -91,46 +74,255 @@ impl Code for Expr {
                         // capturing the current Env for future reference.
                         closure: env,
                     }
-                    .into()
+                    .into())
                 })
             }
             Expr::Apply { left, right } => {
                 let compiled_func = left.compile();
-                let compiled_input = right.compile();
+                let compiled_input = Rc::new(right.compile());
                 CompiledCode::new(move |env| {
-                    if let Value::Lambda {
-                        param,
-                        body,
-                        closure,
-                        ..
-                    } = &*compiled_func.execute(env.clone()).borrow()
-                    {
-                        // Evaluating a function-block needs a separate Env
-                        // The current env is only needed for resolving the parameter,
-                        // which is inserted in the function's private Env alongside
-                        // all its local definitions. Any other "external" names are
-                        // resolved with the closure Env saved upon the evaluation
-                        // of the Function expression. This might by the Env of another
-                        // function application or a block expression.
-                        let fenv = Rc::new(RefCell::new(Env::default()));
-                        let input_value = compiled_input.execute(env);
-                        fenv.borrow_mut()
-                            .names
-                            .insert(param.to_string(), input_value);
-                        fenv.borrow_mut().outer = Some(closure.clone());
-                        body.execute(fenv)
-                    } else {
-                        // TODO: switch all unreachable!'s to the unreachable
-                        // intrinsic for more optimization (?)
-                        unreachable!()
-                    }
+                    let compiled_input = compiled_input.clone();
+                    and_then(compiled_func.execute(env.clone()), move |function| {
+                        and_then(compiled_input.execute(env), move |input_value| {
+                            apply(&function, input_value)
+                        })
+                    })
+                })
+            }
+            // Type ascriptions only matter to the type-checker.
+            Expr::Ascribe { expr, .. } => expr.compile(),
+            Expr::Negate { expr } => {
+                let compiled_expr = expr.compile();
+                CompiledCode::new(move |env| {
+                    and_then(compiled_expr.execute(env), |value| {
+                        if let Value::Int(i) = *value.borrow() {
+                            attribute::arithmetic(i.checked_neg())
+                        } else {
+                            unreachable!()
+                        }
+                    })
+                })
+            }
+            Expr::Handle { expr, clauses } => {
+                let compiled_expr = Rc::new(expr.compile());
+                let compiled_clauses = clauses
+                    .into_iter()
+                    .map(|clause| CompiledClause {
+                        last: effect::resumes_last(&clause),
+                        operation: clause.operation,
+                        param: clause.param,
+                        resume: clause.resume,
+                        body: clause.body.compile(),
+                    })
+                    .collect::<Vec<_>>();
+                let compiled_clauses = Rc::new(compiled_clauses);
+                CompiledCode::new(move |env| effect::handle(&compiled_clauses, env, &compiled_expr))
+            }
+            Expr::Try { expr, pattern, handler } => {
+                let compiled_expr = expr.compile();
+                let compiled_handler = Rc::new((pattern, handler.compile()));
+                CompiledCode::new(move |env| {
+                    let outcome = compiled_expr.execute(env.clone());
+                    catch(outcome, compiled_handler.clone(), env)
+                })
+            }
+            Expr::Match { expr, arms } => {
+                let compiled_expr = expr.compile();
+                let compiled_arms = arms
+                    .into_iter()
+                    .map(|arm| (arm.pattern, arm.body.compile()))
+                    .collect::<Rc<[_]>>();
+                CompiledCode::new(move |env| {
+                    let compiled_arms = compiled_arms.clone();
+                    and_then(compiled_expr.execute(env.clone()), move |value| {
+                        select(&compiled_arms, value, env)
+                    })
                 })
             }
-            _ => unimplemented!("expression {self:?} is not evaluated!"),
+            // The typechecker rejects them.
+            Expr::Field { .. } | Expr::Assign { .. } => CompiledCode::new(move |_env| {
+                attribute::fault("fields and assignments are not supported")
+            }),
+            Expr::Infix { .. } => unreachable!("infix expressions are resolved by the fixity pass"),
+        }
+    }
+}
+
+/// Evaluates the `elems` of a list or an array that follow the ones of which
+/// `values` are the values, then `finish`es with all of their values.
+/// NOTE: the functions that follow are the evaluation of the expressions
+/// that have subexpressions, from any subexpression on, so that operations
+/// performed in those can be resumed from there, see `effect`.
+fn elements(
+    elems: Rc<[CompiledCode]>,
+    env: WoEnv,
+    mut values: Vec<WoValue>,
+    finish: fn(Vec<WoValue>) -> Outcome,
+) -> Outcome {
+    while let Some(elem) = elems.get(values.len()) {
+        match elem.execute(env.clone()) {
+            Ok(value) => values.push(value),
+            Err(unwind) => {
+                return Err(unwind.then(move |value| {
+                    values.push(value);
+                    elements(elems, env, values, finish)
+                }))
+            }
+        }
+    }
+    finish(values)
+}
+
+/// Runs the statements of `body` from the `from`th one, the last one producing
+/// the result.
+fn block(body: Rc<[CompiledCode]>, from: usize, env: WoEnv) -> Outcome {
+    let last = body.len() - 1;
+    for i in from..last {
+        if let Err(unwind) = body[i].execute(env.clone()) {
+            return Err(unwind.then(move |_| block(body, i + 1, env)));
+        }
+    }
+    body[last].execute(env)
+}
+
+/// Takes the first path of a branch, from the `from`th one, whose condition holds.
+fn branch(paths: Rc<[(CompiledCode, Rc<[CompiledCode]>)]>, from: usize, env: WoEnv) -> Outcome {
+    let cond = match paths.get(from) {
+        Some((cond, _)) => cond.execute(env.clone()),
+        None => return Ok(Value::Void.into()),
+    };
+    and_then(cond, move |cond| {
+        let holds = match *cond.borrow() {
+            Value::Bool(b) => b,
+            _ => unreachable!(),
+        };
+        match holds {
+            true => block(paths[from].1.clone(), 0, env),
+            false => branch(paths, from + 1, env),
+        }
+    })
+}
+
+/// Runs the handler of a `try` expression if `outcome` is an exception its
+/// pattern matches, which includes those raised once the operations performed
+/// in the expression are resumed. Other exceptions are raised further.
+fn catch(outcome: Outcome, handler: Rc<(Pattern, CompiledCode)>, env: WoEnv) -> Outcome {
+    match outcome {
+        Err(Unwind::Raise { exception }) => {
+            // Like a lambda's parameter, what the pattern binds
+            // lives in an Env of its own.
+            let henv = Env::new(Some(env));
+            match bind(&handler.0, &exception, &henv) {
+                true => handler.1.execute(henv),
+                false => Err(Unwind::Raise { exception }),
+            }
+        }
+        Err(unwind) => Err(unwind.around(|resume| {
+            Continuation::new(move |value| catch(resume.resume(value), handler, env))
+        })),
+        outcome => outcome,
+    }
+}
+
+/// Runs the first of `arms` whose pattern matches `value`.
+fn select(arms: &[(Pattern, CompiledCode)], value: WoValue, env: WoEnv) -> Outcome {
+    // The names bound by the pattern live in an Env of their own.
+    let aenv = Env::new(Some(env));
+    let found = arms.iter().find(|(pattern, _)| bind(pattern, &value, &aenv));
+    match found {
+        Some((_, body)) => body.execute(aenv),
+        None => attribute::fault(&format!("no arm matches the value {}", value.borrow())),
+    }
+}
+
+/// Whether `pattern` matches `value`, in which case the names it binds
+/// are inserted in `env`.
+fn bind(pattern: &Pattern, value: &WoValue, env: &WoEnv) -> bool {
+    match pattern {
+        Pattern::Constructor { name, fields } => match &*value.borrow() {
+            Value::Data {
+                constructor,
+                fields: values,
+            } if constructor == name => {
+                for (field, value) in fields.iter().zip(values) {
+                    env.borrow_mut().names.insert(field.clone(), value.clone());
+                }
+                true
+            }
+            _ => false,
+        },
+        Pattern::Name(name) => {
+            env.borrow_mut().names.insert(name.clone(), value.clone());
+            true
         }
     }
 }
 
+/// Applies the value of a function to `input`, which is either a lambda, an
+/// effect operation that is performed, the continuation of a handler, or
+/// the constructor of a data type.
+pub fn apply(function: &WoValue, input: WoValue) -> Outcome {
+    let (param, body, closure) = match &*function.borrow() {
+        Value::Lambda {
+            param,
+            body,
+            closure,
+        } => (param.clone(), body.clone(), closure.clone()),
+        Value::Operation { name, default } => {
+            let (name, default) = (name.clone(), default.clone());
+            return effect::perform(&name, input, default.as_ref());
+        }
+        Value::Continuation(resume) => return effect::resume(resume, input),
+        Value::Constructor {
+            name,
+            arity,
+            fields,
+        } => {
+            let mut fields = fields.clone();
+            fields.push(input);
+            let value = if fields.len() == *arity {
+                Value::Data {
+                    constructor: name.clone(),
+                    fields,
+                }
+            } else {
+                Value::Constructor {
+                    name: name.clone(),
+                    arity: *arity,
+                    fields,
+                }
+            };
+            return Ok(value.into());
+        }
+        // TODO: switch all unreachable!'s to the unreachable
+        // intrinsic for more optimization (?)
+        _ => unreachable!(),
+    };
+    // Evaluating a function-block needs a separate Env
+    // The current env is only needed for resolving the parameter,
+    // which is inserted in the function's private Env alongside
+    // all its local definitions. Any other "external" names are
+    // resolved with the closure Env saved upon the evaluation
+    // of the Function expression. This might by the Env of another
+    // function application or a block expression.
+    let fenv = Env::new(Some(closure));
+    fenv.borrow_mut().names.insert(param, input);
+    sandbox::step(|| body.execute(fenv)).map_err(nested)
+}
+
+/// Once resumed, what is left of a function's body is still nested in it.
+fn nested(unwind: Unwind) -> Unwind {
+    unwind.around(|resume| {
+        Continuation::new(move |value| sandbox::nest(|| resume.resume(value)).map_err(nested))
+    })
+}
+
+/// An item with an attribute the interpreter doesn't know raises when run.
+fn unknown(attr: &str) -> CompiledCode {
+    let message = format!("unknown attribute {}", attr);
+    CompiledCode::new(move |_env| attribute::fault(&message))
+}
+
 impl Code for Stmt {
     fn compile(self) -> CompiledCode {
         match self {
@@ -155,24 +347,89 @@ impl Code for Item {
                 let compiled_expr = expr.compile();
                 match self.attr {
                     None => CompiledCode::new(move |env| {
-                        let rhs_value = compiled_expr.execute(env.clone());
-                        env.borrow_mut().names.insert(name.to_string(), rhs_value);
-                        Value::Void.into()
+                        let name = name.clone();
+                        and_then(compiled_expr.execute(env.clone()), move |rhs_value| {
+                            env.borrow_mut().names.insert(name, rhs_value);
+                            Ok(Value::Void.into())
+                        })
                     }),
                     Some(attr) => {
                         if attr.name == "intrinsic" {
                             CompiledCode::new(move |env| {
-                                let rhs_value = intrinsic(&attr.args[0]).into();
+                                let rhs_value = intrinsic(&attr.args[0])?;
                                 env.borrow_mut().names.insert(name.to_string(), rhs_value);
-                                Value::Void.into()
+                                Ok(Value::Void.into())
                             })
                         } else {
-                            panic!("chimera: unknown attribute {}", attr.name)
+                            unknown(&attr.name)
                         }
                     }
                 }
             }
-            _ => unimplemented!("item {self:#?} is not evaluated!"),
+            // Every operation of an effect is bound to a function performing it,
+            // intrinsic operations are implemented by the interpreter by default.
+            ItemKind::Effect { operations, .. } => {
+                let operations = operations
+                    .into_iter()
+                    .map(|op| match op.attr {
+                        None => Ok((op.name, None)),
+                        Some(attr) if attr.name == "intrinsic" => {
+                            Ok((op.name, Some(attr.args[0].clone())))
+                        }
+                        Some(attr) => Err(attr.name),
+                    })
+                    .collect::<Result<Vec<_>, _>>();
+                let operations = match operations {
+                    Ok(operations) => operations,
+                    Err(attr) => return unknown(&attr),
+                };
+                CompiledCode::new(move |env| {
+                    for (name, default) in &operations {
+                        let value = Value::Operation {
+                            name: name.clone(),
+                            default: default.as_deref().map(intrinsic).transpose()?,
+                        };
+                        env.borrow_mut().names.insert(name.clone(), value.into());
+                    }
+                    Ok(Value::Void.into())
+                })
+            }
+            // Every variant of a data type is bound to its constructor.
+            ItemKind::DataType { variants, .. } => {
+                let constructors = variants
+                    .into_iter()
+                    .map(|(name, fields)| (name, fields.len()))
+                    .collect::<Vec<_>>();
+                CompiledCode::new(move |env| {
+                    for (name, arity) in &constructors {
+                        let value = match arity {
+                            0 => Value::Data {
+                                constructor: name.clone(),
+                                fields: Vec::new(),
+                            },
+                            _ => Value::Constructor {
+                                name: name.clone(),
+                                arity: *arity,
+                                fields: Vec::new(),
+                            },
+                        };
+                        env.borrow_mut().names.insert(name.clone(), value.into());
+                    }
+                    Ok(Value::Void.into())
+                })
+            }
+            // Modules only group items for now, the names they bind are global.
+            ItemKind::Module { items, .. } => {
+                let void = CompiledCode::new(|_env| Ok(Value::Void.into()));
+                let compiled_items = items
+                    .into_iter()
+                    .map(Code::compile)
+                    .chain([void])
+                    .collect::<Rc<[_]>>();
+                CompiledCode::new(move |env| block(compiled_items.clone(), 0, env))
+            }
+            // Fixity declarations only matter to the parser.
+            ItemKind::Fixity { .. } => CompiledCode::new(|_env| Ok(Value::Void.into())),
         }
     }
 }
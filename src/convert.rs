@@ -0,0 +1,221 @@
+/// Conversions between Chimera values and Rust ones, for use with `Interpreter`.
+/// Lists are `Vec`s and strings are lists of characters, while `Option` and
+/// `Result` map to the data types of the same name in the core library.
+use crate::error::Error;
+use crate::value::{Value, WoValue};
+
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+pub trait FromValue: Sized {
+    fn from_value(value: &WoValue) -> Result<Self, Error>;
+}
+
+fn mismatch<T>(expected: &'static str, value: &WoValue) -> Result<T, Error> {
+    Err(Error::Conversion {
+        expected,
+        found: value.borrow().to_string(),
+    })
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Value {
+        self
+    }
+}
+
+impl FromValue for WoValue {
+    fn from_value(value: &WoValue) -> Result<Self, Error> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Value {
+        Value::Void
+    }
+}
+
+impl FromValue for () {
+    fn from_value(value: &WoValue) -> Result<Self, Error> {
+        match *value.borrow() {
+            Value::Void => Ok(()),
+            _ => mismatch("()", value),
+        }
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Value {
+        Value::Int(self)
+    }
+}
+
+impl FromValue for i64 {
+    fn from_value(value: &WoValue) -> Result<Self, Error> {
+        match *value.borrow() {
+            Value::Int(i) => Ok(i),
+            _ => mismatch("an Int", value),
+        }
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Value {
+        Value::Bool(self)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &WoValue) -> Result<Self, Error> {
+        match *value.borrow() {
+            Value::Bool(b) => Ok(b),
+            _ => mismatch("a Bool", value),
+        }
+    }
+}
+
+impl IntoValue for char {
+    fn into_value(self) -> Value {
+        Value::Char(self)
+    }
+}
+
+impl FromValue for char {
+    fn from_value(value: &WoValue) -> Result<Self, Error> {
+        match *value.borrow() {
+            Value::Char(c) => Ok(c),
+            _ => mismatch("a Char", value),
+        }
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Value {
+        self.chars().collect::<Vec<_>>().into_value()
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Value {
+        self.as_str().into_value()
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &WoValue) -> Result<Self, Error> {
+        match Vec::<char>::from_value(value) {
+            Ok(chars) => Ok(chars.into_iter().collect()),
+            Err(_) => mismatch("a List Char", value),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        let elems = self.into_iter().map(|e| e.into_value().into()).collect::<Vec<_>>();
+        Value::List(elems.into())
+    }
+}
+
+/// Both lists and arrays convert to vectors.
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &WoValue) -> Result<Self, Error> {
+        let elems = match &*value.borrow() {
+            Value::List(list) => Vec::from(list.clone()),
+            Value::Array(elems) => elems.to_vec(),
+            _ => return mismatch("a List", value),
+        };
+        elems.iter().map(T::from_value).collect()
+    }
+}
+
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(value) => Value::Data {
+                constructor: "Some".to_string(),
+                fields: vec![value.into_value().into()],
+            },
+            None => Value::Data {
+                constructor: "None".to_string(),
+                fields: Vec::new(),
+            },
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: &WoValue) -> Result<Self, Error> {
+        match &*value.borrow() {
+            Value::Data {
+                constructor,
+                fields,
+            } if constructor == "Some" && fields.len() == 1 => Ok(Some(T::from_value(&fields[0])?)),
+            Value::Data {
+                constructor,
+                fields,
+            } if constructor == "None" && fields.is_empty() => Ok(None),
+            _ => mismatch("an Option", value),
+        }
+    }
+}
+
+impl<T: IntoValue, E: IntoValue> IntoValue for Result<T, E> {
+    fn into_value(self) -> Value {
+        let (constructor, field) = match self {
+            Ok(value) => ("Ok", value.into_value()),
+            Err(error) => ("Err", error.into_value()),
+        };
+        Value::Data {
+            constructor: constructor.to_string(),
+            fields: vec![field.into()],
+        }
+    }
+}
+
+impl<T: FromValue, E: FromValue> FromValue for Result<T, E> {
+    fn from_value(value: &WoValue) -> Result<Self, Error> {
+        match &*value.borrow() {
+            Value::Data {
+                constructor,
+                fields,
+            } if fields.len() == 1 => match constructor.as_str() {
+                "Ok" => Ok(Ok(T::from_value(&fields[0])?)),
+                "Err" => Ok(Err(E::from_value(&fields[0])?)),
+                _ => mismatch("a Result", value),
+            },
+            _ => mismatch("a Result", value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: IntoValue + FromValue>(value: T) -> T {
+        T::from_value(&value.into_value().into()).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        assert_eq!(round_trip(42), 42);
+        assert_eq!(round_trip("héllo".to_string()), "héllo");
+        assert_eq!(round_trip(vec![Some(true), None]), vec![Some(true), None]);
+        let result: Result<i64, String> = Err("oops".to_string());
+        assert_eq!(round_trip(result.clone()), result);
+    }
+
+    #[test]
+    fn mismatches() {
+        let value: WoValue = Value::Bool(true).into();
+        assert!(matches!(
+            i64::from_value(&value),
+            Err(Error::Conversion { expected: "an Int", .. })
+        ));
+        let chars: WoValue = "abc".into_value().into();
+        assert!(Vec::<i64>::from_value(&chars).is_err());
+    }
+}
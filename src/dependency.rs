@@ -0,0 +1,416 @@
+/// Dependency analysis of a sequence of items, as found at the top-level,
+/// in modules and in blocks. Definitions that refer to each other, directly
+/// or not, form a group which the type checker must handle all at once.
+use std::collections::{HashMap, HashSet};
+use std::slice;
+
+use crate::ast::{Expr, Item, ItemKind, Pattern, Stmt};
+
+/// The strongly connected components of the dependency graph of `items`.
+/// Groups are numbered in dependency order: a group only ever depends on
+/// groups with a smaller number, so they can be checked in that order.
+pub struct Groups<'i> {
+    items: Vec<&'i Item>,
+    groups: Vec<Vec<usize>>,
+    group_of: Vec<usize>,
+}
+
+impl<'i> Groups<'i> {
+    pub fn new(items: Vec<&'i Item>) -> Self {
+        let edges = (0..items.len())
+            .map(|i| dependencies(&items, i))
+            .collect::<Vec<_>>();
+        let mut tarjan = Tarjan {
+            edges: &edges,
+            index: vec![None; items.len()],
+            lowlink: vec![0; items.len()],
+            stack: Vec::new(),
+            on_stack: vec![false; items.len()],
+            next: 0,
+            groups: Vec::new(),
+        };
+        for i in 0..items.len() {
+            if tarjan.index[i].is_none() {
+                tarjan.connect(i);
+            }
+        }
+        let mut groups = tarjan.groups;
+        let mut group_of = vec![0; items.len()];
+        for (g, members) in groups.iter_mut().enumerate() {
+            // Members are checked in source order.
+            members.sort_unstable();
+            for &i in members.iter() {
+                group_of[i] = g;
+            }
+        }
+        Groups {
+            items,
+            groups,
+            group_of,
+        }
+    }
+
+    /// The group the `i`th item belongs to.
+    pub fn group_of(&self, i: usize) -> usize {
+        self.group_of[i]
+    }
+
+    /// The indices of the items in the `g`th group, in source order.
+    pub fn indices(&self, g: usize) -> &[usize] {
+        &self.groups[g]
+    }
+
+    pub fn members(&self, g: usize) -> impl Iterator<Item = &'i Item> + '_ {
+        self.groups[g].iter().map(|&i| self.items[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.groups.len()
+    }
+}
+
+/// Tarjan's algorithm, which emits components in reverse topological order,
+/// that is, every component comes after the components it depends on.
+struct Tarjan<'e> {
+    edges: &'e [Vec<usize>],
+    index: Vec<Option<usize>>,
+    lowlink: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    next: usize,
+    groups: Vec<Vec<usize>>,
+}
+
+impl Tarjan<'_> {
+    fn connect(&mut self, v: usize) {
+        self.index[v] = Some(self.next);
+        self.lowlink[v] = self.next;
+        self.next += 1;
+        self.stack.push(v);
+        self.on_stack[v] = true;
+        for &w in &self.edges[v] {
+            match self.index[w] {
+                None => {
+                    self.connect(w);
+                    self.lowlink[v] = self.lowlink[v].min(self.lowlink[w]);
+                }
+                Some(index) if self.on_stack[w] => {
+                    self.lowlink[v] = self.lowlink[v].min(index);
+                }
+                Some(_) => (),
+            }
+        }
+        if Some(self.lowlink[v]) == self.index[v] {
+            let mut group = Vec::new();
+            while let Some(w) = self.stack.pop() {
+                self.on_stack[w] = false;
+                group.push(w);
+                if w == v {
+                    break;
+                }
+            }
+            self.groups.push(group);
+        }
+    }
+}
+
+/// The names `item` binds: that of a definition, the operations of an effect,
+/// the constructors of a data type or those bound in a module.
+fn defined_names(item: &Item) -> Vec<&str> {
+    match &item.kind {
+        ItemKind::Definition { name, .. } => vec![name],
+        ItemKind::Effect { operations, .. } => {
+            operations.iter().map(|op| op.name.as_str()).collect()
+        }
+        ItemKind::DataType { variants, .. } => {
+            variants.iter().map(|(name, _)| name.as_str()).collect()
+        }
+        // The names of a module are global for now.
+        ItemKind::Module { items, .. } => items.iter().flat_map(defined_names).collect(),
+        ItemKind::Fixity { .. } => Vec::new(),
+    }
+}
+
+/// The definitions, effects and data types among `items` that the `i`th item
+/// refers to.
+/// A name refers to its latest definition up to and including the `i`th item,
+/// or else to its first definition after it, as the items might be
+/// mutually recursive.
+fn dependencies(items: &[&Item], i: usize) -> Vec<usize> {
+    let mut names = HashSet::new();
+    free_names_item(items[i], &mut Vec::new(), &mut names);
+    let mut deps = names
+        .iter()
+        .filter_map(|name| {
+            let defines = |j: &usize| defined_names(items[*j]).contains(&name.as_str());
+            (0..=i)
+                .rev()
+                .find(defines)
+                .or_else(|| (i + 1..items.len()).find(defines))
+        })
+        .collect::<Vec<_>>();
+    deps.sort_unstable();
+    deps
+}
+
+/// Collects the names that occur in `expr` without being bound in it.
+pub fn free_names(expr: &Expr, bound: &mut Vec<String>, names: &mut HashSet<String>) {
+    Collector { bound, names, lambdas: true }.expr(expr)
+}
+
+/// The names defined in a block are bound throughout it.
+pub fn free_names_body(body: &[Stmt], bound: &mut Vec<String>, names: &mut HashSet<String>) {
+    Collector { bound, names, lambdas: true }.body(body)
+}
+
+fn free_names_item(item: &Item, bound: &mut Vec<String>, names: &mut HashSet<String>) {
+    Collector { bound, names, lambdas: true }.item(item)
+}
+
+/// Collects free names, leaving out the bodies of functions unless `lambdas`
+/// is set: without them, these are the names an expression needs right away.
+struct Collector<'c> {
+    bound: &'c mut Vec<String>,
+    names: &'c mut HashSet<String>,
+    lambdas: bool,
+}
+
+impl Collector<'_> {
+    fn name(&mut self, name: &str) {
+        if !self.bound.iter().any(|b| b == name) {
+            self.names.insert(name.to_string());
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Name(name) => self.name(name),
+            Expr::Lambda { .. } if !self.lambdas => (),
+            Expr::Lambda { param, expr, .. } => {
+                self.bound.push(param.clone());
+                self.expr(expr);
+                self.bound.pop();
+            }
+            Expr::Block { body } => self.body(body),
+            Expr::Branch { paths } => {
+                for (cond, body) in paths {
+                    self.expr(cond);
+                    self.body(body);
+                }
+            }
+            Expr::Infix { head, tail } => {
+                self.expr(head);
+                for (op, operand) in tail {
+                    self.name(op);
+                    self.expr(operand);
+                }
+            }
+            Expr::List(elems) | Expr::Array(elems) => {
+                for elem in elems {
+                    self.expr(elem);
+                }
+            }
+            Expr::Apply { left, right } | Expr::Assign { left, right } => {
+                self.expr(left);
+                self.expr(right);
+            }
+            Expr::Negate { expr }
+            | Expr::Ascribe { expr, .. }
+            | Expr::Field { expr, .. }
+            | Expr::Located { expr, .. } => self.expr(expr),
+            Expr::Handle { expr, clauses } => {
+                self.expr(expr);
+                for clause in clauses {
+                    self.name(&clause.operation);
+                    self.bound.push(clause.param.clone());
+                    self.bound.push(clause.resume.clone());
+                    self.expr(&clause.body);
+                    self.bound.truncate(self.bound.len() - 2);
+                }
+            }
+            Expr::Try { expr, pattern, handler } => {
+                self.expr(expr);
+                self.arm(pattern, handler);
+            }
+            Expr::Match { expr, arms } => {
+                self.expr(expr);
+                for arm in arms {
+                    self.arm(&arm.pattern, &arm.body);
+                }
+            }
+            Expr::Ellipsis(_) | Expr::Void | Expr::Int(_) | Expr::Bool(_) | Expr::Char(_) => (),
+        }
+    }
+
+    fn body(&mut self, body: &[Stmt]) {
+        let depth = self.bound.len();
+        self.bound.extend(body.iter().flat_map(|stmt| match stmt {
+            Stmt::Item(item) => defined_names(item).into_iter().map(str::to_string).collect(),
+            Stmt::Expr(_) => Vec::new(),
+        }));
+        for stmt in body {
+            match stmt {
+                Stmt::Expr(expr) => self.expr(expr),
+                Stmt::Item(item) => self.item(item),
+            }
+        }
+        self.bound.truncate(depth);
+    }
+
+    /// The constructor of a pattern is needed, and the names it binds are
+    /// bound in the body of its arm.
+    fn arm(&mut self, pattern: &Pattern, body: &Expr) {
+        if let Pattern::Constructor { name, .. } = pattern {
+            self.name(name);
+        }
+        let depth = self.bound.len();
+        self.bound.extend(pattern.names().iter().cloned());
+        self.expr(body);
+        self.bound.truncate(depth);
+    }
+
+    fn item(&mut self, item: &Item) {
+        match &item.kind {
+            ItemKind::Definition { expr, .. } => self.expr(expr),
+            ItemKind::Module { items, .. } => {
+                for item in items {
+                    self.item(item);
+                }
+            }
+            ItemKind::DataType { .. } | ItemKind::Effect { .. } | ItemKind::Fixity { .. } => (),
+        }
+    }
+}
+
+/// A step of a sequence that is run in order: an item, or an expression
+/// whose value is discarded.
+pub enum Step<'s> {
+    Item(&'s Item),
+    Expr(&'s Expr),
+}
+
+impl<'s> Step<'s> {
+    /// The steps of `items`, in which the items of modules are run in place.
+    pub fn items(items: &'s [Item]) -> Vec<Step<'s>> {
+        let mut steps = Vec::new();
+        for item in items {
+            match &item.kind {
+                ItemKind::Module { items, .. } => steps.extend(Step::items(items)),
+                _ => steps.push(Step::Item(item)),
+            }
+        }
+        steps
+    }
+
+    /// The steps of a block.
+    pub fn body(body: &'s [Stmt]) -> Vec<Step<'s>> {
+        body.iter()
+            .flat_map(|stmt| match stmt {
+                Stmt::Item(item) => Step::items(slice::from_ref(item)),
+                Stmt::Expr(expr) => vec![Step::Expr(expr)],
+            })
+            .collect()
+    }
+}
+
+/// The first name of `steps` that would be used before the definition it
+/// refers to has run, if any. A step uses the names it refers to outside of
+/// functions, and since it may call the functions it refers to, every name
+/// they refer to in turn, and so on. Only the steps before it have run then,
+/// hence a definition may only refer to itself or to a later one lazily,
+/// from the body of a function.
+pub fn used_early(steps: &[Step]) -> Option<String> {
+    let mut positions = HashMap::<&str, Vec<usize>>::new();
+    for (i, step) in steps.iter().enumerate() {
+        if let Step::Item(item) = step {
+            for name in defined_names(item) {
+                positions.entry(name).or_default().push(i);
+            }
+        }
+    }
+    // Every name a definition refers to, computed as needed.
+    let mut refers = vec![None; steps.len()];
+    for (i, step) in steps.iter().enumerate() {
+        let mut needed = HashSet::new();
+        let mut collector = Collector {
+            bound: &mut Vec::new(),
+            names: &mut needed,
+            lambdas: false,
+        };
+        match step {
+            Step::Item(item) => collector.item(item),
+            Step::Expr(expr) => collector.expr(expr),
+        }
+        let mut todo = needed.into_iter().collect::<Vec<_>>();
+        let mut seen = HashSet::new();
+        while let Some(name) = todo.pop() {
+            let Some(defined) = positions.get(name.as_str()) else {
+                // Defined outside of the sequence.
+                continue;
+            };
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            // The definition in scope once the previous steps have run.
+            let j = match defined.partition_point(|&j| j < i) {
+                0 => return Some(name),
+                k => defined[k - 1],
+            };
+            let names = refers[j].get_or_insert_with(|| {
+                let mut names = HashSet::new();
+                if let Step::Item(item) = steps[j] {
+                    free_names_item(item, &mut Vec::new(), &mut names);
+                }
+                names
+            });
+            todo.extend(names.iter().cloned());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixity::Fixities;
+    use crate::parser::parse;
+
+    use super::*;
+
+    fn groups_of(source: &str) -> Vec<Vec<String>> {
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        let groups = Groups::new(items.iter().collect());
+        (0..groups.len())
+            .map(|g| {
+                groups
+                    .members(g)
+                    .flat_map(defined_names)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        let source = "\
+let even = |n| if n == 0 then true else odd (n - 1) end
+let odd = |n| if n == 0 then false else even (n - 1) end
+let (==) = ...
+let (-) = ...
+";
+        assert_eq!(
+            groups_of(source),
+            vec![vec!["(==)"], vec!["(-)"], vec!["even", "odd"]]
+        );
+    }
+
+    #[test]
+    fn shadowed_names() {
+        // The first `f` is not recursive, it refers to `x`, and is shadowed.
+        let source = "let x = 0\nlet f = |y| x\nlet x = f ()\nlet f = |y| f y\n";
+        assert_eq!(
+            groups_of(source),
+            vec![vec!["x"], vec!["f"], vec!["x"], vec!["f"]]
+        );
+    }
+}
@@ -0,0 +1,543 @@
+/// Chimera's documentation generator, used by `chimera doc`.
+/// Every module gets its own page, listing its items alongside their
+/// doc comments and their annotated (or otherwise inferred) types.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use polytype::TypeSchema;
+
+use crate::ast::{Item, ItemKind, Operation};
+use crate::typechecker::{Lexicon, Pretty};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Html,
+    Markdown,
+}
+
+impl Format {
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Html => "html",
+            Format::Markdown => "md",
+        }
+    }
+}
+
+struct Page {
+    path: Vec<String>,
+    doc: Option<String>,
+    entries: Vec<Entry>,
+}
+
+struct Entry {
+    name: String,
+    doc: Option<String>,
+    kind: EntryKind,
+}
+
+enum EntryKind {
+    Definition {
+        schema: Option<TypeSchema>,
+        intrinsic: Option<String>,
+    },
+    DataType {
+        schema: TypeSchema,
+        variants: Vec<(String, Vec<(String, TypeSchema)>)>,
+    },
+    Effect {
+        schema: TypeSchema,
+        operations: Vec<Operation>,
+    },
+    // The index of the module's own page.
+    Module(usize),
+}
+
+impl Entry {
+    fn anchor(&self) -> String {
+        match self.kind {
+            EntryKind::Definition { .. } => format!("value.{}", slug(&self.name)),
+            EntryKind::DataType { .. } => format!("type.{}", slug(&self.name)),
+            EntryKind::Effect { .. } => format!("effect.{}", slug(&self.name)),
+            EntryKind::Module(_) => format!("mod.{}", slug(&self.name)),
+        }
+    }
+}
+
+/// Where a documented name can be found.
+struct Target {
+    page: String,
+    anchor: String,
+}
+
+struct Generator {
+    format: Format,
+    pages: Vec<Page>,
+    // Names are resolved to all the places they are defined in.
+    links: HashMap<String, Vec<Target>>,
+}
+
+/// Generates the documentation of `items` in the directory `out`.
+pub fn generate(items: &[Item], format: Format, out: &Path) -> Result<()> {
+    let generator = Generator::new(items, format);
+    fs::create_dir_all(out)
+        .with_context(|| format!("error creating the directory `{}`", out.display()))?;
+    for page in &generator.pages {
+        let file = out.join(generator.file(&page.path));
+        let contents = match format {
+            Format::Html => generator.html(page),
+            Format::Markdown => generator.markdown(page),
+        };
+        fs::write(&file, contents)
+            .with_context(|| format!("error writing the file `{}`", file.display()))?;
+    }
+    Ok(())
+}
+
+impl Generator {
+    fn new(items: &[Item], format: Format) -> Self {
+        let mut generator = Generator {
+            format,
+            pages: vec![Page {
+                path: vec![],
+                doc: None,
+                entries: vec![],
+            }],
+            links: HashMap::new(),
+        };
+        generator.collect(items, 0, &Lexicon::default());
+        generator.index();
+        generator
+    }
+
+    /// Walks `items` and records them in the page numbered `page`, types are
+    /// inferred along the way with `lexicon` as one would when checking a program.
+    fn collect(&mut self, items: &[Item], page: usize, lexicon: &Lexicon) {
+        for item in items {
+            let (name, kind) = match &item.kind {
+                ItemKind::Definition { name, ann, .. } => {
+                    // A definition that doesn't type-check is still documented,
+                    // its type will simply be missing.
+                    let checked = lexicon.check(item).is_ok();
+                    let schema = match ann {
+                        Some(ts) => Some(ts.clone()),
+                        None if checked => lexicon.schema(name),
+                        None => None,
+                    };
+                    let intrinsic = match &item.attr {
+                        Some(attr) if attr.name == "intrinsic" => attr.args.first().cloned(),
+                        _ => None,
+                    };
+                    (name.clone(), EntryKind::Definition { schema, intrinsic })
+                }
+                ItemKind::DataType { schema, variants } => {
+                    let name = match schema {
+                        TypeSchema::Monotype(polytype::Type::Constructed(n, _)) => n.to_string(),
+                        _ => schema.to_string(),
+                    };
+                    let variants = variants.clone();
+                    (
+                        name,
+                        EntryKind::DataType {
+                            schema: schema.clone(),
+                            variants,
+                        },
+                    )
+                }
+                ItemKind::Effect { schema, operations } => {
+                    // Its operations must be in scope for the definitions using them.
+                    let _ = lexicon.check(item);
+                    let name = match schema {
+                        TypeSchema::Monotype(polytype::Type::Constructed(n, _)) => n.to_string(),
+                        _ => schema.to_string(),
+                    };
+                    let kind = EntryKind::Effect {
+                        schema: schema.clone(),
+                        operations: operations.clone(),
+                    };
+                    (name, kind)
+                }
+                ItemKind::Module { name, items } => {
+                    let mut path = self.pages[page].path.clone();
+                    path.push(name.clone());
+                    self.pages.push(Page {
+                        path,
+                        doc: item.doc.clone(),
+                        entries: vec![],
+                    });
+                    let index = self.pages.len() - 1;
+                    self.collect(items, index, lexicon);
+                    (name.clone(), EntryKind::Module(index))
+                }
+                ItemKind::Fixity { .. } => continue,
+            };
+            self.pages[page].entries.push(Entry {
+                name,
+                doc: item.doc.clone(),
+                kind,
+            });
+        }
+    }
+
+    /// Records where every documented name lives, for cross-linking.
+    fn index(&mut self) {
+        for page in &self.pages {
+            let file = self.file(&page.path);
+            for entry in &page.entries {
+                let (name, anchor) = (entry.name.clone(), entry.anchor());
+                let target = Target {
+                    page: file.clone(),
+                    anchor,
+                };
+                self.links.entry(name).or_default().push(target);
+            }
+        }
+    }
+
+    fn file(&self, path: &[String]) -> String {
+        if path.is_empty() {
+            format!("index.{}", self.format.extension())
+        } else {
+            format!("{}.{}", path.join("."), self.format.extension())
+        }
+    }
+
+    /// A link to `name` as seen from `page`, names on the same page are preferred.
+    fn link(&self, name: &str, page: &str) -> Option<String> {
+        let targets = self.links.get(name)?;
+        let target = targets
+            .iter()
+            .find(|t| t.page == page)
+            .unwrap_or(&targets[0]);
+        if target.page == page {
+            Some(format!("#{}", target.anchor))
+        } else {
+            Some(format!("{}#{}", target.page, target.anchor))
+        }
+    }
+
+    /// Links the quoted code in doc comments, which may be a name such as
+    /// `map`, an operator in parentheses `(|>)` or a bare operator `|>`.
+    fn link_code(&self, code: &str, page: &str) -> Option<String> {
+        self.link(code, page)
+            .or_else(|| self.link(&format!("({})", code), page))
+    }
+
+    /// Displays a type, calling `link` on each data type name that is documented.
+    fn fmt_type(&self, ts: &TypeSchema, page: &str, link: impl Fn(&str, &str) -> String) -> String {
+        let source = Pretty(ts).to_string();
+        let mut result = String::new();
+        let mut rest = source.as_str();
+        while let Some(start) = rest.find(|c: char| c.is_uppercase()) {
+            let len = rest[start..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len() - start);
+            let word = &rest[start..start + len];
+            result.push_str(&self.plain(&rest[..start]));
+            match self.link(word, page) {
+                Some(href) => result.push_str(&link(word, &href)),
+                None => result.push_str(word),
+            }
+            rest = &rest[start + len..];
+        }
+        result.push_str(&self.plain(rest));
+        result
+    }
+
+    fn plain(&self, text: &str) -> String {
+        match self.format {
+            Format::Html => escape(text),
+            Format::Markdown => text.to_string(),
+        }
+    }
+
+    fn title(page: &Page) -> String {
+        if page.path.is_empty() {
+            "Documentation".to_string()
+        } else {
+            format!("Module {}", page.path.join("::"))
+        }
+    }
+
+    fn html(&self, page: &Page) -> String {
+        let file = self.file(&page.path);
+        let mut out = String::new();
+        let _ = write!(
+            out,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n",
+            escape(&Self::title(page)),
+            STYLE
+        );
+        let _ = writeln!(out, "<nav><a href=\"{}\">index</a></nav>", self.file(&[]));
+        let _ = writeln!(out, "<h1>{}</h1>", escape(&Self::title(page)));
+        if let Some(doc) = &page.doc {
+            out.push_str(&self.html_doc(doc, &file));
+        }
+        for entry in &page.entries {
+            let anchor = entry.anchor();
+            let _ = writeln!(out, "<section class=\"item\" id=\"{}\">", anchor);
+            let name = format!("<a href=\"#{}\">{}</a>", anchor, escape(&entry.name));
+            let link = |word: &str, href: &str| format!("<a href=\"{}\">{}</a>", href, word);
+            match &entry.kind {
+                EntryKind::Definition { schema, intrinsic } => {
+                    let _ = match schema {
+                        Some(ts) => writeln!(
+                            out,
+                            "<h3><code>let {} : {}</code></h3>",
+                            name,
+                            self.fmt_type(ts, &file, link)
+                        ),
+                        None => writeln!(out, "<h3><code>let {}</code></h3>", name),
+                    };
+                    if let Some(intrinsic) = intrinsic {
+                        let _ = writeln!(
+                            out,
+                            "<p class=\"attr\"><code>@[intrinsic({})]</code></p>",
+                            escape(intrinsic)
+                        );
+                    }
+                }
+                EntryKind::DataType { schema, variants } => {
+                    let _ = writeln!(
+                        out,
+                        "<h3><code>data {}</code></h3>",
+                        self.fmt_type(schema, &file, link)
+                    );
+                    out.push_str("<ul class=\"variants\">\n");
+                    for (variant, fields) in variants {
+                        let fields = fields
+                            .iter()
+                            .map(|(f, ts)| {
+                                format!("{}: {}", escape(f), self.fmt_type(ts, &file, link))
+                            })
+                            .collect::<Vec<_>>();
+                        let _ = writeln!(
+                            out,
+                            "<li><code>{} {{ {} }}</code></li>",
+                            escape(variant),
+                            fields.join(", ")
+                        );
+                    }
+                    out.push_str("</ul>\n");
+                }
+                EntryKind::Effect { schema, operations } => {
+                    let _ = writeln!(
+                        out,
+                        "<h3><code>effect {}</code></h3>",
+                        self.fmt_type(schema, &file, link)
+                    );
+                    out.push_str("<ul class=\"operations\">\n");
+                    for op in operations {
+                        let _ = writeln!(
+                            out,
+                            "<li><code>{} : {}</code>",
+                            escape(&op.name),
+                            self.fmt_type(&op.ann, &file, link)
+                        );
+                        if let Some(doc) = &op.doc {
+                            out.push_str(&self.html_doc(doc, &file));
+                        }
+                        out.push_str("</li>\n");
+                    }
+                    out.push_str("</ul>\n");
+                }
+                EntryKind::Module(index) => {
+                    let href = self.file(&self.pages[*index].path);
+                    let _ = writeln!(
+                        out,
+                        "<h3><code>mod <a href=\"{}\">{}</a></code></h3>",
+                        href,
+                        escape(&entry.name)
+                    );
+                }
+            }
+            if let Some(doc) = &entry.doc {
+                out.push_str(&self.html_doc(doc, &file));
+            }
+            out.push_str("</section>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    fn html_doc(&self, doc: &str, page: &str) -> String {
+        let mut out = String::from("<div class=\"doc\">\n");
+        for paragraph in doc.split("\n\n") {
+            let mut text = String::new();
+            for (i, part) in paragraph.split('`').enumerate() {
+                // Odd parts are the ones within backticks.
+                if i % 2 == 0 {
+                    text.push_str(&escape(part));
+                } else {
+                    match self.link_code(part, page) {
+                        Some(href) => {
+                            let _ = write!(
+                                text,
+                                "<a href=\"{}\"><code>{}</code></a>",
+                                href,
+                                escape(part)
+                            );
+                        }
+                        None => {
+                            let _ = write!(text, "<code>{}</code>", escape(part));
+                        }
+                    }
+                }
+            }
+            let _ = writeln!(out, "<p>{}</p>", text);
+        }
+        out.push_str("</div>\n");
+        out
+    }
+
+    fn markdown(&self, page: &Page) -> String {
+        let file = self.file(&page.path);
+        let mut out = String::new();
+        let _ = writeln!(out, "# {}\n", Self::title(page));
+        if let Some(doc) = &page.doc {
+            let _ = writeln!(out, "{}\n", self.markdown_doc(doc, &file));
+        }
+        for entry in &page.entries {
+            let _ = writeln!(out, "<a id=\"{}\"></a>", entry.anchor());
+            let link = |word: &str, href: &str| format!("[{}]({})", word, href);
+            match &entry.kind {
+                EntryKind::Definition { schema, intrinsic } => {
+                    let _ = writeln!(out, "### `{}`\n", entry.name);
+                    if let Some(ts) = schema {
+                        let _ = writeln!(
+                            out,
+                            "`{}` : {}\n",
+                            entry.name,
+                            self.fmt_type(ts, &file, link)
+                        );
+                    }
+                    if let Some(intrinsic) = intrinsic {
+                        let _ = writeln!(out, "`@[intrinsic({})]`\n", intrinsic);
+                    }
+                }
+                EntryKind::DataType { schema, variants } => {
+                    let _ = writeln!(out, "### data {}\n", self.fmt_type(schema, &file, link));
+                    for (variant, fields) in variants {
+                        let fields = fields
+                            .iter()
+                            .map(|(f, ts)| format!("{}: {}", f, self.fmt_type(ts, &file, link)))
+                            .collect::<Vec<_>>();
+                        let _ = writeln!(out, "- `{}` {{ {} }}", variant, fields.join(", "));
+                    }
+                    out.push('\n');
+                }
+                EntryKind::Effect { schema, operations } => {
+                    let _ = writeln!(out, "### effect {}\n", self.fmt_type(schema, &file, link));
+                    for op in operations {
+                        let ann = self.fmt_type(&op.ann, &file, link);
+                        let _ = write!(out, "- `{}` : {}", op.name, ann);
+                        match &op.doc {
+                            Some(doc) => {
+                                let _ = writeln!(out, ", {}", self.markdown_doc(doc, &file));
+                            }
+                            None => out.push('\n'),
+                        }
+                    }
+                    out.push('\n');
+                }
+                EntryKind::Module(index) => {
+                    let href = self.file(&self.pages[*index].path);
+                    let _ = writeln!(out, "### mod [`{}`]({})\n", entry.name, href);
+                }
+            }
+            if let Some(doc) = &entry.doc {
+                let _ = writeln!(out, "{}\n", self.markdown_doc(doc, &file));
+            }
+        }
+        out
+    }
+
+    fn markdown_doc(&self, doc: &str, page: &str) -> String {
+        let mut text = String::new();
+        for (i, part) in doc.split('`').enumerate() {
+            if i % 2 == 0 {
+                text.push_str(part);
+            } else {
+                match self.link_code(part, page) {
+                    Some(href) => {
+                        let _ = write!(text, "[`{}`]({})", part, href);
+                    }
+                    None => {
+                        let _ = write!(text, "`{}`", part);
+                    }
+                }
+            }
+        }
+        text
+    }
+}
+
+/// Turns a name into something usable as an HTML id, operators
+/// such as `(|>)` are spelled out by their character codes.
+fn slug(name: &str) -> String {
+    match name.strip_prefix('(').and_then(|n| n.strip_suffix(')')) {
+        Some(op) => {
+            let codes = op
+                .chars()
+                .map(|c| format!("{:x}", c as u32))
+                .collect::<Vec<_>>();
+            format!("op-{}", codes.join("-"))
+        }
+        None => name.to_string(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str = "\
+body { max-width: 50em; margin: auto; font-family: sans-serif; line-height: 1.5; }
+code { font-family: monospace; }
+section.item { border-top: 1px solid #ddd; }
+section.item h3 { margin-bottom: 0.25em; }
+p.attr { color: #777; margin: 0; }
+a { color: #3873ad; text-decoration: none; }";
+
+#[cfg(test)]
+mod tests {
+    use crate::fixity::Fixities;
+    use crate::parser::parse;
+
+    use super::*;
+
+    #[test]
+    fn slug_operator() {
+        assert_eq!(slug("map"), "map");
+        assert_eq!(slug("(|>)"), "op-7c-3e");
+    }
+
+    #[test]
+    fn markdown_module_links() {
+        let source = "mod lists
+            --- Doubles every element, see `double`.
+            let twice = |xs| xs
+        end
+        --- The double of `x`, also used by `lists`.
+        let double = |x| x
+        ";
+        let generator = Generator::new(
+            &parse(source, &mut Fixities::default()).unwrap(),
+            Format::Markdown,
+        );
+        assert_eq!(generator.pages.len(), 2);
+        let index = generator.markdown(&generator.pages[0]);
+        assert!(index.contains("### mod [`lists`](lists.md)"));
+        assert!(index.contains("`double` : forall a. a -> a"));
+        assert!(index.contains("also used by [`lists`](#mod.lists)."));
+        let lists = generator.markdown(&generator.pages[1]);
+        assert!(lists.contains("see [`double`](index.md#value.double)."));
+    }
+}
@@ -0,0 +1,238 @@
+/// Effect handlers at runtime. Performing an operation looks up the innermost
+/// handler of it in a dynamic stack, then unwinds to its `handle` expression
+/// with an `Unwind::Perform`. The compiled closures it goes through add what
+/// they have left to do to its continuation, which the handler's clause is
+/// given as `resume`: calling it carries on with the handled expression, under
+/// the same handler, and returns what the handled expression ends up with.
+/// A continuation can only be resumed once.
+/// Most clauses only ever resume last, as the ones of readers and printers do,
+/// in which case capturing the continuation is a waste: these clauses run
+/// right where the operation is performed, as if it were a function, and
+/// `resume` unwinds back there. A clause that returns without resuming
+/// unwinds to its `handle` expression instead, which returns what the clause
+/// returned.
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::ast::{Clause, Expr, Stmt};
+use crate::attribute;
+use crate::code::{CompiledCode, Continuation, Env, Outcome, Unwind, WoEnv};
+use crate::compiler::apply;
+use crate::dependency;
+use crate::value::{Value, WoValue};
+
+pub struct CompiledClause {
+    pub operation: String,
+    pub param: String,
+    pub resume: String,
+    pub body: CompiledCode,
+    /// Whether the clause only calls `resume` last, if at all, see `resumes_last`.
+    pub last: bool,
+}
+
+/// The `resume` function of a clause, see `Value::Continuation`.
+#[derive(Debug, PartialEq)]
+pub enum Resume {
+    /// Unwinds back to the operation performed under the handler numbered
+    /// `handler`, for a clause that runs right where it is performed.
+    Last { handler: usize },
+    /// The captured continuation of the operation, until it is resumed.
+    Captured(Rc<RefCell<Option<Continuation>>>),
+}
+
+/// A running `handle` expression, along with the Env its clauses run in.
+struct Handler {
+    id: usize,
+    clauses: Rc<Vec<CompiledClause>>,
+    env: WoEnv,
+}
+
+impl Handler {
+    fn clause(&self, operation: &str) -> &CompiledClause {
+        self.clauses
+            .iter()
+            .find(|c| c.operation == operation)
+            .expect("handlers are only looked up by the operations they handle")
+    }
+}
+
+thread_local! {
+    // The handlers being run, innermost last.
+    static HANDLERS: RefCell<Vec<Rc<Handler>>> = const { RefCell::new(Vec::new()) };
+    static NEXT_ID: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Runs `expr` with `clauses` handling the operations it performs.
+pub fn handle(
+    clauses: &Rc<Vec<CompiledClause>>,
+    env: WoEnv,
+    expr: &Rc<CompiledCode>,
+) -> Outcome {
+    let handler = Rc::new(Handler {
+        id: NEXT_ID.with(|next| next.replace(next.get() + 1)),
+        clauses: clauses.clone(),
+        env: env.clone(),
+    });
+    let result = under(&handler, Box::new(|| expr.execute(env)));
+    handled(&handler, result)
+}
+
+/// Runs `run` with `handler` installed. The operations performed under it
+/// for an outer handler are resumed under it as well.
+fn under(handler: &Rc<Handler>, run: Box<dyn '_ + FnOnce() -> Outcome>) -> Outcome {
+    HANDLERS.with(|handlers| handlers.borrow_mut().push(handler.clone()));
+    let result = run();
+    HANDLERS.with(|handlers| handlers.borrow_mut().pop());
+    match result {
+        Err(Unwind::Abort { handler: id, value }) if id == handler.id => Ok(value),
+        Err(unwind @ Unwind::Perform { handler: id, .. }) if id != handler.id => {
+            let handler = handler.clone();
+            Err(unwind.around(|resume| {
+                Continuation::new(move |value| {
+                    handled(&handler, under(&handler, Box::new(|| resume.resume(value))))
+                })
+            }))
+        }
+        result => result,
+    }
+}
+
+/// Runs the clause of `handler` for the operation performed under it, if
+/// any, with the continuation of the operation as its `resume` function.
+fn handled(handler: &Rc<Handler>, result: Outcome) -> Outcome {
+    match result {
+        Err(Unwind::Perform {
+            handler: id,
+            operation,
+            value,
+            resume,
+        }) if id == handler.id => {
+            let clause = handler.clause(&operation);
+            let cenv = Env::new(Some(handler.env.clone()));
+            let outer = handler.clone();
+            let resume = Continuation::new(move |value| {
+                handled(&outer, under(&outer, Box::new(|| resume.resume(value))))
+            });
+            let resume = Resume::Captured(Rc::new(RefCell::new(Some(resume))));
+            let resume = Value::Continuation(resume);
+            cenv.borrow_mut().names.insert(clause.param.clone(), value);
+            cenv.borrow_mut().names.insert(clause.resume.clone(), resume.into());
+            clause.body.execute(cenv)
+        }
+        result => result,
+    }
+}
+
+/// Performs the operation `name` with the argument `value`. Without a handler,
+/// the operation is left to its `default` implementation if it has one.
+pub fn perform(name: &str, value: WoValue, default: Option<&WoValue>) -> Outcome {
+    let found = HANDLERS.with(|handlers| {
+        handlers
+            .borrow()
+            .iter()
+            .rev()
+            .find(|h| h.clauses.iter().any(|c| c.operation == name))
+            .cloned()
+    });
+    let handler = match (found, default) {
+        (Some(handler), _) => handler,
+        (None, Some(default)) => return apply(default, value),
+        (None, None) => {
+            return attribute::fault(&format!("unhandled effect operation `{}`", name))
+        }
+    };
+    let clause = handler.clause(name);
+    if !clause.last {
+        return Err(Unwind::Perform {
+            handler: handler.id,
+            operation: name.to_string(),
+            value,
+            resume: Continuation::new(Ok),
+        });
+    }
+    let cenv = Env::new(Some(handler.env.clone()));
+    cenv.borrow_mut().names.insert(clause.param.clone(), value);
+    let resume = Value::Continuation(Resume::Last { handler: handler.id });
+    cenv.borrow_mut().names.insert(clause.resume.clone(), resume.into());
+    in_place(handler.id, Box::new(|| clause.body.execute(cenv)))
+}
+
+/// Runs a clause of the handler numbered `id` where its operation is performed,
+/// outside of that handler and of those installed since, which are reinstated
+/// once it is done.
+fn in_place(id: usize, run: Box<dyn '_ + FnOnce() -> Outcome>) -> Outcome {
+    let inner = HANDLERS.with(|handlers| {
+        let mut handlers = handlers.borrow_mut();
+        let index = handlers.iter().rposition(|h| h.id == id).unwrap();
+        handlers.split_off(index)
+    });
+    let result = run();
+    HANDLERS.with(|handlers| handlers.borrow_mut().extend(inner));
+    match result {
+        Err(Unwind::Resume { handler, value }) if handler == id => Ok(value),
+        Ok(value) => Err(Unwind::Abort { handler: id, value }),
+        Err(unwind) => Err(unwind.around(|resume| {
+            Continuation::new(move |value| in_place(id, Box::new(|| resume.resume(value))))
+        })),
+    }
+}
+
+/// Resumes the operation a clause handles with `value`.
+pub fn resume(resume: &Resume, value: WoValue) -> Outcome {
+    match resume {
+        Resume::Last { handler } => Err(Unwind::Resume { handler: *handler, value }),
+        Resume::Captured(continuation) => {
+            let continuation = continuation.borrow_mut().take();
+            match continuation {
+                Some(continuation) => continuation.resume(value),
+                None => attribute::fault("resumed an operation twice"),
+            }
+        }
+    }
+}
+
+/// Whether `clause` only calls `resume` last, if at all, so that it can run
+/// where its operation is performed. It may resume last on some paths and not
+/// resume on others, but `resume` may not be used in any other way.
+pub fn resumes_last(clause: &Clause) -> bool {
+    last(&clause.body, &clause.resume)
+}
+
+fn last(expr: &Expr, resume: &str) -> bool {
+    match expr {
+        Expr::Apply { left, right } if matches!(&**left, Expr::Name(name) if name == resume) => {
+            !mentions(right, resume)
+        }
+        Expr::Block { body } => last_of(body, resume),
+        Expr::Branch { paths } => paths
+            .iter()
+            .all(|(cond, body)| !mentions(cond, resume) && last_of(body, resume)),
+        Expr::Match { expr, arms } => {
+            !mentions(expr, resume) && arms.iter().all(|arm| last(&arm.body, resume))
+        }
+        // The exceptions raised once resumed are no concern of the handler.
+        Expr::Try { expr, handler, .. } => !mentions(expr, resume) && last(handler, resume),
+        Expr::Ascribe { expr, .. } | Expr::Located { expr, .. } => last(expr, resume),
+        expr => !mentions(expr, resume),
+    }
+}
+
+fn last_of(body: &[Stmt], resume: &str) -> bool {
+    match body.split_last() {
+        Some((Stmt::Expr(expr), init)) => !mentioned(init, resume) && last(expr, resume),
+        _ => !mentioned(body, resume),
+    }
+}
+
+fn mentions(expr: &Expr, name: &str) -> bool {
+    let mut names = HashSet::new();
+    dependency::free_names(expr, &mut Vec::new(), &mut names);
+    names.contains(name)
+}
+
+fn mentioned(body: &[Stmt], name: &str) -> bool {
+    let mut names = HashSet::new();
+    dependency::free_names_body(body, &mut Vec::new(), &mut names);
+    names.contains(name)
+}
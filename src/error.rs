@@ -1,20 +1,163 @@
-use polytype::UnificationError;
+use std::io;
+use std::time::Duration;
+
 use thiserror::Error;
 
+use crate::ast::Span;
+use crate::kind::Diagnostic;
+
+/// Everything that can go wrong when running Chimera from Rust,
+/// see `Interpreter`.
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("error reading source file `{0}`, you are on your own.")]
+    Io(String, #[source] io::Error),
+    #[error("error while parsing source file `{file}`")]
+    Source {
+        file: String,
+        #[source]
+        error: Box<Error>,
+    },
+    #[error("{0}")]
+    Syntax(String),
+    #[error(transparent)]
+    Fixity(#[from] FixityError),
+    #[error("encountered kind errors:\n{}", fmt_diagnostics(.0))]
+    Kind(Vec<Diagnostic>),
+    // Rendered along with the source it was found in, when it is known.
+    #[error("{}", fmt_type_error(.error, .snippet))]
+    Type {
+        error: TypeError,
+        snippet: Option<String>,
+    },
+    #[error("the name `{0}` is not defined")]
+    Undefined(String),
+    #[error("the native `{0}` is already defined")]
+    Redefined(String),
+    #[error("expected {expected} but found `{found}`")]
+    Conversion { expected: &'static str, found: String },
+    #[error("uncaught exception: {0}")]
+    Exception(String),
+    #[error("exited with status {0}")]
+    Exit(i32),
+    // An effect handler was misused, e.g. by resuming outside of it.
+    #[error("{0}")]
+    Handler(String),
+    #[error(transparent)]
+    Limit(#[from] Limit),
+}
+
+/// The limit a program went over, see `Limits`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Limit {
+    #[error("ran out of fuel after {0} steps")]
+    Fuel(u64),
+    #[error("exceeded the maximum recursion depth of {0}")]
+    Depth(usize),
+    #[error("used more than {0} bytes of stack")]
+    Stack(usize),
+    #[error("created more than {0} values")]
+    Values(u64),
+    #[error("timed out after {} ms", .0.as_millis())]
+    Timeout(Duration),
+    #[error("`{0}` needs I/O, which is not allowed")]
+    Denied(String),
+}
+
+impl From<TypeError> for Error {
+    fn from(error: TypeError) -> Self {
+        Error::Type { error, snippet: None }
+    }
+}
+
+fn fmt_type_error(error: &TypeError, snippet: &Option<String>) -> String {
+    match snippet {
+        Some(snippet) => snippet.clone(),
+        None => format!("encountered a type error: {}", error),
+    }
+}
+
+fn fmt_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    let diagnostics = diagnostics.iter().map(|d| d.to_string()).collect::<Vec<_>>();
+    diagnostics.join("\n")
+}
+
 #[derive(Error, Clone, Debug, PartialEq)]
 pub enum LexicalError {
-    #[error("Invalid syntax")]
-    InvalidSyntax,
+    #[error("invalid character literal")]
+    InvalidCharLiteral(usize, usize),
+    #[error("unterminated block comment")]
+    UnterminatedComment(usize),
+    #[error("integer literal out of range")]
+    IntegerOverflow(usize, usize),
+}
+
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum FixityError {
+    #[error("the precedence of `{0}` must be between 0 and 9, found {1}")]
+    Precedence(String, i64),
+    #[error("the fixity of `{0}` is declared more than once")]
+    Redeclared(String),
+    #[error("`{0}` is non-associative and cannot be chained, use parentheses")]
+    NonAssociative(String),
+    #[error("cannot mix `{0}` and `{1}` in the same infix expression, use parentheses")]
+    Ambiguous(String, String),
+}
+
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum KindError {
+    #[error("the type `{0}` is not in scope")]
+    UnknownType(String),
+    #[error("the type `{0}` expects {1} argument(s) but was given {2}")]
+    Arity(String, usize, usize),
+    #[error("the type variable `{0}` is not bound by the declaration or a `forall`")]
+    UnboundVariable(String),
+    #[error("the effect `{0}` is not in scope")]
+    UnknownEffect(String),
+    #[error("`{0}` is an effect, it can only appear in the effects of a function type")]
+    EffectAsType(String),
 }
 
 #[derive(Error, Debug, PartialEq)]
 pub enum TypeError {
-    // FIXME: do proper error reporting, this would require:
-    //  1. translating this error to a human-readable version
-    //  2. recording information about where in the source code
-    //     the expression shows and use annotate-snippets.
-    #[error("unification error")]
-    UnificationError(#[from] UnificationError),
-    #[error("the name `{0}` is not in scope")]
-    ScopeError(String),
+    // The `span` of `Mismatch` and `ScopeError` is the one of the innermost
+    // expression that is located, see `Expr::Located`.
+    #[error("expected `{expected}` but found `{found}`")]
+    Mismatch {
+        expected: String,
+        found: String,
+        span: Option<Span>,
+    },
+    #[error("the name `{name}` is not in scope")]
+    ScopeError { name: String, span: Option<Span> },
+    #[error("`{0}` is used before it is defined")]
+    UsedEarly(String),
+    #[error("{0} are not supported yet")]
+    Unsupported(&'static str),
+    #[error("the annotation of `{name}` is `{expected}` but its definition has type `{found}`")]
+    AnnotationMismatch {
+        name: String,
+        expected: String,
+        found: String,
+    },
+    #[error("the operation `{0}` must have a function type")]
+    OperationType(String),
+    #[error("`{0}` is not the operation of an effect")]
+    NotAnOperation(String),
+    #[error("`{0}` is not an operation of the effect `{1}`")]
+    ForeignOperation(String, String),
+    #[error("the operation `{0}` is handled more than once")]
+    DuplicateClause(String),
+    #[error("the handler of `{0}` does not handle its operation `{1}`")]
+    UnhandledOperation(String, String),
+    #[error("the clause of `{0}` must work for every type the operation is used at")]
+    ClauseEscape(String),
+    #[error("`{0}` is not the constructor of a data type")]
+    NotAConstructor(String),
+    #[error("the field `{1}` of `{0}` has type variables that aren't parameters of its data type")]
+    UndeclaredVariable(String, String),
+    #[error("the constructor `{0}` has {1} field(s) but its pattern binds {2}")]
+    ConstructorArity(String, usize, usize),
+    #[error("the match expression does not cover the constructor `{0}`")]
+    NonExhaustive(String),
 }
@@ -0,0 +1,357 @@
+/// The fixity pass, which runs right after parsing.
+/// The grammar parses infix expressions as flat chains of operators
+/// and operands, it is here that they are re-associated according to
+/// the `infixl`/`infixr`/`infix` declarations in scope.
+use std::collections::HashMap;
+
+use crate::ast::{Assoc, Expr, Item, ItemKind, Stmt};
+use crate::error::FixityError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fixity {
+    pub assoc: Assoc,
+    pub precedence: i64,
+}
+
+// Operators without a declaration behave as in Haskell.
+const DEFAULT_FIXITY: Fixity = Fixity {
+    assoc: Assoc::Left,
+    precedence: 9,
+};
+
+/// The fixities declared so far, these persist across source files
+/// so that the prelude's declarations apply to the user's program.
+#[derive(Debug, Default, Clone)]
+pub struct Fixities(HashMap<String, Fixity>);
+
+impl Fixities {
+    pub fn get(&self, operator: &str) -> Fixity {
+        self.0.get(operator).copied().unwrap_or(DEFAULT_FIXITY)
+    }
+
+    /// Records every fixity declaration in `items`, including the ones nested
+    /// in modules and blocks. Fixities are global, regardless of where
+    /// they are declared.
+    pub fn declare(&mut self, items: &[Item]) -> Result<(), FixityError> {
+        for item in items {
+            match &item.kind {
+                ItemKind::Fixity {
+                    assoc,
+                    precedence,
+                    operators,
+                } => {
+                    if !(0..=9).contains(precedence) {
+                        return Err(FixityError::Precedence(operators[0].clone(), *precedence));
+                    }
+                    for op in operators {
+                        let fixity = Fixity {
+                            assoc: *assoc,
+                            precedence: *precedence,
+                        };
+                        if self.0.insert(op.clone(), fixity).is_some() {
+                            return Err(FixityError::Redeclared(op.clone()));
+                        }
+                    }
+                }
+                ItemKind::Module { items, .. } => self.declare(items)?,
+                ItemKind::Definition { expr, .. } => self.declare_expr(expr)?,
+                ItemKind::DataType { .. } | ItemKind::Effect { .. } => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn declare_expr(&mut self, expr: &Expr) -> Result<(), FixityError> {
+        match expr {
+            Expr::Infix { head, tail } => {
+                self.declare_expr(head)?;
+                for (_, operand) in tail {
+                    self.declare_expr(operand)?;
+                }
+            }
+            Expr::List(elems) | Expr::Array(elems) => {
+                for elem in elems {
+                    self.declare_expr(elem)?;
+                }
+            }
+            Expr::Lambda { expr, .. }
+            | Expr::Field { expr, .. }
+            | Expr::Negate { expr }
+            | Expr::Ascribe { expr, .. }
+            | Expr::Located { expr, .. } => {
+                self.declare_expr(expr)?
+            }
+            Expr::Apply { left, right } | Expr::Assign { left, right } => {
+                self.declare_expr(left)?;
+                self.declare_expr(right)?;
+            }
+            Expr::Block { body } => self.declare_body(body)?,
+            Expr::Branch { paths } => {
+                for (cond, body) in paths {
+                    self.declare_expr(cond)?;
+                    self.declare_body(body)?;
+                }
+            }
+            Expr::Handle { expr, clauses } => {
+                self.declare_expr(expr)?;
+                for clause in clauses {
+                    self.declare_expr(&clause.body)?;
+                }
+            }
+            Expr::Try { expr, handler, .. } => {
+                self.declare_expr(expr)?;
+                self.declare_expr(handler)?;
+            }
+            Expr::Match { expr, arms } => {
+                self.declare_expr(expr)?;
+                for arm in arms {
+                    self.declare_expr(&arm.body)?;
+                }
+            }
+            Expr::Ellipsis(_)
+            | Expr::Void
+            | Expr::Int(_)
+            | Expr::Bool(_)
+            | Expr::Char(_)
+            | Expr::Name(_) => (),
+        }
+        Ok(())
+    }
+
+    fn declare_body(&mut self, body: &[Stmt]) -> Result<(), FixityError> {
+        for stmt in body {
+            match stmt {
+                Stmt::Expr(expr) => self.declare_expr(expr)?,
+                Stmt::Item(item) => self.declare(std::slice::from_ref(item))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolves every infix chain in `items` into nested applications.
+    pub fn resolve(&self, items: &mut [Item]) -> Result<(), FixityError> {
+        for item in items {
+            match &mut item.kind {
+                ItemKind::Definition { expr, .. } => self.resolve_expr(expr)?,
+                ItemKind::Module { items, .. } => self.resolve(items)?,
+                ItemKind::DataType { .. } | ItemKind::Effect { .. } | ItemKind::Fixity { .. } => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn resolve_expr(&self, expr: &mut Expr) -> Result<(), FixityError> {
+        match expr {
+            Expr::Infix { head, tail } => {
+                self.resolve_expr(head)?;
+                for (_, operand) in tail.iter_mut() {
+                    self.resolve_expr(operand)?;
+                }
+                let head = std::mem::replace(head.as_mut(), Expr::Void);
+                let tail = std::mem::take(tail);
+                *expr = self.reassociate(head, tail)?;
+            }
+            Expr::List(elems) | Expr::Array(elems) => {
+                for elem in elems {
+                    self.resolve_expr(elem)?;
+                }
+            }
+            Expr::Lambda { expr, .. }
+            | Expr::Field { expr, .. }
+            | Expr::Negate { expr }
+            | Expr::Ascribe { expr, .. }
+            | Expr::Located { expr, .. } => {
+                self.resolve_expr(expr)?
+            }
+            Expr::Apply { left, right } | Expr::Assign { left, right } => {
+                self.resolve_expr(left)?;
+                self.resolve_expr(right)?;
+            }
+            Expr::Block { body } => self.resolve_body(body)?,
+            Expr::Branch { paths } => {
+                for (cond, body) in paths {
+                    self.resolve_expr(cond)?;
+                    self.resolve_body(body)?;
+                }
+            }
+            Expr::Handle { expr, clauses } => {
+                self.resolve_expr(expr)?;
+                for clause in clauses {
+                    self.resolve_expr(&mut clause.body)?;
+                }
+            }
+            Expr::Try { expr, handler, .. } => {
+                self.resolve_expr(expr)?;
+                self.resolve_expr(handler)?;
+            }
+            Expr::Match { expr, arms } => {
+                self.resolve_expr(expr)?;
+                for arm in arms {
+                    self.resolve_expr(&mut arm.body)?;
+                }
+            }
+            Expr::Ellipsis(_)
+            | Expr::Void
+            | Expr::Int(_)
+            | Expr::Bool(_)
+            | Expr::Char(_)
+            | Expr::Name(_) => (),
+        }
+        Ok(())
+    }
+
+    fn resolve_body(&self, body: &mut [Stmt]) -> Result<(), FixityError> {
+        for stmt in body {
+            match stmt {
+                Stmt::Expr(expr) => self.resolve_expr(expr)?,
+                Stmt::Item(item) => self.resolve(std::slice::from_mut(item))?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Operator-precedence parsing of a resolved chain `head op1 e1 op2 e2 ...`.
+    /// An operator on the stack is applied before pushing the next one if it
+    /// binds tighter, or as tight and both associate to the left.
+    fn reassociate(&self, head: Expr, tail: Vec<(String, Expr)>) -> Result<Expr, FixityError> {
+        let mut operands = vec![head];
+        let mut operators: Vec<String> = Vec::new();
+        for (op, operand) in tail {
+            let fixity = self.get(&op);
+            while let Some(top) = operators.last() {
+                let top_fixity = self.get(top);
+                if top_fixity.precedence > fixity.precedence
+                    || (top_fixity.precedence == fixity.precedence
+                        && top_fixity.assoc == Assoc::Left
+                        && fixity.assoc == Assoc::Left)
+                {
+                    let top = operators.pop().unwrap();
+                    apply(&mut operands, top);
+                } else if top_fixity.precedence == fixity.precedence
+                    && !(top_fixity.assoc == Assoc::Right && fixity.assoc == Assoc::Right)
+                {
+                    return Err(if *top == op {
+                        FixityError::NonAssociative(op)
+                    } else {
+                        FixityError::Ambiguous(top.clone(), op)
+                    });
+                } else {
+                    break;
+                }
+            }
+            operators.push(op);
+            operands.push(operand);
+        }
+        while let Some(op) = operators.pop() {
+            apply(&mut operands, op);
+        }
+        Ok(operands.pop().unwrap())
+    }
+}
+
+/// Replaces the two topmost operands by `left op right`.
+fn apply(operands: &mut Vec<Expr>, op: String) {
+    let right = operands.pop().unwrap();
+    let left = operands.pop().unwrap();
+    operands.push(Expr::Apply {
+        left: Box::new(Expr::Apply {
+            left: Box::new(Expr::Name(op)),
+            right: Box::new(left),
+        }),
+        right: Box::new(right),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::Span;
+    use crate::parser::parse;
+
+    use super::*;
+
+    fn name(name: &str) -> Box<Expr> {
+        Box::new(Expr::Name(name.to_string()))
+    }
+
+    fn binary(op: &str, left: Expr, right: Expr) -> Expr {
+        Expr::Apply {
+            left: Box::new(Expr::Apply {
+                left: name(op),
+                right: Box::new(left),
+            }),
+            right: Box::new(right),
+        }
+    }
+
+    fn expr_of(source: &str) -> Result<Expr, String> {
+        let mut fixities = Fixities::default();
+        let items = parse(source, &mut fixities).map_err(|e| e.to_string())?;
+        // The chain is located as a whole, it is resolved within.
+        match items.into_iter().last().map(|i| i.kind) {
+            Some(ItemKind::Definition { expr: Expr::Located { expr, .. }, .. }) => Ok(*expr),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let source = "infixl 6 +\ninfixl 7 *\nlet x = 1 + 2 * 3\n";
+        assert_eq!(
+            expr_of(source),
+            Ok(binary(
+                "(+)",
+                Expr::Int(1),
+                binary("(*)", Expr::Int(2), Expr::Int(3))
+            ))
+        );
+    }
+
+    #[test]
+    fn right_associative() {
+        let source = "infixr 5 ::\nlet x = 1 :: 2 :: []\n";
+        assert_eq!(
+            expr_of(source),
+            Ok(binary(
+                "(::)",
+                Expr::Int(1),
+                binary("(::)", Expr::Int(2), Expr::List(vec![]))
+            ))
+        );
+    }
+
+    #[test]
+    fn default_left_associative() {
+        let source = "let x = 1 - 2 - (3 - 4)\n";
+        let start = source.find("3 - 4").unwrap();
+        let nested = Expr::Located {
+            span: Span::new(source, 0, start, start + 5),
+            expr: Box::new(binary("(-)", Expr::Int(3), Expr::Int(4))),
+        };
+        assert_eq!(
+            expr_of(source),
+            Ok(binary(
+                "(-)",
+                binary("(-)", Expr::Int(1), Expr::Int(2)),
+                nested
+            ))
+        );
+    }
+
+    #[test]
+    fn non_associative() {
+        let source = "infix 4 ==\nlet x = 1 == 2 == 3\n";
+        assert_eq!(
+            expr_of(source),
+            Err(FixityError::NonAssociative("(==)".to_string()).to_string())
+        );
+    }
+
+    #[test]
+    fn mixed_associativity() {
+        let source = "infixl 5 +\ninfixr 5 ::\nlet x = 1 + 2 :: []\n";
+        assert_eq!(
+            expr_of(source),
+            Err(FixityError::Ambiguous("(+)".to_string(), "(::)".to_string()).to_string())
+        );
+    }
+}
@@ -0,0 +1,294 @@
+/// A cycle collector for environments. A function captures the Env it is
+/// defined in, and a recursive function is bound in that same Env, which makes
+/// a cycle of `Rc`s that is never freed. As values are immutable, every cycle
+/// goes through a function and the Env it captures, so these Envs are tracked
+/// for the collector to find the ones only kept alive by cycles, and to empty
+/// them.
+///
+/// It works by trial deletion: the references between the objects reachable
+/// from the tracked Envs are counted, and an object with more references than
+/// that is also referenced from elsewhere, by the host or the code running.
+/// Such objects are alive along with everything they reach, and the Envs left
+/// are garbage.
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use crate::code::{Env, WoEnv};
+use crate::tree::{Node, Tree};
+use crate::value::{List, Value, WoValue};
+
+thread_local! {
+    static ENVS: RefCell<Vec<Weak<RefCell<Env>>>> = const { RefCell::new(Vec::new()) };
+    // How many Envs may be tracked before the next collection.
+    static THRESHOLD: Cell<usize> = const { Cell::new(MIN_THRESHOLD) };
+}
+
+const MIN_THRESHOLD: usize = 4096;
+
+/// How many objects are alive, to look for leaks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Census {
+    pub envs: usize,
+    pub values: usize,
+}
+
+/// Tracks `env`, which a function captures. Cycles are collected once there
+/// are twice as many Envs tracked as there were left after the last time.
+pub fn track(env: &WoEnv) {
+    let tracked = ENVS.with(|envs| {
+        let mut envs = envs.borrow_mut();
+        envs.push(Rc::downgrade(env));
+        envs.len()
+    });
+    if tracked > THRESHOLD.with(Cell::get) {
+        collect();
+    }
+}
+
+/// Frees the Envs only kept alive by cycles, and returns how many there were.
+pub fn collect() -> usize {
+    let heap = Heap::scan();
+    let garbage = heap.garbage();
+    // The Envs are all emptied before anything is dropped, so that dropping
+    // one doesn't recurse into the others.
+    let contents = garbage
+        .iter()
+        .map(|env| mem::take(&mut *env.borrow_mut()))
+        .collect::<Vec<_>>();
+    drop(contents);
+    drop(heap);
+    let live = ENVS.with(|envs| {
+        let mut envs = envs.borrow_mut();
+        // An Env captured by several functions is tracked as many times.
+        envs.retain(|env| env.strong_count() > 0);
+        envs.sort_by_key(Weak::as_ptr);
+        envs.dedup_by(|a, b| a.ptr_eq(b));
+        envs.len()
+    });
+    THRESHOLD.with(|t| t.set((2 * live).max(MIN_THRESHOLD)));
+    garbage.len()
+}
+
+/// Counts the Envs alive, garbage included, and the values they reach.
+pub fn census() -> Census {
+    let heap = Heap::scan();
+    let count = |f: fn(&Object) -> bool| heap.objects.iter().filter(|o| f(o)).count();
+    Census {
+        envs: count(|o| matches!(o, Object::Env(_))),
+        values: count(|o| matches!(o, Object::Value(_))),
+    }
+}
+
+// Everything that is shared by way of an `Rc` and may lead to an Env.
+enum Object {
+    Env(WoEnv),
+    Value(WoValue),
+    Array(Rc<[WoValue]>),
+    Node(Rc<Node>),
+}
+
+impl Object {
+    fn address(&self) -> *const () {
+        match self {
+            Object::Env(env) => Rc::as_ptr(env) as *const (),
+            Object::Value(value) => Rc::as_ptr(value) as *const (),
+            Object::Array(elems) => Rc::as_ptr(elems) as *const (),
+            Object::Node(node) => Rc::as_ptr(node) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Env(env) => Rc::strong_count(env),
+            Object::Value(value) => Rc::strong_count(value),
+            Object::Array(elems) => Rc::strong_count(elems),
+            Object::Node(node) => Rc::strong_count(node),
+        }
+    }
+
+    /// One object for every reference this one holds, or `None` if it is
+    /// being modified and can't be looked at.
+    fn references(&self) -> Option<Vec<Object>> {
+        let mut refs = Vec::new();
+        match self {
+            Object::Env(env) => {
+                let env = env.try_borrow().ok()?;
+                let values = env.names.values().chain(env.vars.values());
+                refs.extend(values.map(|value| Object::Value(value.clone())));
+                refs.extend(env.outer.clone().map(Object::Env));
+            }
+            Object::Value(value) => match &*value.try_borrow().ok()? {
+                Value::List(list) => {
+                    let mut list = list;
+                    while let List::Cons(head, tail) = list {
+                        refs.push(Object::Value(head.clone()));
+                        list = tail;
+                    }
+                }
+                Value::Lambda { closure, .. } => refs.push(Object::Env(closure.clone())),
+                Value::Operation { default, .. } => {
+                    refs.extend(default.clone().map(Object::Value))
+                }
+                Value::Data { fields, .. } | Value::Constructor { fields, .. } => {
+                    refs.extend(fields.iter().map(|field| Object::Value(field.clone())))
+                }
+                Value::Array(elems) => refs.push(Object::Array(elems.clone())),
+                Value::Map(tree) | Value::Set(tree) => refs.extend(root(tree)),
+                Value::Void
+                | Value::Int(_)
+                | Value::Bool(_)
+                | Value::Char(_)
+                | Value::Continuation(_) => {}
+            },
+            Object::Array(elems) => {
+                refs.extend(elems.iter().map(|elem| Object::Value(elem.clone())))
+            }
+            Object::Node(node) => {
+                let (key, value, left, right) = node.parts();
+                refs.push(Object::Value(key.clone()));
+                refs.push(Object::Value(value.clone()));
+                refs.extend(root(left));
+                refs.extend(root(right));
+            }
+        }
+        Some(refs)
+    }
+}
+
+fn root(tree: &Tree) -> Option<Object> {
+    tree.root().cloned().map(Object::Node)
+}
+
+/// The objects reachable from the tracked Envs, each of them held once,
+/// and the references between them.
+#[derive(Default)]
+struct Heap {
+    objects: Vec<Object>,
+    indices: HashMap<*const (), usize>,
+    references: Vec<Vec<usize>>,
+    // The objects that couldn't be looked at, which are assumed alive.
+    opaque: Vec<usize>,
+}
+
+impl Heap {
+    fn scan() -> Heap {
+        let envs = ENVS.with(|envs| {
+            let envs = envs.borrow();
+            envs.iter().filter_map(Weak::upgrade).collect::<Vec<_>>()
+        });
+        let mut heap = Heap::default();
+        let mut stack = Vec::new();
+        for env in envs {
+            heap.add(Object::Env(env), &mut stack);
+        }
+        while let Some(index) = stack.pop() {
+            let refs = match heap.objects[index].references() {
+                Some(refs) => refs,
+                None => {
+                    heap.opaque.push(index);
+                    continue;
+                }
+            };
+            for object in refs {
+                let child = heap.add(object, &mut stack);
+                heap.references[index].push(child);
+            }
+        }
+        heap
+    }
+
+    /// The index of `object`, which is pushed on `stack` to be scanned
+    /// if it is new. Each object must only be scanned once, or the
+    /// references it holds would be counted twice.
+    fn add(&mut self, object: Object, stack: &mut Vec<usize>) -> usize {
+        if let Some(&index) = self.indices.get(&object.address()) {
+            return index;
+        }
+        let index = self.objects.len();
+        self.indices.insert(object.address(), index);
+        self.objects.push(object);
+        self.references.push(Vec::new());
+        stack.push(index);
+        index
+    }
+
+    /// The Envs that are only referenced by the heap itself, and not alive.
+    fn garbage(&self) -> Vec<WoEnv> {
+        let mut internal = vec![0; self.objects.len()];
+        for refs in &self.references {
+            for &index in refs {
+                internal[index] += 1;
+            }
+        }
+        // The heap holds one more reference to everything.
+        let mut stack = (0..self.objects.len())
+            .filter(|&i| self.objects[i].strong_count() > internal[i] + 1)
+            .chain(self.opaque.iter().copied())
+            .collect::<Vec<_>>();
+        let mut alive = vec![false; self.objects.len()];
+        while let Some(index) = stack.pop() {
+            if !alive[index] {
+                alive[index] = true;
+                stack.extend(&self.references[index]);
+            }
+        }
+        self.objects
+            .iter()
+            .zip(alive)
+            .filter_map(|(object, alive)| match object {
+                Object::Env(env) if !alive => Some(env.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::apply;
+    use crate::convert::IntoValue;
+    use crate::interpreter::Interpreter;
+
+    const COUNTDOWN: &str = "\
+let countdown = |n| do
+    let go = |k| if k == 0 then 0 else go (k - 1) end
+    go n
+end
+";
+
+    #[test]
+    fn recursive_closures() {
+        let mut chimera = Interpreter::new().unwrap();
+        chimera.load(COUNTDOWN).unwrap();
+        collect();
+        // Every call leaves behind the Env where `go` is defined.
+        chimera.call::<i64>("countdown", vec![3.into_value()]).unwrap();
+        assert_eq!(collect(), 1);
+        let before = census();
+        for _ in 0..10_000 {
+            chimera.call::<i64>("countdown", vec![3.into_value()]).unwrap();
+        }
+        // Some of them were collected along the way.
+        assert!(census().envs < before.envs + 10_000);
+        collect();
+        assert_eq!(census(), before);
+    }
+
+    #[test]
+    fn referenced_cycles() {
+        let mut chimera = Interpreter::new().unwrap();
+        collect();
+        // The host holds on to `go`, and the Env it is defined in.
+        let go = chimera
+            .eval::<WoValue>("let go = |k| if k == 0 then 0 else go (k - 1) end\ngo")
+            .unwrap();
+        assert_eq!(collect(), 0);
+        let zero = apply(&go, Value::Int(3).into()).unwrap();
+        assert_eq!(*zero.borrow(), Value::Int(0));
+        drop(go);
+        assert_eq!(collect(), 1);
+    }
+}
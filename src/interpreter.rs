@@ -0,0 +1,682 @@
+/// Running Chimera from Rust. An `Interpreter` holds the names defined by the
+/// sources it has loaded, which are type-checked against everything loaded
+/// before them, and the host can then read or call those names. It can
+/// extend the language with native functions. Every interpreter has its own
+/// limits, arguments and random number generator, but the rest of the state
+/// of the runtime, such as the natives or the handlers of effects, is shared
+/// by the interpreters of a thread.
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+
+use polytype::TypeSchema;
+
+use crate::ast::{Expr, Item, ItemKind};
+use crate::attribute;
+use crate::code::{Code, Env, Unwind, WoEnv};
+use crate::compiler;
+use crate::convert::FromValue;
+use crate::error::Error;
+use crate::fixity::Fixities;
+use crate::gc::{self, Census};
+use crate::kind::Kinds;
+use crate::parser::{parse, parse_body, parse_file, Sources};
+use crate::registry;
+use crate::row;
+use crate::sandbox::{self, Limits};
+use crate::typechecker::Lexicon;
+use crate::value::{Value, WoValue};
+
+/// The core library, which `Interpreter::new` loads.
+pub const PRELUDE: &str = include_str!("../core.chi");
+
+pub struct Interpreter {
+    env: WoEnv,
+    declarations: Declarations,
+    // Every item loaded so far, for `check`.
+    program: Vec<Item>,
+    // What the code this interpreter runs is given, which is installed in
+    // the runtime of the thread for every run.
+    limits: Limits,
+    args: Vec<String>,
+    // The state of the random number generator, kept between runs.
+    seed: Cell<u64>,
+}
+
+impl Interpreter {
+    /// An interpreter with the core library loaded.
+    pub fn new() -> Result<Self, Error> {
+        let mut interpreter = Interpreter::empty();
+        interpreter.load(PRELUDE)?;
+        Ok(interpreter)
+    }
+
+    /// An interpreter where nothing is defined, not even the core library.
+    pub fn empty() -> Self {
+        Interpreter {
+            env: Env::new(None),
+            declarations: Declarations::default(),
+            program: Vec::new(),
+            limits: Limits::default(),
+            args: Vec::new(),
+            seed: Cell::new(attribute::clock_seed()),
+        }
+    }
+
+    /// Sets the arguments the program gets from `args`.
+    pub fn set_args(&mut self, args: Vec<String>) {
+        self.args = args;
+    }
+
+    /// Seeds the random number generator, so that runs are reproducible.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed.set(seed);
+    }
+
+    /// Limits the resources the code run from now on may use, which is
+    /// how untrusted code is sandboxed. The limits apply to every call of
+    /// `load`, `eval`, `call` or `run_main` separately.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Frees the environments of functions that are only kept alive by
+    /// referencing each other, and returns how many there were. This happens
+    /// on its own as the program runs, but can be forced at any time.
+    pub fn collect(&self) -> usize {
+        gc::collect()
+    }
+
+    /// Counts the environments alive and the values they hold, which helps
+    /// finding leaks.
+    pub fn census(&self) -> Census {
+        gc::census()
+    }
+
+    /// Runs the items of `source`, which may use everything loaded so far.
+    /// Nothing runs unless they are well-typed.
+    pub fn load(&mut self, source: &str) -> Result<(), Error> {
+        let mut declarations = self.declarations.clone();
+        let items = declarations.parse(source)?;
+        declarations.check_types(&items, &Sources::single(source))?;
+        self.commit(declarations, items)
+    }
+
+    /// Runs the items of every source file in order, as if they were a
+    /// single program. Nothing runs unless it is well-typed.
+    pub fn load_files<P: AsRef<Path>>(
+        &mut self,
+        paths: impl IntoIterator<Item = P>,
+    ) -> Result<(), Error> {
+        let mut declarations = self.declarations.clone();
+        let (items, sources) = declarations.parse_files(paths)?;
+        declarations.check_types(&items, &sources)?;
+        self.commit(declarations, items)
+    }
+
+    /// Defines `name` as the native function `native`, whose type is given
+    /// by the annotation `ann` as if it were declared in Chimera with
+    /// `@[intrinsic(name)] let name : ann = ...`. It takes as many arguments
+    /// as its type says, and is given them all at once. The errors it returns
+    /// are raised as exceptions, except for `Error::Exit` which exits. As the
+    /// natives are shared by the interpreters of a thread, `name` must not be
+    /// the name of another native, builtins included.
+    pub fn register(
+        &mut self,
+        name: &str,
+        ann: &str,
+        native: impl Fn(&[WoValue]) -> Result<Value, Error> + 'static,
+    ) -> Result<(), Error> {
+        let declaration = format!("@[intrinsic({})]\nlet {} : {}\n    = ...\n", name, name, ann);
+        let mut declarations = self.declarations.clone();
+        let items = declarations.parse(&declaration)?;
+        let arity = match &items[..] {
+            [Item {
+                kind: ItemKind::Definition { ann: Some(ts), .. },
+                ..
+            }] => arity(ts),
+            _ => unreachable!(),
+        };
+        // The native is only defined once its type is known to be well-formed.
+        declarations.check_types(&items, &Sources::single(&declaration))?;
+        registry::define(name, arity, move |args| match native(args) {
+            Ok(value) => Ok(value.into()),
+            Err(error) => Err(error.into()),
+        })?;
+        self.commit(declarations, items)
+    }
+
+    /// Type-checks everything loaded so far, natives included.
+    pub fn check(&self) -> Result<(), Error> {
+        Lexicon::default().check_items(&self.program)?;
+        Ok(())
+    }
+
+    /// Evaluates the expression `expr`, without defining anything. It may be
+    /// preceded by statements, as the body of a `do` block, and isn't
+    /// evaluated unless it is well-typed.
+    pub fn eval<T: FromValue>(&mut self, expr: &str) -> Result<T, Error> {
+        // The expression is wrapped in a definition, which is run in an Env
+        // of its own so that it doesn't leak.
+        let source = format!("{}\n", expr.trim());
+        let body = parse_body(&source)?;
+        let mut items = vec![Item {
+            doc: None,
+            attr: None,
+            kind: ItemKind::Definition {
+                name: "it".to_string(),
+                ann: None,
+                expr: Expr::Block { body },
+            },
+        }];
+        // Nor do the fixities or types it declares.
+        let mut declarations = self.declarations.clone();
+        declarations.fixities.declare(&items)?;
+        declarations.fixities.resolve(&mut items)?;
+        declarations.check_kinds(&items)?;
+        declarations.check_types(&items, &Sources::single(&source))?;
+        let env = Env::new(Some(self.env.clone()));
+        self.execute(items, env.clone())?;
+        let value = env.borrow().names["it"].clone();
+        T::from_value(&value)
+    }
+
+    /// The value of `name`.
+    pub fn get<T: FromValue>(&self, name: &str) -> Result<T, Error> {
+        T::from_value(&self.lookup(name)?)
+    }
+
+    /// Applies the function `name` to `args`, one after the other.
+    pub fn call<T: FromValue>(&self, name: &str, args: Vec<Value>) -> Result<T, Error> {
+        let mut value = self.lookup(name)?;
+        self.run(|| {
+            for arg in args {
+                value = compiler::apply(&value, arg.into())?;
+            }
+            Ok(())
+        })?;
+        T::from_value(&value)
+    }
+
+    /// Calls `main` with `()` if it is a function, and returns the exit status
+    /// of the program: `main` itself or what it returns, if it's an `Int`,
+    /// which must then be between 0 and 255.
+    pub fn run_main(&self) -> Result<i32, Error> {
+        let main = match self.env.borrow().names.get("main") {
+            None => return Ok(0),
+            Some(main) => main.clone(),
+        };
+        let is_function = matches!(*main.borrow(), Value::Lambda { .. });
+        let status = if is_function {
+            self.run(|| compiler::apply(&main, Value::Void.into()))?
+        } else {
+            main
+        };
+        let code = match *status.borrow() {
+            Value::Int(code) => attribute::exit_status(code)?,
+            _ => 0,
+        };
+        Ok(code)
+    }
+
+    fn lookup(&self, name: &str) -> Result<WoValue, Error> {
+        let env = self.env.borrow();
+        env.names
+            .get(name)
+            .or_else(|| env.vars.get(name))
+            .cloned()
+            .ok_or_else(|| Error::Undefined(name.to_string()))
+    }
+
+    /// Runs `items`, then keeps the declarations they were checked with,
+    /// so that nothing is declared by what fails to load.
+    fn commit(&mut self, declarations: Declarations, items: Vec<Item>) -> Result<(), Error> {
+        self.execute(items.clone(), self.env.clone())?;
+        self.declarations = declarations;
+        self.program.extend(items);
+        Ok(())
+    }
+
+    fn execute(&self, items: Vec<Item>, env: WoEnv) -> Result<(), Error> {
+        self.run(|| {
+            for item in items {
+                item.compile().execute(env.clone())?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Runs `code` with the limits, arguments and random number generator
+    /// of this interpreter.
+    fn run<T>(&self, code: impl FnOnce() -> Result<T, Unwind>) -> Result<T, Error> {
+        sandbox::set_limits(self.limits);
+        sandbox::start();
+        attribute::set_args(self.args.clone());
+        attribute::set_seed(self.seed.get());
+        let result = code();
+        self.seed.set(attribute::seed());
+        Ok(result?)
+    }
+}
+
+/// What the items loaded so far declare, which what is loaded next is
+/// parsed and checked against. It is extended on a copy, which is only kept
+/// once what it was extended with has loaded.
+#[derive(Clone, Default)]
+struct Declarations {
+    fixities: Fixities,
+    kinds: Kinds,
+    // The types of everything loaded so far.
+    lexicon: Lexicon<'static>,
+}
+
+impl Declarations {
+    fn parse(&mut self, source: &str) -> Result<Vec<Item>, Error> {
+        let items = parse(source, &mut self.fixities)?;
+        self.check_kinds(&items)?;
+        Ok(items)
+    }
+
+    fn parse_files<P: AsRef<Path>>(
+        &mut self,
+        paths: impl IntoIterator<Item = P>,
+    ) -> Result<(Vec<Item>, Sources), Error> {
+        let mut program = Vec::new();
+        let mut sources = Sources::default();
+        for path in paths {
+            let file = path.as_ref().display().to_string();
+            let source = fs::read_to_string(&path).map_err(|e| Error::Io(file.clone(), e))?;
+            let number = sources.add(Some(file.clone()), source.clone());
+            let items =
+                parse_file(&source, number, &mut self.fixities).map_err(|error| Error::Source {
+                    file,
+                    error: Box::new(error),
+                })?;
+            program.extend(items);
+        }
+        self.check_kinds(&program)?;
+        Ok((program, sources))
+    }
+
+    /// Type-checks `items` as the continuation of everything loaded so far,
+    /// which is then extended with what they define. Their type errors are
+    /// rendered against `sources`, which they were parsed from.
+    fn check_types(&mut self, items: &[Item], sources: &Sources) -> Result<(), Error> {
+        let lexicon = self.lexicon.clone();
+        lexicon.check_items(items).map_err(|error| sources.type_error(error))?;
+        self.lexicon = lexicon;
+        Ok(())
+    }
+
+    /// Checks that the types `items` mention are well-formed.
+    fn check_kinds(&mut self, items: &[Item]) -> Result<(), Error> {
+        self.kinds.declare(items);
+        let diagnostics = self.kinds.check(items);
+        if diagnostics.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Kind(diagnostics))
+        }
+    }
+}
+
+/// Parses every source file in order, as if they were a single program,
+/// then checks that the types it mentions are well-formed. The sources are
+/// returned along with it, to render its type errors with.
+pub fn parse_files<P: AsRef<Path>>(
+    paths: impl IntoIterator<Item = P>,
+) -> Result<(Vec<Item>, Sources), Error> {
+    Declarations::default().parse_files(paths)
+}
+
+/// The number of arguments a function of type `ts` takes.
+fn arity(ts: &TypeSchema) -> usize {
+    let mut t = match ts {
+        TypeSchema::Monotype(t) => t,
+        TypeSchema::Polytype { body, .. } => return arity(body),
+    };
+    let mut arity = 0;
+    while let Some((_, result, _)) = row::as_arrow(t) {
+        arity += 1;
+        t = result;
+    }
+    arity
+}
+
+impl From<Error> for Unwind {
+    fn from(error: Error) -> Self {
+        let message = match error {
+            Error::Exit(code) => return Unwind::Exit { code },
+            Error::Limit(limit) => return Unwind::Halt { limit },
+            Error::Exception(message) => message,
+            error => error.to_string(),
+        };
+        Unwind::Raise {
+            exception: attribute::failure(&message),
+        }
+    }
+}
+
+impl From<Unwind> for Error {
+    fn from(unwind: Unwind) -> Self {
+        match unwind {
+            Unwind::Raise { exception } => Error::Exception(attribute::message(&exception)),
+            Unwind::Exit { code } => Error::Exit(code),
+            Unwind::Halt { limit } => Error::Limit(limit),
+            unwind => Error::Handler(unwind.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::sandbox::DEFAULT_STACK;
+
+    use crate::convert::IntoValue;
+    use crate::error::Limit;
+
+    #[test]
+    fn load_and_call() {
+        let mut chimera = Interpreter::new().unwrap();
+        chimera.load("let double = |x| x * 2\nlet greeting = \"hi\"\n").unwrap();
+        assert_eq!(chimera.call::<i64>("double", vec![21.into_value()]).unwrap(), 42);
+        assert_eq!(chimera.get::<String>("greeting").unwrap(), "hi");
+        let args = vec![vec![1, 2].into_value(), vec![3].into_value()];
+        assert_eq!(chimera.call::<Vec<i64>>("(^)", args).unwrap(), vec![1, 2, 3]);
+        assert!(matches!(chimera.get::<i64>("nope"), Err(Error::Undefined(_))));
+    }
+
+    #[test]
+    fn prelude() {
+        let chimera = Interpreter::new().unwrap();
+        chimera.call::<()>("tests", vec![Value::Void]).unwrap();
+    }
+
+    #[test]
+    fn eval() {
+        let mut chimera = Interpreter::new().unwrap();
+        assert_eq!(chimera.eval::<Vec<i64>>("map (+ 1) [1, 2]").unwrap(), vec![2, 3]);
+        assert_eq!(chimera.eval::<Option<i64>>("parse_int \"12\"").unwrap(), Some(12));
+        // What the expression defines doesn't leak.
+        chimera.eval::<()>("let x = 1\n()").unwrap();
+        assert!(matches!(chimera.get::<i64>("x"), Err(Error::Undefined(_))));
+    }
+
+    #[test]
+    fn reload() {
+        let mut chimera = Interpreter::new().unwrap();
+        // What fails to load declares nothing, so it can be loaded again once fixed.
+        let source = "infixl 6 <+>\nlet (<+>) = |x, y| x + y\n\
+                      data Pair\n    Pair { left: Int, right: Int },\nend\nlet p = ";
+        let failed = chimera.load(&format!("{}1 <+> true\n", source));
+        assert!(matches!(failed, Err(Error::Type { .. })));
+        chimera.load(&format!("{}Pair (1 <+> 2) 3\n", source)).unwrap();
+        // Neither does what is evaluated.
+        let local = "infixl 6 <->\nlet (<->) = |x, y| x - y\n3 <-> 1";
+        assert_eq!(chimera.eval::<i64>(local).unwrap(), 2);
+        assert_eq!(chimera.eval::<i64>(local).unwrap(), 2);
+    }
+
+    #[test]
+    fn program() {
+        let mut chimera = Interpreter::new().unwrap();
+        assert_eq!(chimera.run_main().unwrap(), 0);
+        chimera.set_args(vec!["-v".to_string(), "file.txt".to_string()]);
+        assert_eq!(chimera.eval::<Vec<String>>("args ()").unwrap(), vec!["-v", "file.txt"]);
+        chimera.load("let main = |_| len (args ())\n").unwrap();
+        assert_eq!(chimera.run_main().unwrap(), 2);
+        let mut chimera = Interpreter::new().unwrap();
+        chimera.load("let main = |_| print \"\"\n").unwrap();
+        assert_eq!(chimera.run_main().unwrap(), 0);
+        // Exiting skips the rest of the program, whatever handles it.
+        chimera
+            .load("let main = |_| do\n    try exit 5 catch _ -> () end\n    1\nend\n")
+            .unwrap();
+        assert!(matches!(chimera.run_main(), Err(Error::Exit(5))));
+    }
+
+    #[test]
+    fn natives() {
+        let mut chimera = Interpreter::new().unwrap();
+        chimera
+            .register("repeat_string", "Int -> List Char -> List Char", |args| {
+                let n = i64::from_value(&args[0])?;
+                let s = String::from_value(&args[1])?;
+                Ok(s.repeat(n.max(0) as usize).into_value())
+            })
+            .unwrap();
+        chimera
+            .register("checked", "Int -> Int", |args| match i64::from_value(&args[0])? {
+                n if n < 0 => Err(Error::Exception("negative".to_string())),
+                n => Ok(Value::Int(n)),
+            })
+            .unwrap();
+        chimera.load("let twice = repeat_string 2\n").unwrap();
+        assert_eq!(chimera.call::<String>("twice", vec!["ab".into_value()]).unwrap(), "abab");
+        assert_eq!(chimera.eval::<i64>("try checked (-1) catch _ -> 0 end").unwrap(), 0);
+        assert!(chimera.check().is_ok());
+        // Nothing ill-typed is loaded.
+        let wrong = chimera.load("let wrong = |_| checked \"one\"\n");
+        assert!(matches!(wrong, Err(Error::Type { .. })));
+        assert!(chimera.check().is_ok());
+        let syntax_error = chimera.register("bad", "Int ->", |_| Ok(Value::Void));
+        assert!(matches!(syntax_error, Err(Error::Syntax(_))));
+        // Natives are shared by the interpreters of a thread, so none can
+        // replace another, or a builtin.
+        let builtin = chimera.register("add", "Int -> Int -> Int", |_| Ok(Value::Int(0)));
+        assert!(matches!(builtin, Err(Error::Redefined(_))));
+        let native = chimera.register("checked", "Int -> Int", |_| Ok(Value::Int(0)));
+        assert!(matches!(native, Err(Error::Redefined(_))));
+        let mut fresh = Interpreter::new().unwrap();
+        assert_eq!(fresh.eval::<i64>("1 + 2").unwrap(), 3);
+        assert_eq!(chimera.eval::<i64>("checked 5").unwrap(), 5);
+        // A native whose type is ill-formed isn't defined.
+        let ill_formed = chimera.register("unknown", "Foo -> Int", |_| Ok(Value::Int(0)));
+        assert!(matches!(ill_formed, Err(Error::Kind(_))));
+        chimera.register("unknown", "Int -> Int", |_| Ok(Value::Int(1))).unwrap();
+    }
+
+    #[test]
+    fn limits() {
+        let mut chimera = Interpreter::new().unwrap();
+        chimera
+            .load(
+                "let count = |n| if n == 0 then 0 else 1 + count (n - 1) end\n\
+                 let spin = |n| if n == 0 then () else do\n\
+                 \x20   spin (n - 1)\n\
+                 \x20   spin (n - 1)\n\
+                 end end\n",
+            )
+            .unwrap();
+        let limit = |result: Result<i64, Error>| match result {
+            Err(Error::Limit(limit)) => limit,
+            result => panic!("expected to go over a limit, got {:?}", result),
+        };
+        chimera.set_limits(Limits {
+            depth: 50,
+            ..Limits::default()
+        });
+        assert_eq!(chimera.eval::<i64>("count 40").unwrap(), 40);
+        // Going over a limit can't be caught.
+        let result = chimera.eval("try count 1000 catch _ -> 0 end");
+        assert_eq!(limit(result), Limit::Depth(50));
+        chimera.set_limits(Limits {
+            fuel: Some(1000),
+            ..Limits::default()
+        });
+        assert_eq!(limit(chimera.eval("spin 20\n0")), Limit::Fuel(1000));
+        // Every run gets the whole fuel.
+        assert_eq!(chimera.call::<i64>("count", vec![Value::Int(50)]).unwrap(), 50);
+        chimera.set_limits(Limits {
+            values: Some(1000),
+            ..Limits::default()
+        });
+        assert_eq!(limit(chimera.eval("foldl (+) 0 (0..1000)")), Limit::Values(1000));
+        let timeout = Duration::from_millis(20);
+        chimera.set_limits(Limits {
+            timeout: Some(timeout),
+            ..Limits::default()
+        });
+        assert_eq!(limit(chimera.eval("spin 30\n0")), Limit::Timeout(timeout));
+        assert_eq!(limit(chimera.eval("sleep 10000\n0")), Limit::Timeout(timeout));
+        chimera.set_limits(Limits {
+            io: false,
+            ..Limits::default()
+        });
+        let result = chimera.eval("match get_env \"HOME\" with\nSome _ -> 1\nNone -> 0\nend");
+        assert_eq!(limit(result), Limit::Denied("get_env".to_string()));
+        // Printing is still allowed.
+        chimera.eval::<()>("print \"\"").unwrap();
+    }
+
+    #[test]
+    fn untrusted_source() {
+        // What a sandboxed program is made of can't take down its host.
+        let mut chimera = Interpreter::new().unwrap();
+        let overflow = chimera.load("let x = 99999999999999999999\n");
+        assert!(matches!(overflow, Err(Error::Syntax(_))));
+        assert!(matches!(chimera.get::<i64>("x"), Err(Error::Undefined(_))));
+    }
+
+    #[test]
+    fn own_limits() {
+        let mut limited = Interpreter::new().unwrap();
+        limited.set_limits(Limits {
+            fuel: Some(10),
+            ..Limits::default()
+        });
+        let mut chimera = Interpreter::new().unwrap();
+        let sum = "foldl (+) 0 (1..100)";
+        assert!(matches!(limited.eval::<i64>(sum), Err(Error::Limit(Limit::Fuel(10)))));
+        assert_eq!(chimera.eval::<i64>(sum).unwrap(), 4950);
+        // Every interpreter draws from a generator of its own.
+        limited.set_limits(Limits::default());
+        limited.set_seed(7);
+        chimera.set_seed(7);
+        let draw = "random_int 0 1000000";
+        let first = limited.eval::<i64>(draw).unwrap();
+        assert_eq!(chimera.eval::<i64>(draw).unwrap(), first);
+        let second = limited.eval::<i64>(draw).unwrap();
+        assert_eq!(chimera.eval::<i64>(draw).unwrap(), second);
+    }
+
+    #[test]
+    fn deep_recursion() {
+        // The default limits fit in the stack of a thread spawned as usual,
+        // however the interpreter was built.
+        let halted = thread::spawn(|| {
+            let mut chimera = Interpreter::new().unwrap();
+            chimera
+                .load("let deep = |n| if n == 0 then 0 else 1 + deep (n - 1) end\n")
+                .unwrap();
+            chimera.eval::<i64>("deep 90000").unwrap_err()
+        });
+        let error = halted.join().unwrap();
+        assert!(matches!(error, Error::Limit(Limit::Stack(DEFAULT_STACK))));
+    }
+
+    #[test]
+    fn continuations() {
+        let mut chimera = Interpreter::new().unwrap();
+        chimera
+            .load(
+                "effect Yield\n\
+                 \x20   yield : Int -> Void\n\
+                 end\n\
+                 let collect = |f| handle do\n\
+                 \x20   f ()\n\
+                 \x20   []\n\
+                 end with\n\
+                 \x20   yield x resume -> x :: resume ()\n\
+                 end\n",
+            )
+            .unwrap();
+        let counted = "handle do\nprint 1\nprint 2\n0\nend with\n\
+                       print _ k -> 1 + k ()\nflush _ k -> k ()\nend";
+        assert_eq!(chimera.eval::<i64>(counted).unwrap(), 2);
+        let generator = "collect (|_| do\nyield 1\nmap yield [2, 3]\nend)";
+        assert_eq!(chimera.eval::<Vec<i64>>(generator).unwrap(), vec![1, 2, 3]);
+        // Operations of outer handlers are resumed under the inner ones.
+        let nested = "collect (|_| handle do\nyield 1\nprint 5\nyield 2\nend with\n\
+                      print _ k -> do\nyield 9\nk ()\nend\nflush _ k -> k ()\nend)";
+        assert_eq!(chimera.eval::<Vec<i64>>(nested).unwrap(), vec![1, 9, 2]);
+        // A continuation can only be resumed once.
+        let twice = "try handle do\nprint 1\n0\nend with\nprint _ k -> do\nk ()\nk ()\nend\n\
+                     flush _ k -> k ()\nend\ncatch e -> len (message e) end";
+        assert_eq!(chimera.eval::<i64>(twice).unwrap(), 26);
+    }
+
+    #[test]
+    fn undeclared_field_variables() {
+        let mut chimera = Interpreter::new().unwrap();
+        // `a` would stand for whatever type each match wants it to be.
+        let data = "data Box\n    Box { v: a },\nend\n";
+        let uses = [
+            "let x = match Box [1] with\n    Box v -> v + 1\nend\n",
+            "let x = match Box true with\n    Box v -> head v\nend\n",
+        ];
+        for source in uses {
+            let loaded = chimera.load(&format!("{}{}", data, source));
+            assert!(matches!(loaded, Err(Error::Kind(_))), "{:?}", loaded);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let mut chimera = Interpreter::new().unwrap();
+        assert!(matches!(chimera.load("let x = (\n"), Err(Error::Syntax(_))));
+        assert!(matches!(chimera.load("let x : Foo = 1\n"), Err(Error::Kind(_))));
+        // Nothing runs unless it is well-typed.
+        assert!(matches!(chimera.eval::<i64>("nope + 1"), Err(Error::Type { .. })));
+        assert!(matches!(chimera.eval::<i64>("1 + true"), Err(Error::Type { .. })));
+        assert!(matches!(chimera.load("let y = 1 + true\n"), Err(Error::Type { .. })));
+        assert!(matches!(chimera.get::<i64>("y"), Err(Error::Undefined(_))));
+        // Type errors are shown along with where they are found.
+        match chimera.eval::<i64>("let x = 1\nx + true") {
+            Err(error @ Error::Type { snippet: Some(_), .. }) => {
+                let shown = error.to_string();
+                assert!(shown.contains("expected `Int` but found `Bool`"), "{}", shown);
+                assert!(shown.contains("x + true"), "{}", shown);
+            }
+            result => panic!("{:?}", result),
+        }
+        match chimera.eval::<i64>("let x = 1\nx + nope") {
+            Err(error @ Error::Type { snippet: Some(_), .. }) => {
+                let shown = error.to_string();
+                assert!(shown.contains("the name `nope` is not in scope"), "{}", shown);
+                assert!(shown.contains("x + nope"), "{}", shown);
+            }
+            result => panic!("{:?}", result),
+        }
+        assert_eq!(
+            chimera.eval::<i64>("1 / 0").unwrap_err().to_string(),
+            "uncaught exception: division by zero"
+        );
+        assert_eq!(chimera.eval::<i64>("try ... catch _ -> 1 end").unwrap(), 1);
+        chimera.load("effect Ask\n    ask : Void -> Int\nend\n").unwrap();
+        assert_eq!(
+            chimera.eval::<i64>("ask ()").unwrap_err().to_string(),
+            "uncaught exception: unhandled effect operation `ask`"
+        );
+        assert!(matches!(chimera.eval::<i64>("exit 3"), Err(Error::Exit(3))));
+        assert_eq!(
+            chimera.eval::<i64>("exit 4294967296").unwrap_err().to_string(),
+            "uncaught exception: the exit status 4294967296 is not between 0 and 255"
+        );
+        // What is evaluated can't close its block to define names.
+        let injected = "1\nend\nlet z = 2\nlet it = do\n3";
+        assert!(matches!(chimera.eval::<i64>(injected), Err(Error::Syntax(_))));
+        assert!(matches!(chimera.get::<i64>("z"), Err(Error::Undefined(_))));
+        chimera.load("let p = 1\n").unwrap();
+        assert!(matches!(chimera.eval::<i64>("p.name"), Err(Error::Type { .. })));
+        assert!(matches!(chimera.eval::<()>("p.name = 2"), Err(Error::Type { .. })));
+        chimera.load("let main = 256\n").unwrap();
+        assert!(matches!(chimera.run_main(), Err(Error::Exception(_))));
+        assert!(matches!(chimera.eval::<bool>("1"), Err(Error::Conversion { .. })));
+        assert!(matches!(chimera.load_files(["missing.chi"]), Err(Error::Io(..))));
+    }
+}
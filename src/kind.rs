@@ -0,0 +1,404 @@
+/// The kind checker, which runs once the whole program is parsed.
+/// Type constructors are only ever applied to types, so their kind
+/// amounts to their arity, which every use of them must agree with.
+/// The same goes for effects, which only ever appear in effect rows.
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use polytype::{Type, TypeSchema};
+
+use crate::ast::{Expr, Item, ItemKind, Stmt};
+use crate::error::KindError;
+use crate::row;
+
+/// The type constructors and the effects in scope along with their arity.
+#[derive(Debug, Clone)]
+pub struct Kinds {
+    types: HashMap<&'static str, usize>,
+    effects: HashMap<&'static str, usize>,
+}
+
+impl Default for Kinds {
+    fn default() -> Self {
+        let builtins = [
+            ("Void", 0),
+            ("Int", 0),
+            ("Bool", 0),
+            ("Char", 0),
+            ("List", 1),
+            ("Array", 1),
+            ("Map", 2),
+            ("Set", 1),
+            // Function types take the row of effects they perform.
+            (row::ARROW, 3),
+        ];
+        Kinds {
+            types: builtins.into_iter().collect(),
+            effects: HashMap::new(),
+        }
+    }
+}
+
+/// A kind error, along with the definition or data type it occured in.
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub item: String,
+    pub error: KindError,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "in `{}`: {}", self.item, self.error)
+    }
+}
+
+impl Kinds {
+    /// Records every `data` and `effect` declaration in `items`, including the
+    /// ones nested in modules and blocks. Much like fixities, they are global.
+    pub fn declare(&mut self, items: &[Item]) {
+        for item in items {
+            match &item.kind {
+                ItemKind::DataType { schema, .. } => {
+                    if let Type::Constructed(name, args) = body(schema) {
+                        self.types.insert(name, args.len());
+                    }
+                }
+                ItemKind::Effect { schema, .. } => {
+                    if let Type::Constructed(name, args) = body(schema) {
+                        self.effects.insert(name, args.len());
+                    }
+                }
+                ItemKind::Module { items, .. } => self.declare(items),
+                ItemKind::Definition { expr, .. } => self.declare_expr(expr),
+                ItemKind::Fixity { .. } => (),
+            }
+        }
+    }
+
+    fn declare_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Block { body } => self.declare_body(body),
+            Expr::Branch { paths } => {
+                for (cond, body) in paths {
+                    self.declare_expr(cond);
+                    self.declare_body(body);
+                }
+            }
+            Expr::Infix { head, tail } => {
+                self.declare_expr(head);
+                for (_, operand) in tail {
+                    self.declare_expr(operand);
+                }
+            }
+            Expr::List(elems) | Expr::Array(elems) => {
+                for elem in elems {
+                    self.declare_expr(elem);
+                }
+            }
+            Expr::Lambda { expr, .. }
+            | Expr::Field { expr, .. }
+            | Expr::Negate { expr }
+            | Expr::Ascribe { expr, .. }
+            | Expr::Located { expr, .. } => self.declare_expr(expr),
+            Expr::Apply { left, right } | Expr::Assign { left, right } => {
+                self.declare_expr(left);
+                self.declare_expr(right);
+            }
+            Expr::Handle { expr, clauses } => {
+                self.declare_expr(expr);
+                for clause in clauses {
+                    self.declare_expr(&clause.body);
+                }
+            }
+            Expr::Try { expr, handler, .. } => {
+                self.declare_expr(expr);
+                self.declare_expr(handler);
+            }
+            Expr::Match { expr, arms } => {
+                self.declare_expr(expr);
+                for arm in arms {
+                    self.declare_expr(&arm.body);
+                }
+            }
+            Expr::Ellipsis(_)
+            | Expr::Void
+            | Expr::Int(_)
+            | Expr::Bool(_)
+            | Expr::Char(_)
+            | Expr::Name(_) => (),
+        }
+    }
+
+    fn declare_body(&mut self, body: &[Stmt]) {
+        for stmt in body {
+            match stmt {
+                Stmt::Expr(expr) => self.declare_expr(expr),
+                Stmt::Item(item) => self.declare(std::slice::from_ref(item)),
+            }
+        }
+    }
+
+    /// Checks every type in `items`: those of annotations, ascriptions,
+    /// the fields of data types and the operations of effects.
+    pub fn check(&self, items: &[Item]) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        self.check_items(items, &mut diagnostics);
+        diagnostics
+    }
+
+    fn check_items(&self, items: &[Item], diagnostics: &mut Vec<Diagnostic>) {
+        for item in items {
+            match &item.kind {
+                ItemKind::Definition { name, ann, expr } => {
+                    if let Some(ts) = ann {
+                        self.check_type(name, body(ts), diagnostics);
+                    }
+                    self.check_expr(name, expr, diagnostics);
+                }
+                ItemKind::DataType { schema, variants } => {
+                    let name = match body(schema) {
+                        Type::Constructed(name, _) => name.to_string(),
+                        t => t.to_string(),
+                    };
+                    for (_, fields) in variants {
+                        for (_, ts) in fields {
+                            self.check_type(&name, body(ts), diagnostics);
+                        }
+                    }
+                }
+                ItemKind::Effect { operations, .. } => {
+                    for op in operations {
+                        self.check_type(&op.name, body(&op.ann), diagnostics);
+                    }
+                }
+                ItemKind::Module { items, .. } => self.check_items(items, diagnostics),
+                ItemKind::Fixity { .. } => (),
+            }
+        }
+    }
+
+    fn check_expr(&self, item: &str, expr: &Expr, diagnostics: &mut Vec<Diagnostic>) {
+        match expr {
+            Expr::Lambda { ann, expr, .. } => {
+                if let Some(t) = ann {
+                    self.check_type(item, t, diagnostics);
+                }
+                self.check_expr(item, expr, diagnostics);
+            }
+            Expr::Ascribe { expr, ann } => {
+                self.check_type(item, ann, diagnostics);
+                self.check_expr(item, expr, diagnostics);
+            }
+            Expr::Block { body } => self.check_body(item, body, diagnostics),
+            Expr::Branch { paths } => {
+                for (cond, body) in paths {
+                    self.check_expr(item, cond, diagnostics);
+                    self.check_body(item, body, diagnostics);
+                }
+            }
+            Expr::Infix { head, tail } => {
+                self.check_expr(item, head, diagnostics);
+                for (_, operand) in tail {
+                    self.check_expr(item, operand, diagnostics);
+                }
+            }
+            Expr::List(elems) | Expr::Array(elems) => {
+                for elem in elems {
+                    self.check_expr(item, elem, diagnostics);
+                }
+            }
+            Expr::Field { expr, .. } | Expr::Negate { expr } | Expr::Located { expr, .. } => {
+                self.check_expr(item, expr, diagnostics)
+            }
+            Expr::Apply { left, right } | Expr::Assign { left, right } => {
+                self.check_expr(item, left, diagnostics);
+                self.check_expr(item, right, diagnostics);
+            }
+            Expr::Handle { expr, clauses } => {
+                self.check_expr(item, expr, diagnostics);
+                for clause in clauses {
+                    self.check_expr(item, &clause.body, diagnostics);
+                }
+            }
+            Expr::Try { expr, handler, .. } => {
+                self.check_expr(item, expr, diagnostics);
+                self.check_expr(item, handler, diagnostics);
+            }
+            Expr::Match { expr, arms } => {
+                self.check_expr(item, expr, diagnostics);
+                for arm in arms {
+                    self.check_expr(item, &arm.body, diagnostics);
+                }
+            }
+            Expr::Ellipsis(_)
+            | Expr::Void
+            | Expr::Int(_)
+            | Expr::Bool(_)
+            | Expr::Char(_)
+            | Expr::Name(_) => (),
+        }
+    }
+
+    fn check_body(&self, item: &str, body: &[Stmt], diagnostics: &mut Vec<Diagnostic>) {
+        for stmt in body {
+            match stmt {
+                Stmt::Expr(expr) => self.check_expr(item, expr, diagnostics),
+                Stmt::Item(nested) => self.check_items(std::slice::from_ref(nested), diagnostics),
+            }
+        }
+    }
+
+    fn check_type(&self, item: &str, t: &Type, diagnostics: &mut Vec<Diagnostic>) {
+        match t {
+            Type::Constructed(name, args) if *name == row::EXTEND => {
+                self.check_label(item, &args[0], diagnostics);
+                self.check_type(item, &args[1], diagnostics);
+            }
+            Type::Constructed(name, args) => {
+                let error = if name.starts_with(|c: char| c.is_lowercase() || c == '_') {
+                    // The parser keeps the unbound variables of declarations
+                    // as constructors.
+                    Some(KindError::UnboundVariable(name.to_string()))
+                } else {
+                    match self.types.get(name) {
+                        None if self.effects.contains_key(name) => {
+                            Some(KindError::EffectAsType(name.to_string()))
+                        }
+                        None => Some(KindError::UnknownType(name.to_string())),
+                        Some(&arity) if arity != args.len() => {
+                            Some(KindError::Arity(name.to_string(), arity, args.len()))
+                        }
+                        Some(_) => None,
+                    }
+                };
+                if let Some(error) = error {
+                    diagnostics.push(Diagnostic {
+                        item: item.to_string(),
+                        error,
+                    });
+                }
+                for arg in args {
+                    self.check_type(item, arg, diagnostics);
+                }
+            }
+            Type::Variable(_) => (),
+        }
+    }
+
+    /// The labels of an effect row must be effects, whose arguments are types.
+    fn check_label(&self, item: &str, label: &Type, diagnostics: &mut Vec<Diagnostic>) {
+        if let Type::Constructed(name, args) = label {
+            let error = match self.effects.get(name) {
+                None => Some(KindError::UnknownEffect(name.to_string())),
+                Some(&arity) if arity != args.len() => {
+                    Some(KindError::Arity(name.to_string(), arity, args.len()))
+                }
+                Some(_) => None,
+            };
+            if let Some(error) = error {
+                diagnostics.push(Diagnostic {
+                    item: item.to_string(),
+                    error,
+                });
+            }
+            for arg in args {
+                self.check_type(item, arg, diagnostics);
+            }
+        }
+    }
+}
+
+fn body(ts: &TypeSchema) -> &Type {
+    match ts {
+        TypeSchema::Monotype(t) => t,
+        TypeSchema::Polytype { body: ts, .. } => body(ts),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixity::Fixities;
+    use crate::parser::parse;
+
+    use super::*;
+
+    fn errors_of(source: &str) -> Vec<KindError> {
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        let mut kinds = Kinds::default();
+        kinds.declare(&items);
+        kinds.check(&items).into_iter().map(|d| d.error).collect()
+    }
+
+    #[test]
+    fn builtin_types() {
+        let source = "let f : forall a. (a -> Bool) -> List a -> List (List a) = ...\n";
+        assert_eq!(errors_of(source), vec![]);
+        let source = "let g : Map Int (Set Char) -> Set Int = ...\n";
+        assert_eq!(errors_of(source), vec![]);
+    }
+
+    #[test]
+    fn misapplied_constructor() {
+        let source = "let f = |x: List Int Bool| ([] : List)\n";
+        assert_eq!(
+            errors_of(source),
+            vec![
+                KindError::Arity("List".to_string(), 1, 2),
+                KindError::Arity("List".to_string(), 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_type_and_variable() {
+        let source = "data Wrap\n    Wrap { f: Foo -> a },\nend\n";
+        assert_eq!(
+            errors_of(source),
+            vec![
+                KindError::UnknownType("Foo".to_string()),
+                KindError::UnboundVariable("a".to_string()),
+            ]
+        );
+        let source = "effect Ask\n    ask : Void -> a\nend\n";
+        assert_eq!(errors_of(source), vec![KindError::UnboundVariable("a".to_string())]);
+    }
+
+    #[test]
+    fn implicit_variable() {
+        // The variables of an annotation are implicitly quantified.
+        let source = "let f : Foo -> a = ...\n";
+        assert_eq!(errors_of(source), vec![KindError::UnknownType("Foo".to_string())]);
+        let source = "effect Ask\n    ask : forall a. Void -> a\nend\n";
+        assert_eq!(errors_of(source), vec![]);
+    }
+
+    #[test]
+    fn declared_data_type() {
+        let source = "\
+let f : Pair a Int -> a = ...
+data Pair a b
+    Pair { first: a, second: b }
+end
+";
+        assert_eq!(errors_of(source), vec![]);
+    }
+
+    #[test]
+    fn declared_effect() {
+        let source = "\
+effect State s
+    get : Void -> s
+    put : s -> Void
+end
+let incr : Void -> {State Int} Void = ...
+let wrong : Void -> {Int} State = ...
+";
+        assert_eq!(
+            errors_of(source),
+            vec![
+                KindError::EffectAsType("State".to_string()),
+                KindError::UnknownEffect("Int".to_string()),
+            ]
+        );
+    }
+}
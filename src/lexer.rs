@@ -18,6 +18,8 @@ pub enum Tok<'input> {
     StrLiteral(&'input str),
     CharLiteral(char),
 
+    DocComment(&'input str),
+
     Ellipsis,
 
     At,
@@ -29,6 +31,9 @@ pub enum Tok<'input> {
     End,
     Data,
     Forall,
+    Infixl,
+    Infixr,
+    Infix,
 
     True,
     False,
@@ -40,8 +45,17 @@ pub enum Tok<'input> {
     Loop,
     Break,
 
+    Effect,
+    Handle,
+    With,
+
+    Try,
+    Catch,
+    Match,
+
     Colon,
     Arrow,
+    Minus,
     Pipe,
     Comma,
     Equal,
@@ -65,6 +79,9 @@ pub static RESERVED_NAMES: phf::Map<&'static str, Tok> = phf::phf_map! {
     "end"       => Tok::End,
     "data"      => Tok::Data,
     "forall"    => Tok::Forall,
+    "infixl"    => Tok::Infixl,
+    "infixr"    => Tok::Infixr,
+    "infix"     => Tok::Infix,
     "true"      => Tok::True,
     "false"     => Tok::False,
     "if"        => Tok::If,
@@ -73,12 +90,19 @@ pub static RESERVED_NAMES: phf::Map<&'static str, Tok> = phf::phf_map! {
     "else"      => Tok::Else,
     "loop"      => Tok::Loop,
     "break"     => Tok::Break,
+    "effect"    => Tok::Effect,
+    "handle"    => Tok::Handle,
+    "with"      => Tok::With,
+    "try"       => Tok::Try,
+    "catch"     => Tok::Catch,
+    "match"     => Tok::Match,
 };
 
 pub static RESERVED_SYMBOLS: phf::Map<&'static str, Tok> = phf::phf_map! {
     "..." => Tok::Ellipsis,
     ":"   => Tok::Colon,
     "->"  => Tok::Arrow,
+    "-"   => Tok::Minus,
     "|"   => Tok::Pipe,
     "="   => Tok::Equal,
     "~"   => Tok::Tilde,
@@ -90,6 +114,17 @@ pub static RESERVED_SYMBOLS: phf::Map<&'static str, Tok> = phf::phf_map! {
 pub struct Lexer<'input> {
     chars: Peekable<CharIndices<'input>>,
     input: &'input str,
+    // Whether the last emitted token ended a line, this is used to avoid
+    // producing a `Newline` for lines that only contain comments.
+    at_line_start: bool,
+    // Whether the last emitted token may end an operand, after which a `-`
+    // is a subtraction rather than the sign of a literal.
+    after_operand: bool,
+    // Whether the operations of an effect are being lexed, as they can be
+    // documented like items.
+    in_effect: bool,
+    // Whether `--- ...` lines may be doc comments, see `documents`.
+    docs: bool,
 }
 
 impl<'input> Lexer<'input> {
@@ -97,6 +132,10 @@ impl<'input> Lexer<'input> {
         Lexer {
             chars: input.char_indices().peekable(),
             input,
+            at_line_start: true,
+            after_operand: false,
+            in_effect: false,
+            docs: true,
         }
     }
 
@@ -149,12 +188,20 @@ impl<'input> Lexer<'input> {
         Ok((start, token, end))
     }
 
+    /// Lexes an integer literal, whose sign is folded in when `start` points
+    /// at a `-`, so that `-9223372036854775808` is in range.
     fn integer(&mut self, start: usize) -> Spanned<'input> {
         let (end, src) = self.take_while(start, |c| c.is_numeric());
-        let int = i64::from_str(src).unwrap();
+        let int = i64::from_str(src).map_err(|_| LexicalError::IntegerOverflow(start, end))?;
         Ok((start, Tok::IntLiteral(int), end))
     }
 
+    /// Whether the `-` at `start` is the sign of an integer literal, rather
+    /// than a subtraction as in `x -1`.
+    fn signs(&self, start: usize) -> bool {
+        !self.after_operand && self.input[start + 1..].starts_with(char::is_numeric)
+    }
+
     fn string(&mut self, start: usize) -> Spanned<'input> {
         self.chars.next(); // Consume the opening double quotes.
         let (end, src) = self.take_while(start + 1, |b| b != '"');
@@ -163,41 +210,105 @@ impl<'input> Lexer<'input> {
     }
 
     fn character(&mut self, start: usize) -> Spanned<'input> {
-        self.chars.next();
-        let (_, src) = self
-            .chars
-            .next()
-            .ok_or(LexicalError::InvalidSyntax)?;
-        let token = if src == '\\' {
-            let (_, escape) = self
-                .chars
-                .next()
-                .ok_or(LexicalError::InvalidSyntax)?;
-            Tok::CharLiteral(match escape {
-                '\\' => '\\',
-                '\'' => '\'',
-                'n' => '\n',
-                'r' => '\r',
-                't' => '\t',
-                _ => return Err(LexicalError::InvalidSyntax)
-            })
-        } else {
-            Tok::CharLiteral(src)
+        self.chars.next(); // Consume the opening quote.
+        let c = match self.chars.next() {
+            Some((_, '\\')) => match self.chars.next() {
+                Some((_, '\\')) => '\\',
+                Some((_, '\'')) => '\'',
+                Some((_, 'n')) => '\n',
+                Some((_, 'r')) => '\r',
+                Some((_, 't')) => '\t',
+                _ => return Err(self.invalid_syntax(start)),
+            },
+            Some((_, c)) => c,
+            None => return Err(self.invalid_syntax(start)),
         };
-        self.chars.next();
-        Ok((start, token, start + 3))
+        match self.chars.next() {
+            Some((end, '\'')) => Ok((start, Tok::CharLiteral(c), end + 1)),
+            _ => Err(self.invalid_syntax(start)),
+        }
     }
-}
 
-impl<'input> Iterator for Lexer<'input> {
-    type Item = Spanned<'input>;
+    /// The error for what was consumed since `start`, which can't be lexed.
+    fn invalid_syntax(&mut self, start: usize) -> LexicalError {
+        let end = self.chars.peek().map_or(self.input.len(), |&(end, _)| end);
+        LexicalError::InvalidCharLiteral(start, end)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    /// Skips a `--` comment, or produces a `DocComment` if it is of the form
+    /// `--- ...`, stands on its own line and documents something.
+    fn line_comment(&mut self, start: usize) -> Option<Spanned<'input>> {
+        let (end, src) = self.take_while(start, |c| c != '\n');
+        let is_doc = src.starts_with("---") && !src.starts_with("----");
+        if is_doc && self.docs && self.at_line_start && self.documents(end) {
+            let text = src[3..].strip_prefix(' ').unwrap_or(&src[3..]).trim_end();
+            // Newlines following a doc comment are irrelevant, since
+            // the grammar expects an item to follow.
+            self.take_while(end, |c| c.is_whitespace());
+            Some(Ok((start, Tok::DocComment(text), end)))
+        } else {
+            self.comment_trail(end)
+        }
+    }
+
+    /// Whether the code from `start` on, past any comments, begins with an
+    /// item or an operation, which are the only things doc comments may
+    /// precede. Elsewhere, they are ordinary comments.
+    fn documents(&self, start: usize) -> bool {
+        let mut rest = Lexer::new(&self.input[start..]);
+        rest.docs = false;
+        match rest.find(|token| !matches!(token, Ok((_, Tok::Newline, _)))) {
+            Some(Ok((_, token, _))) => match token {
+                Tok::Let | Tok::Data | Tok::Effect | Tok::Mod => true,
+                Tok::Infixl | Tok::Infixr | Tok::Infix | Tok::At => true,
+                Tok::Name(_) => self.in_effect,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// Skips a (nested) `{- ... -}` comment, `start` points at the opening brace.
+    fn block_comment(&mut self, start: usize) -> Option<Spanned<'input>> {
+        let mut depth = 0usize;
+        while let Some((i, c)) = self.chars.next() {
+            let next = self.chars.peek().map(|&(_, c)| c);
+            match (c, next) {
+                ('{', Some('-')) => {
+                    self.chars.next();
+                    depth += 1;
+                }
+                ('-', Some('}')) => {
+                    self.chars.next();
+                    depth -= 1;
+                    if depth == 0 {
+                        return self.comment_trail(i + 2);
+                    }
+                }
+                _ => (),
+            }
+        }
+        Some(Err(LexicalError::UnterminatedComment(start)))
+    }
+
+    /// Consumes the whitespace that follows a comment. A `Newline` is only
+    /// produced if the comment trails some code on the same line, e.g:
+    /// `let x = 1 -- one`, otherwise the comment is transparent.
+    fn comment_trail(&mut self, start: usize) -> Option<Spanned<'input>> {
+        let (end, src) = self.take_while(start, |c| c.is_whitespace());
+        if src.contains('\n') && !self.at_line_start {
+            Some(Ok((start, Tok::Newline, end)))
+        } else {
+            None
+        }
+    }
+
+    fn token(&mut self) -> Option<Spanned<'input>> {
         while let Some(&(start, c)) = self.chars.peek() {
             return match c {
                 c if c.is_whitespace() => {
                     let (end, src) = self.take_while(start, |c| c.is_whitespace());
-                    if src.contains('\n') {
+                    if src.contains('\n') && !self.at_line_start {
                         Some(Ok((start, Tok::Newline, end)))
                     } else {
                         continue;
@@ -207,31 +318,26 @@ impl<'input> Iterator for Lexer<'input> {
                 // Unicode characters too, replace it or keep
                 // this in the language.
                 c if c.is_numeric() => Some(self.integer(start)),
+                '-' if self.signs(start) => {
+                    self.chars.next(); // Consume the sign.
+                    Some(self.integer(start))
+                }
                 c if c.is_uppercase() => Some(self.type_name(start)),
                 c if c.is_lowercase() || c == '_' => Some(self.name(start)),
                 '\'' => Some(self.character(start)),
                 '"' => Some(self.string(start)),
-                '-' => {
-                    self.chars.next(); // Consume the first hyphen
-                    if let Some(&(start, '-')) = self.chars.peek() {
-                        self.take_while(start, |c| c != '\n');
-                        // Also consume all newlines that follow, they are irrelevant
-                        // for the syntax as the context is a comment.
-                        self.take_while(start, |c| c.is_whitespace());
-                        continue;
-                    } else {
-                        // HACK: this is worse than it looks, as it uses the fact
-                        // that the already consumed hyphen will be part of an
-                        // operator, hence why we allow ourselves to start
-                        // the search done in the `take_while` call of operator.
-                        Some(self.operator(start))
+                '-' if self.input[start..].starts_with("--") => match self.line_comment(start) {
+                    None => continue,
+                    token => token,
+                },
+                // Block comments follow Haskell's syntax, and they nest
+                // so that commenting out code never ends prematurely.
+                '{' if self.input[start..].starts_with("{-") => {
+                    match self.block_comment(start) {
+                        None => continue,
+                        token => token,
                     }
                 }
-                // NOTE: candidates for multiline comments:
-                //  1. {- ... -} Haskell
-                //  2. (* ... *) AppleScript, *ML, Pascal
-                //  3. --[[ ... ]]-- Lua
-                //  4. -{ ... }- Passerine
                 '(' => {
                     self.chars.next();
                     Some(Ok((start, Tok::LParen, start + 1)))
@@ -267,6 +373,38 @@ impl<'input> Iterator for Lexer<'input> {
     }
 }
 
+impl<'input> Iterator for Lexer<'input> {
+    type Item = Spanned<'input>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let token = self.token();
+        if let Some(Ok((_, tok, _))) = &token {
+            self.at_line_start = matches!(tok, Tok::Newline | Tok::DocComment(_));
+            self.after_operand = matches!(
+                tok,
+                Tok::Name(_)
+                    | Tok::TypeName(_)
+                    | Tok::IntLiteral(_)
+                    | Tok::StrLiteral(_)
+                    | Tok::CharLiteral(_)
+                    | Tok::Ellipsis
+                    | Tok::True
+                    | Tok::False
+                    | Tok::End
+                    | Tok::RParen
+                    | Tok::RBrace
+                    | Tok::RBrack
+            );
+            match tok {
+                Tok::Effect => self.in_effect = true,
+                Tok::End => self.in_effect = false,
+                _ => (),
+            }
+        }
+        token
+    }
+}
+
 impl<'input> Display for Tok<'input> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{:?}", self)
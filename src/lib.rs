@@ -0,0 +1,59 @@
+//! Chimera, as a library to embed it in Rust programs. An `Interpreter`
+//! runs Chimera sources, and its values convert to and from Rust ones:
+//!
+//! ```
+//! use chimera::{IntoValue, Interpreter};
+//!
+//! let mut chimera = Interpreter::new().unwrap();
+//! chimera.load("let double = |x| x * 2\n").unwrap();
+//! let answer: i64 = chimera.call("double", vec![21.into_value()]).unwrap();
+//! assert_eq!(answer, 42);
+//! ```
+
+#[macro_use]
+extern crate lalrpop_util;
+
+pub mod ast;
+mod attribute;
+pub mod cgen;
+mod code;
+mod compiler;
+mod convert;
+mod dependency;
+pub mod doc;
+mod effect;
+pub mod error;
+mod fixity;
+mod gc;
+mod interpreter;
+mod kind;
+mod lexer;
+mod lift;
+mod parser;
+mod registry;
+mod row;
+mod sandbox;
+mod tree;
+pub mod typechecker;
+pub mod typed;
+pub mod value;
+
+pub use convert::{FromValue, IntoValue};
+pub use error::{Error, Limit};
+pub use gc::Census;
+pub use interpreter::{parse_files, Interpreter, PRELUDE};
+pub use parser::Sources;
+pub use sandbox::{Limits, DEFAULT_DEPTH, DEFAULT_STACK};
+pub use value::{Value, WoValue};
+
+lalrpop_mod!(#[allow(clippy::all)] pub grammar);
+
+/*
+    use core::io::println
+
+    let main ~ do
+        -- The answer to life,
+        -- the universe and everything.
+        println 42
+    end
+*/
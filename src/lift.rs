@@ -0,0 +1,521 @@
+/// Closure conversion and lambda lifting, which lower a type-checked program
+/// to the first-order code that `cgen` turns into C. Every scope that the
+/// interpreter makes an Env for becomes a frame of numbered slots, one for
+/// each name bound anywhere in the scope, and every function becomes a
+/// top-level one which is given the frame it is nested in.
+///
+/// The interpreter looks names up dynamically, through the chain of Envs, so
+/// a name that isn't bound yet in a scope is found further out. To behave the
+/// same, a name is resolved to every slot it may be in, innermost first, and
+/// the first of them that is set at runtime holds its value.
+use std::collections::HashSet;
+use std::slice;
+
+use anyhow::{bail, Result};
+
+use crate::ast::{Clause, Expr, Item, ItemKind, Pattern, Span, Stmt};
+use crate::dependency::free_names;
+use crate::effect;
+
+/// A whole program, whose items run in the frame of the globals.
+pub struct Program {
+    pub globals: usize,
+    /// The slot of `main`, if the program defines it.
+    pub main: Option<usize>,
+    pub items: Term,
+    pub functions: Vec<Function>,
+}
+
+pub struct Function {
+    pub kind: Kind,
+    pub body: Term,
+}
+
+/// How a function gets the frame its body runs in.
+pub enum Kind {
+    /// A lambda makes a frame of `slots` nested in its closure, where its
+    /// parameter is the first slot.
+    Lambda { slots: usize },
+    /// The clause of a handler makes a frame of `slots` nested in the one of
+    /// its handler, where its parameter is the first slot, followed by the
+    /// slot of `resume` unless it has the same name.
+    Clause { slots: usize, resume: usize },
+    /// The body of a `try` or `handle` expression runs in the frame it is
+    /// found in, and a match arm or a `catch` in the one its caller makes.
+    Scope,
+}
+
+pub enum Term {
+    Void,
+    Int(i64),
+    Bool(bool),
+    Char(char),
+    /// The value of `name`, in the first of `slots` that is set. A slot is
+    /// given by how many frames out it is, and its index there.
+    Name { name: String, slots: Vec<(usize, usize)> },
+    List(Vec<Term>),
+    Array(Vec<Term>),
+    /// The lambda lifted to the function numbered `0`, closed over the
+    /// current frame.
+    Closure(usize),
+    Apply(Box<Term>, Box<Term>),
+    Negate(Box<Term>),
+    /// The terms in order, whose value is that of the last one.
+    Sequence(Vec<Term>),
+    /// Sets a slot of the current frame, which is `()`.
+    Define(usize, Box<Term>),
+    Intrinsic(String),
+    Operation { name: String, default: Option<String> },
+    Constructor { name: String, arity: usize },
+    /// The first of the paths whose condition holds, if any does.
+    Branch(Vec<(Term, Term)>),
+    /// Runs `body`, then the arm `handler` if it raises an exception that
+    /// the arm's pattern matches.
+    Try { body: usize, handler: Arm },
+    /// Runs `body` with clauses handling operations, by name.
+    Handle { body: usize, clauses: Vec<(String, usize)> },
+    Match { value: Box<Term>, arms: Vec<Arm> },
+    Hole(Span),
+    /// What can't be compiled, in a function that is never called, which
+    /// faults with the message all the same if it runs.
+    Unsupported(String),
+}
+
+/// A match arm or a `catch`, whose `body` runs in a frame of `slots` that holds what its
+/// pattern binds.
+pub struct Arm {
+    pub pattern: Binder,
+    pub slots: usize,
+    pub body: usize,
+}
+
+pub enum Binder {
+    /// The fields of data built by the constructor `name`, bound in order.
+    Constructor { name: String, fields: Vec<usize> },
+    /// The value itself.
+    Name(usize),
+}
+
+pub fn lift(program: &[Item]) -> Result<Program> {
+    let mut globals = Vec::new();
+    for item in program {
+        item_bindings(item, &mut globals);
+    }
+    let mut lifter = Lifter {
+        scopes: vec![globals],
+        functions: Vec::new(),
+        called: called(program),
+        checked: true,
+    };
+    let items = program
+        .iter()
+        .map(|item| lifter.item(item))
+        .collect::<Result<Vec<_>>>()?;
+    let globals = lifter.scopes.pop().unwrap();
+    Ok(Program {
+        globals: globals.len(),
+        main: globals.iter().position(|name| name == "main"),
+        items: Term::Sequence(items),
+        functions: lifter.functions,
+    })
+}
+
+struct Lifter {
+    // The names bound in each enclosing scope, innermost last.
+    scopes: Vec<Vec<String>>,
+    functions: Vec<Function>,
+    // The top-level functions that may be called, see `called`.
+    called: HashSet<String>,
+    // Whether the item being lifted may run, otherwise what can't be
+    // compiled in it is only an error if it runs.
+    checked: bool,
+}
+
+impl Lifter {
+    /// Lifts `body`, which runs in a new scope binding `names` along with
+    /// everything it binds itself, and returns it with the size of its frame.
+    fn nested(&mut self, names: &[String], body: &Expr) -> Result<(Term, usize)> {
+        let mut scope = Vec::new();
+        for name in names {
+            bind(&mut scope, name);
+        }
+        bindings(body, &mut scope);
+        let slots = scope.len();
+        self.scopes.push(scope);
+        let body = self.expr(body);
+        self.scopes.pop();
+        Ok((body?, slots))
+    }
+
+    /// Lifts `body`, which runs in the current scope, to a function of its own.
+    fn scope(&mut self, body: &Expr) -> Result<usize> {
+        let body = self.expr(body)?;
+        Ok(self.push(Kind::Scope, body))
+    }
+
+    fn push(&mut self, kind: Kind, body: Term) -> usize {
+        self.functions.push(Function { kind, body });
+        self.functions.len() - 1
+    }
+
+    /// Sets the slot of `name` in the current scope, which binds it.
+    fn define(&self, name: &str, term: Term) -> Term {
+        let scope = self.scopes.last().unwrap();
+        let slot = scope.iter().position(|n| n == name).unwrap();
+        Term::Define(slot, Box::new(term))
+    }
+
+    fn name(&self, name: &str) -> Term {
+        let slots = self
+            .scopes
+            .iter()
+            .rev()
+            .enumerate()
+            .filter_map(|(depth, scope)| {
+                let index = scope.iter().position(|n| n == name)?;
+                Some((depth, index))
+            })
+            .collect();
+        Term::Name {
+            name: name.to_string(),
+            slots,
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Term> {
+        Ok(match expr {
+            Expr::Ellipsis(span) => Term::Hole(*span),
+            Expr::Void => Term::Void,
+            Expr::Int(i) => Term::Int(*i),
+            Expr::Bool(b) => Term::Bool(*b),
+            Expr::Char(c) => Term::Char(*c),
+            Expr::Name(name) => self.name(name),
+            Expr::List(elems) => Term::List(self.exprs(elems)?),
+            Expr::Array(elems) => Term::Array(self.exprs(elems)?),
+            Expr::Lambda { param, expr, .. } => {
+                let (body, slots) = self.nested(slice::from_ref(param), expr)?;
+                Term::Closure(self.push(Kind::Lambda { slots }, body))
+            }
+            Expr::Block { body } => Term::Sequence(self.stmts(body)?),
+            Expr::Infix { .. } => unreachable!("infix expressions are resolved by the parser"),
+            Expr::Apply { left, right } => {
+                Term::Apply(Box::new(self.expr(left)?), Box::new(self.expr(right)?))
+            }
+            Expr::Negate { expr } => Term::Negate(Box::new(self.expr(expr)?)),
+            Expr::Ascribe { expr, .. } | Expr::Located { expr, .. } => self.expr(expr)?,
+            Expr::Branch { paths } => Term::Branch(
+                paths
+                    .iter()
+                    .map(|(cond, body)| Ok((self.expr(cond)?, Term::Sequence(self.stmts(body)?))))
+                    .collect::<Result<_>>()?,
+            ),
+            Expr::Field { .. } | Expr::Assign { .. } => {
+                bail!("fields and assignments can't be compiled to C")
+            }
+            Expr::Handle { expr, clauses } => {
+                // The C runtime resumes an operation by returning to
+                // where it was performed, which must then be the end.
+                if let Some(clause) = clauses.iter().find(|c| !effect::resumes_last(c)) {
+                    let message = format!(
+                        "the handler of `{}` must resume last to be compiled to C",
+                        clause.operation
+                    );
+                    if self.checked {
+                        bail!(message)
+                    }
+                    return Ok(Term::Unsupported(message));
+                }
+                self.handle(expr, clauses)?
+            }
+            Expr::Try { expr, pattern, handler } => Term::Try {
+                body: self.scope(expr)?,
+                handler: self.arm(pattern, handler)?,
+            },
+            Expr::Match { expr, arms } => {
+                let value = Box::new(self.expr(expr)?);
+                let arms = arms
+                    .iter()
+                    .map(|arm| self.arm(&arm.pattern, &arm.body))
+                    .collect::<Result<_>>()?;
+                Term::Match { value, arms }
+            }
+        })
+    }
+
+    fn handle(&mut self, expr: &Expr, clauses: &[Clause]) -> Result<Term> {
+        Ok(Term::Handle {
+            body: self.scope(expr)?,
+            clauses: clauses
+                .iter()
+                .map(|clause| {
+                    let names = [clause.param.clone(), clause.resume.clone()];
+                    let (body, slots) = self.nested(&names, &clause.body)?;
+                    let resume = usize::from(clause.param != clause.resume);
+                    let code = self.push(Kind::Clause { slots, resume }, body);
+                    Ok((clause.operation.clone(), code))
+                })
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    fn arm(&mut self, pattern: &Pattern, body: &Expr) -> Result<Arm> {
+        let names = pattern.names();
+        // The names of the pattern come first in the frame.
+        let mut bound = Vec::new();
+        for name in names {
+            bind(&mut bound, name);
+        }
+        let slot = |name: &String| bound.iter().position(|n| n == name).unwrap();
+        let pattern = match pattern {
+            Pattern::Constructor { name, fields } => Binder::Constructor {
+                name: name.clone(),
+                fields: fields.iter().map(slot).collect(),
+            },
+            Pattern::Name(name) => Binder::Name(slot(name)),
+        };
+        let (body, slots) = self.nested(names, body)?;
+        Ok(Arm {
+            pattern,
+            slots,
+            body: self.push(Kind::Scope, body),
+        })
+    }
+
+    fn exprs(&mut self, exprs: &[Expr]) -> Result<Vec<Term>> {
+        exprs.iter().map(|expr| self.expr(expr)).collect()
+    }
+
+    fn stmts(&mut self, stmts: &[Stmt]) -> Result<Vec<Term>> {
+        stmts
+            .iter()
+            .map(|stmt| match stmt {
+                Stmt::Expr(expr) => self.expr(expr),
+                Stmt::Item(item) => self.item(item),
+            })
+            .collect()
+    }
+
+    fn item(&mut self, item: &Item) -> Result<Term> {
+        Ok(match &item.kind {
+            ItemKind::Definition { name, expr, .. } => match &item.attr {
+                None => {
+                    if self.scopes.len() == 1 {
+                        self.checked = self.called.contains(name) || !is_function(expr);
+                    }
+                    let term = self.expr(expr)?;
+                    self.define(name, term)
+                }
+                Some(attr) if attr.name == "intrinsic" => {
+                    self.define(name, Term::Intrinsic(attr.args[0].clone()))
+                }
+                Some(attr) => bail!("unknown attribute {}", attr.name),
+            },
+            ItemKind::Effect { operations, .. } => {
+                let mut terms = Vec::new();
+                for op in operations {
+                    let default = match &op.attr {
+                        None => None,
+                        Some(attr) if attr.name == "intrinsic" => Some(attr.args[0].clone()),
+                        Some(attr) => bail!("unknown attribute {}", attr.name),
+                    };
+                    let name = op.name.clone();
+                    terms.push(self.define(&op.name, Term::Operation { name, default }));
+                }
+                terms.push(Term::Void);
+                Term::Sequence(terms)
+            }
+            ItemKind::DataType { variants, .. } => {
+                let mut terms = variants
+                    .iter()
+                    .map(|(name, fields)| {
+                        let arity = fields.len();
+                        self.define(name, Term::Constructor { name: name.clone(), arity })
+                    })
+                    .collect::<Vec<_>>();
+                terms.push(Term::Void);
+                Term::Sequence(terms)
+            }
+            ItemKind::Module { items, .. } => {
+                let mut terms = items
+                    .iter()
+                    .map(|item| self.item(item))
+                    .collect::<Result<Vec<_>>>()?;
+                terms.push(Term::Void);
+                Term::Sequence(terms)
+            }
+            ItemKind::Fixity { .. } => Term::Void,
+        })
+    }
+}
+
+/// The names of the top-level functions that may be called: `main`, and
+/// what is mentioned by the definitions that run when the program is loaded
+/// or by the functions that may be called themselves.
+fn called(program: &[Item]) -> HashSet<String> {
+    let mut definitions = Vec::new();
+    top_level(program, &mut definitions);
+    let mut called = HashSet::new();
+    let mut pending = vec!["main".to_string()];
+    for (_, expr) in &definitions {
+        if !is_function(expr) {
+            free_names(expr, &mut Vec::new(), &mut called);
+        }
+    }
+    pending.extend(called.iter().cloned());
+    while let Some(name) = pending.pop() {
+        called.insert(name.clone());
+        for (_, expr) in definitions.iter().filter(|(n, _)| **n == name) {
+            let mut names = HashSet::new();
+            free_names(expr, &mut Vec::new(), &mut names);
+            pending.extend(names.into_iter().filter(|name| !called.contains(name)));
+        }
+    }
+    called
+}
+
+fn top_level<'p>(items: &'p [Item], definitions: &mut Vec<(&'p String, &'p Expr)>) {
+    for item in items {
+        match &item.kind {
+            ItemKind::Definition { name, expr, .. } if item.attr.is_none() => {
+                definitions.push((name, expr))
+            }
+            ItemKind::Module { items, .. } => top_level(items, definitions),
+            _ => (),
+        }
+    }
+}
+
+fn is_function(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lambda { .. } => true,
+        Expr::Ascribe { expr, .. } | Expr::Located { expr, .. } => is_function(expr),
+        _ => false,
+    }
+}
+
+fn bind(names: &mut Vec<String>, name: &str) {
+    if !names.iter().any(|n| n == name) {
+        names.push(name.to_string());
+    }
+}
+
+/// Collects the names `expr` binds in the scope it runs in. Blocks and
+/// branches don't make scopes of their own, nor do the bodies of `try` and
+/// `handle`, but functions, match arms, `catch`es and clauses do.
+fn bindings(expr: &Expr, names: &mut Vec<String>) {
+    match expr {
+        Expr::Block { body } => body_bindings(body, names),
+        Expr::Branch { paths } => {
+            for (cond, body) in paths {
+                bindings(cond, names);
+                body_bindings(body, names);
+            }
+        }
+        Expr::List(elems) | Expr::Array(elems) => {
+            for elem in elems {
+                bindings(elem, names);
+            }
+        }
+        Expr::Apply { left, right } | Expr::Assign { left, right } => {
+            bindings(left, names);
+            bindings(right, names);
+        }
+        Expr::Negate { expr }
+        | Expr::Ascribe { expr, .. }
+        | Expr::Located { expr, .. }
+        | Expr::Field { expr, .. }
+        | Expr::Handle { expr, .. }
+        | Expr::Try { expr, .. }
+        | Expr::Match { expr, .. } => bindings(expr, names),
+        Expr::Infix { head, tail } => {
+            bindings(head, names);
+            for (_, operand) in tail {
+                bindings(operand, names);
+            }
+        }
+        Expr::Lambda { .. }
+        | Expr::Ellipsis(_)
+        | Expr::Void
+        | Expr::Int(_)
+        | Expr::Bool(_)
+        | Expr::Char(_)
+        | Expr::Name(_) => {}
+    }
+}
+
+fn body_bindings(body: &[Stmt], names: &mut Vec<String>) {
+    for stmt in body {
+        match stmt {
+            Stmt::Expr(expr) => bindings(expr, names),
+            Stmt::Item(item) => item_bindings(item, names),
+        }
+    }
+}
+
+fn item_bindings(item: &Item, names: &mut Vec<String>) {
+    match &item.kind {
+        ItemKind::Definition { name, expr, .. } => {
+            // The definition of an intrinsic isn't evaluated.
+            if item.attr.is_none() {
+                bindings(expr, names);
+            }
+            bind(names, name);
+        }
+        ItemKind::Effect { operations, .. } => {
+            for op in operations {
+                bind(names, &op.name);
+            }
+        }
+        ItemKind::DataType { variants, .. } => {
+            for (name, _) in variants {
+                bind(names, name);
+            }
+        }
+        ItemKind::Module { items, .. } => {
+            for item in items {
+                item_bindings(item, names);
+            }
+        }
+        ItemKind::Fixity { .. } => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixity::Fixities;
+    use crate::parser::parse;
+
+    fn program(source: &str) -> Program {
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        lift(&items).unwrap()
+    }
+
+    #[test]
+    fn resolved_names() {
+        let program = program(
+            "let x = 1\n\
+             let f = |y| do\n\
+             \x20   let z = x\n\
+             \x20   let x = y\n\
+             \x20   z\n\
+             end\n",
+        );
+        assert_eq!(program.globals, 2);
+        let body = match &program.functions[..] {
+            [Function {
+                kind: Kind::Lambda { slots: 3 },
+                body: Term::Sequence(body),
+            }] => body,
+            _ => panic!("expected a single lambda"),
+        };
+        // Before the inner `x` is defined, the global one is found.
+        match &body[0] {
+            Term::Define(1, value) => match &**value {
+                Term::Name { slots, .. } => assert_eq!(slots, &vec![(0, 2), (1, 0)]),
+                _ => panic!("expected a name"),
+            },
+            _ => panic!("expected `z` to be defined"),
+        }
+    }
+}
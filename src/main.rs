@@ -1,65 +1,248 @@
-#[macro_use]
-extern crate lalrpop_util;
+use std::io::{self, Write};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use std::process::{self, Command};
+use std::{env, panic, thread};
 
-use std::cell::RefCell;
-use std::rc::Rc;
-use std::{env, fs};
+use anyhow::{bail, Context, Result};
 
-use anyhow::{Context, Result};
+use chimera::typechecker::Lexicon;
+use chimera::{cgen, doc, parse_files, typed, Error, Interpreter, Limits, DEFAULT_STACK};
 
-use parser::parse;
+const USAGE: &str = "\
+usage: chimera [run] [--seed N] [--fuel N] [--depth N] [--values N] [--timeout MS]
+               [--no-io] FILE... [-- ARG...]
+       chimera check [--types] FILE...
+       chimera build [--target c] [--cc] [-o OUT] FILE...
+       chimera doc [--markdown] [--out DIR] FILE...";
 
-use crate::code::{Code, Env};
+fn main() -> Result<()> {
+    let mut args = env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("check") => {
+            args.next();
+            check(args)
+        }
+        Some("doc") => {
+            args.next();
+            doc(args)
+        }
+        Some("build") => {
+            args.next();
+            build(args)
+        }
+        Some("run") => {
+            args.next();
+            run(args)
+        }
+        Some("--help") | Some("-h") | None => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Some(_) => run(args),
+    }
+}
 
-// use crate::typechecker::Lexicon;
+/// The stack programs run on, much deeper than the one of the main thread.
+const STACK_SIZE: usize = 1 << 30;
 
-mod ast;
-mod attribute;
-mod code;
-mod compiler;
-mod error;
-mod lexer;
-mod parser;
-mod typechecker;
-mod value;
+/// How to run a program, as given on the command line.
+struct Run {
+    seed: Option<u64>,
+    limits: Limits,
+    filenames: Vec<String>,
+    // The arguments of the program, the ones after `--`.
+    args: Vec<String>,
+}
 
-lalrpop_mod!(#[allow(clippy::all)] pub grammar);
+impl Run {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut seed = None;
+        // Programs may use all of their stack, but what the interpreter needs
+        // outside of them.
+        let mut limits = Limits {
+            stack: STACK_SIZE - DEFAULT_STACK,
+            ..Limits::default()
+        };
+        let mut filenames = Vec::new();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--" => break,
+                "--seed" => seed = Some(number(&arg, args.next())?),
+                "--fuel" => limits.fuel = Some(number(&arg, args.next())?),
+                "--depth" => limits.depth = number(&arg, args.next())?,
+                "--values" => limits.values = Some(number(&arg, args.next())?),
+                "--timeout" => {
+                    limits.timeout = Some(Duration::from_millis(number(&arg, args.next())?))
+                }
+                "--no-io" => limits.io = false,
+                _ => filenames.push(arg),
+            }
+        }
+        Ok(Run {
+            seed,
+            limits,
+            filenames,
+            args: args.collect(),
+        })
+    }
+}
 
-/*
-    use core::io::println
+/// Runs the program, whose arguments are the ones after `--`.
+fn run(args: impl Iterator<Item = String>) -> Result<()> {
+    let Run {
+        seed,
+        limits,
+        filenames,
+        args,
+    } = Run::parse(args)?;
+    // The interpreter recurses as deeply as the program does, which the
+    // main thread doesn't have the stack for.
+    let status = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || {
+            let mut chimera = Interpreter::empty();
+            if let Some(seed) = seed {
+                chimera.set_seed(seed);
+            }
+            chimera.set_limits(limits);
+            chimera.set_args(args);
+            chimera.load_files(filenames).and_then(|_| chimera.run_main())
+        })?
+        .join()
+        .unwrap_or_else(|panic| panic::resume_unwind(panic));
+    let code = match status {
+        Ok(code) | Err(Error::Exit(code)) => code,
+        Err(error) => return Err(error.into()),
+    };
+    if code != 0 {
+        // Exiting skips the flush that would happen on return.
+        io::stdout().flush()?;
+        process::exit(code);
+    }
+    Ok(())
+}
 
-    let main ~ do
-        -- The answer to life,
-        -- the universe and everything.
-        println 42
-    end
-*/
+fn number<T: FromStr>(flag: &str, arg: Option<String>) -> Result<T> {
+    match arg.and_then(|arg| arg.parse().ok()) {
+        Some(n) => Ok(n),
+        None => bail!("expected a number after `{}`\n{}", flag, USAGE),
+    }
+}
 
-fn main() -> Result<()> {
-    let mut program = Vec::new();
-    for filename in env::args().skip(1) {
-        let source = fs::read_to_string(&filename).with_context(|| {
-            format!(
-                "error reading source file `{}`, you are on your own.",
-                filename
-            )
-        })?;
-        let items = parse(&source)
-            .with_context(|| format!("error while parsing source file `{}`", filename))?;
+/// Type-checks the program without running it, reporting every typed hole,
+/// and the type of every definition with `--types`.
+fn check(args: impl Iterator<Item = String>) -> Result<()> {
+    let mut types = false;
+    let mut filenames = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--types" => types = true,
+            _ => filenames.push(arg),
+        }
+    }
+    let (program, sources) = parse_files(filenames)?;
+    let lexicon = Lexicon::default();
+    let typed = lexicon
+        .typed_items(&program)
+        .map_err(|error| sources.type_error(error))?;
+    for hole in lexicon.resolved_holes() {
+        eprintln!("{}", sources.hole(&hole));
+    }
+    if types {
+        print!("{}", typed::Bindings(&typed));
+    }
+    Ok(())
+}
 
-        // let lexicon = Lexicon::default();
-        // for item in &items {
-        //     lexicon
-        //         .check(item)
-        //         .with_context(|| format!("encountered a type error in source file `{}`", filename))?;
-        // }
+/// Compiles the program to `OUT.c`, then to the executable `OUT` with
+/// `--cc`, which runs the C compiler `$CC` or else `cc`. `OUT` defaults to
+/// the name of the last file, without its extension.
+fn build(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut compile = false;
+    let mut out = None;
+    let mut filenames = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => match args.next().as_deref() {
+                Some("c") => {}
+                Some(target) => bail!("unknown target `{}`, the only one is `c`", target),
+                None => bail!("expected a target after `--target`\n{}", USAGE),
+            },
+            "--cc" => compile = true,
+            "-o" => match args.next() {
+                Some(path) => out = Some(PathBuf::from(path)),
+                None => bail!("expected a file after `-o`\n{}", USAGE),
+            },
+            _ => filenames.push(arg),
+        }
+    }
+    let out = match (out, filenames.last()) {
+        (Some(out), _) => out,
+        (None, Some(last)) => PathBuf::from(Path::new(last).file_stem().unwrap_or_default()),
+        (None, None) => bail!("expected a file to build\n{}", USAGE),
+    };
+    let (program, sources) = parse_files(&filenames)?;
+    Lexicon::default()
+        .check_items(&program)
+        .map_err(|error| sources.type_error(error))?;
+    let source = out.with_extension("c");
+    fs::write(&source, cgen::generate(&program)?)
+        .with_context(|| format!("error writing `{}`", source.display()))?;
+    if compile {
+        let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+        let status = Command::new(&cc)
+            .args(["-O2", "-pthread", "-o"])
+            .arg(&out)
+            .arg(&source)
+            .status()
+            .with_context(|| format!("error running the C compiler `{}`", cc))?;
+        if !status.success() {
+            bail!("the C compiler `{}` failed with {}", cc, status);
+        }
+    }
+    Ok(())
+}
+
+fn doc(mut args: impl Iterator<Item = String>) -> Result<()> {
+    let mut format = doc::Format::Html;
+    let mut out = PathBuf::from("doc");
+    let mut filenames = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--markdown" => format = doc::Format::Markdown,
+            "--out" => match args.next() {
+                Some(dir) => out = PathBuf::from(dir),
+                None => bail!("expected a directory after `--out`\n{}", USAGE),
+            },
+            _ => filenames.push(arg),
+        }
+    }
+    let (program, _) = parse_files(filenames)?;
+    doc::generate(&program, format, &out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        program.extend(items);
+    fn run_args(args: &[&str]) -> Run {
+        Run::parse(args.iter().map(|arg| arg.to_string())).unwrap()
     }
 
-    let env = Rc::new(RefCell::new(Env::default()));
-    for item in program {
-        item.compile().execute(env.clone());
+    #[test]
+    fn program_args() {
+        let run = run_args(&["--seed", "3", "main.chi", "--", "--fuel", "1", "--"]);
+        assert_eq!(run.seed, Some(3));
+        assert_eq!(run.limits.fuel, None);
+        assert_eq!(run.filenames, vec!["main.chi"]);
+        // Everything after the first `--` is the program's, flags included.
+        assert_eq!(run.args, vec!["--fuel", "1", "--"]);
+        let run = run_args(&["--no-io", "a.chi", "b.chi"]);
+        assert!(!run.limits.io);
+        assert_eq!(run.filenames, vec!["a.chi", "b.chi"]);
+        assert!(run.args.is_empty());
     }
-    Ok(())
 }
@@ -4,25 +4,256 @@ use annotate_snippets::{
     display_list::{DisplayList, FormatOptions},
     snippet::{Annotation, AnnotationType, Slice, Snippet, SourceAnnotation},
 };
-use anyhow::{Error, Result};
 use lalrpop_util::ParseError;
+use polytype::{Type, TypeSchema, Variable};
 
-use crate::{ast::Item, lexer::Tok};
-use crate::error::LexicalError;
+use crate::{ast::{intern, Item, Stmt}, lexer::Tok};
+use crate::error::{Error, LexicalError, TypeError};
+use crate::fixity::Fixities;
 use crate::lexer::Lexer;
+use crate::typechecker::Hole;
 
-pub fn parse(source: &str) -> Result<Vec<Item>> {
+/// Parses `source` then resolves its infix expressions, using the fixities
+/// declared so far alongside the ones declared in `source` itself.
+pub fn parse(source: &str, fixities: &mut Fixities) -> Result<Vec<Item>, Error> {
+    parse_file(source, 0, fixities)
+}
+
+/// Parses `source` like `parse`, as the source numbered `file`, see `Sources`.
+pub fn parse_file(source: &str, file: usize, fixities: &mut Fixities) -> Result<Vec<Item>, Error> {
     let lexer = Lexer::new(source);
-    let result = crate::grammar::ProgramParser::new().parse(source, &mut HashMap::new(), lexer);
+    let result = crate::grammar::ProgramParser::new().parse(
+        source,
+        file,
+        &mut TypeVariables::default(),
+        lexer,
+    );
     match result {
-        Ok(program) => Ok(program),
-        Err(error) => Err(Error::msg(fmt_parse_error(source, error))),
+        Ok(mut program) => {
+            fixities.declare(&program)?;
+            fixities.resolve(&mut program)?;
+            Ok(program)
+        }
+        Err(error) => Err(Error::Syntax(fmt_parse_error(source, error))),
+    }
+}
+
+/// Parses `source` as the statements of a block, as in `do source end`.
+/// Its infix expressions are left for `Fixities::resolve`.
+pub fn parse_body(source: &str) -> Result<Vec<Stmt>, Error> {
+    let lexer = Lexer::new(source);
+    crate::grammar::BodyParser::new()
+        .parse(source, 0, &mut TypeVariables::default(), lexer)
+        .map_err(|error| Error::Syntax(fmt_parse_error(source, error)))
+}
+
+/// The sources of a program by number, along with the name of their file if
+/// they have one, which the type errors found in them are rendered against.
+#[derive(Debug, Default)]
+pub struct Sources(Vec<(Option<String>, String)>);
+
+impl Sources {
+    /// Adds `source`, which is then numbered as the sources before it.
+    pub fn add(&mut self, file: Option<String>, source: String) -> usize {
+        self.0.push((file, source));
+        self.0.len() - 1
+    }
+
+    /// A single source, without a file, as the one `parse` numbers.
+    pub fn single(source: &str) -> Self {
+        Sources(vec![(None, source.to_string())])
+    }
+
+    /// Renders `error` along with the part of the sources it was found in,
+    /// as syntax errors are, provided that it is known.
+    pub fn type_error(&self, error: TypeError) -> Error {
+        let snippet = match &error {
+            TypeError::Mismatch { span: Some(span), .. }
+            | TypeError::ScopeError { span: Some(span), .. } => {
+                self.0.get(span.file).map(|(file, source)| {
+                    ann_error(
+                        source,
+                        file.as_deref(),
+                        &error.to_string(),
+                        "in this expression",
+                        (span.start, span.end),
+                    )
+                })
+            }
+            _ => None,
+        };
+        Error::Type { error, snippet }
+    }
+
+    /// Shows `hole` along with the file it was found in, if it has one.
+    pub fn hole(&self, hole: &Hole) -> String {
+        match self.0.get(hole.span.file) {
+            Some((Some(file), _)) => hole.located(&format!("{}:{}", file, hole.span)),
+            _ => hole.to_string(),
+        }
+    }
+}
+
+/// The name of the row variable of an annotation, see `TypeVariables::row`.
+/// It cannot clash with user-defined names as it isn't a valid name.
+const ROW: &str = "row#";
+
+/// The type variables in scope while parsing, see the grammar.
+#[derive(Debug, Default)]
+pub struct TypeVariables {
+    /// Bound by a `forall`, these scope over the body of the definition.
+    /// This is a stack, as a `forall` may shadow an enclosing one.
+    scoped: Vec<(String, Variable)>,
+    /// Used without a `forall` in the annotation being parsed.
+    implicit: Vec<(String, Variable)>,
+    /// Used without a `forall` in the local annotations of each definition
+    /// being parsed, innermost last, see `local`.
+    local: Vec<Vec<(String, Variable)>>,
+    /// Every variable gets its own number within a source file.
+    next: Variable,
+    /// Whether a `data` or `effect` declaration is being parsed, where the
+    /// variables are bound by the header or a `forall`, never implicitly.
+    declaring: bool,
+}
+
+impl TypeVariables {
+    fn fresh(&mut self) -> Variable {
+        self.next += 1;
+        self.next - 1
+    }
+
+    /// Binds `name` explicitly, as in `forall name.`
+    pub fn bind(&mut self, name: String) -> Variable {
+        let variable = self.fresh();
+        self.scoped.push((name, variable));
+        variable
+    }
+
+    /// The variable `name` refers to, which is implicitly bound if need be.
+    pub fn get(&mut self, name: &str) -> Variable {
+        let found = self
+            .scoped
+            .iter()
+            .rev()
+            .chain(self.implicit.iter())
+            .find(|(n, _)| n == name);
+        match found {
+            Some((_, variable)) => *variable,
+            None => {
+                let variable = self.fresh();
+                self.implicit.push((name.to_string(), variable));
+                variable
+            }
+        }
+    }
+
+    /// The type variable `name`, see `get`. In declarations, a variable that
+    /// isn't in scope is rather kept as a lower-case constructor, for the
+    /// kind checker to report.
+    pub fn variable(&mut self, name: &str) -> Type {
+        let bound = self.scoped.iter().rev().find(|(n, _)| n == name);
+        match bound {
+            None if self.declaring => Type::Constructed(intern(name), vec![]),
+            _ => Type::Variable(self.get(name)),
+        }
+    }
+
+    /// Starts the body of a declaration, whose header is in scope.
+    pub fn declare(&mut self) {
+        self.declaring = true;
+    }
+
+    /// Ends the body of a declaration. The rows of the function types of its
+    /// fields are left unbound, for the type checker to report.
+    pub fn declared(&mut self) {
+        self.declaring = false;
+        self.implicit.clear();
+    }
+
+    /// Quantifies `ts` over its implicitly bound variables, at the outermost
+    /// level and in order of appearance, as in Haskell and ML.
+    pub fn quantify(&mut self, ts: TypeSchema) -> TypeSchema {
+        self.implicit.drain(..).rev().fold(ts, |ts, (_, variable)| TypeSchema::Polytype {
+            variable,
+            body: Box::new(ts),
+        })
+    }
+
+    /// The row variable that stands for the effects of an annotation's functions
+    /// besides the ones they list, it is implicitly bound like any other. As it
+    /// is shared by the whole annotation, `(a -> b) -> List a -> List b` means
+    /// that the function performs whatever effects its argument performs.
+    pub fn row(&mut self) -> Type {
+        Type::Variable(self.get(ROW))
+    }
+
+    /// A row variable of its own, for a function that returns another one,
+    /// such as the outer arrow of `a -> a -> {Console} Void`. Currying only
+    /// builds a closure, sharing the row of the annotation would rather make
+    /// it the same row as the one of the function it returns.
+    pub fn curried(&mut self) -> Type {
+        let variable = self.fresh();
+        self.implicit.push((format!("{ROW}{variable}"), variable));
+        Type::Variable(variable)
+    }
+
+    /// Quantifies `ts` like `quantify`, but its variables stay in scope until
+    /// `unbind` is called, as the parameters of an effect do for its operations.
+    pub fn scope(&mut self, ts: TypeSchema) -> TypeSchema {
+        self.scoped.extend(self.implicit.iter().cloned());
+        self.quantify(ts)
+    }
+
+    /// Starts the local annotations of a definition.
+    pub fn enter(&mut self) {
+        self.local.push(Vec::new());
+    }
+
+    /// Ends the local annotations of the innermost definition.
+    pub fn leave(&mut self) {
+        self.local.pop();
+    }
+
+    /// The implicitly bound variables of the local annotation `t` are the same
+    /// as those of the same name in the other local annotations of the same
+    /// definition, as the `a` of `|x: a, y: a|`. Rows are not shared though.
+    pub fn local(&mut self, t: Type) -> Type {
+        let frame = match self.local.last_mut() {
+            Some(frame) => frame,
+            None => {
+                self.implicit.clear();
+                return t;
+            }
+        };
+        let mut substitution = HashMap::new();
+        for (name, variable) in self.implicit.drain(..) {
+            if name.starts_with(ROW) {
+                continue;
+            }
+            match frame.iter().find(|(n, _)| *n == name) {
+                Some((_, shared)) => {
+                    substitution.insert(variable, Type::Variable(*shared));
+                }
+                None => frame.push((name, variable)),
+            }
+        }
+        t.substitute(&substitution)
+    }
+
+    /// Takes the variables bound by `ts` out of scope.
+    pub fn unbind(&mut self, ts: &TypeSchema) {
+        let mut bound = Vec::new();
+        let mut schema = ts;
+        while let TypeSchema::Polytype { variable, body } = schema {
+            bound.push(*variable);
+            schema = body;
+        }
+        self.scoped.retain(|(_, variable)| !bound.contains(variable));
     }
 }
 
 /// Takes information extracted from a `TypeError` and the relevant source code
-/// to produce a pretty printed annotated-snippet. This is meant to be wrapped
-/// in `anyhow::Error::msg` for use in `main`.
+/// to produce a pretty printed annotated-snippet, see `Error::Syntax`.
 fn fmt_parse_error(source: &str, error: ParseError<usize, Tok<'_>, LexicalError>) -> String {
     // NOTE: One cannot impl Display for ParseError since it's defined
     // in an external crate, and lalrpop_util implements it anyway.
@@ -68,11 +299,44 @@ fn fmt_parse_error(source: &str, error: ParseError<usize, Tok<'_>, LexicalError>
                 (token.0, token.2),
             )
         }
-        ParseError::User { .. } => unreachable!(),
+        ParseError::User { error } => match error {
+            LexicalError::UnterminatedComment(location) => ann_parse_error(
+                source,
+                "unterminated block comment",
+                "this comment is never closed",
+                (location, location + 2),
+            ),
+            LexicalError::IntegerOverflow(start, end) => ann_parse_error(
+                source,
+                "integer literal out of range",
+                "this does not fit in an `Int`",
+                (start, end),
+            ),
+            LexicalError::InvalidCharLiteral(start, end) => ann_parse_error(
+                source,
+                "invalid character literal",
+                "expected a character or an escape between single quotes",
+                (start, end),
+            ),
+        },
     }
 }
 
 fn ann_parse_error(source: &str, label: &str, slice_label: &str, range: (usize, usize)) -> String {
+    ann_error(source, None, label, slice_label, range)
+}
+
+/// Annotates `range` of `source`, from the file `origin` if it has one.
+/// The source is shown from the line `range` starts on, which is the line
+/// the origin is then numbered with.
+fn ann_error(
+    source: &str,
+    origin: Option<&str>,
+    label: &str,
+    slice_label: &str,
+    range: (usize, usize),
+) -> String {
+    let start = source[..range.0].rfind('\n').map_or(0, |i| i + 1);
     let snippet = Snippet {
         title: Some(Annotation {
             label: Some(label),
@@ -81,14 +345,14 @@ fn ann_parse_error(source: &str, label: &str, slice_label: &str, range: (usize,
         }),
         footer: vec![],
         slices: vec![Slice {
-            source,
-            line_start: 0,
-            origin: None,
+            source: &source[start..],
+            line_start: source[..start].matches('\n').count() + 1,
+            origin,
             fold: true,
             annotations: vec![SourceAnnotation {
                 label: slice_label,
                 annotation_type: AnnotationType::Error,
-                range,
+                range: (range.0 - start, range.1 - start),
             }],
         }],
         opt: FormatOptions {
@@ -104,15 +368,40 @@ mod tests {
     use ::polytype::*;
 
     use crate::ast::*;
+    use crate::row;
 
     use super::*;
 
+    /// `expr`, located where `fragment` first appears in `source`.
+    fn located(source: &str, fragment: &str, expr: Expr) -> Expr {
+        let start = source.find(fragment).unwrap();
+        Expr::Located {
+            span: Span::new(source, 0, start, start + fragment.len()),
+            expr: Box::new(expr),
+        }
+    }
+
+    #[test]
+    fn hole_file() {
+        let mut sources = Sources::default();
+        sources.add(Some("a.chi".to_string()), "let x = 1\n".to_string());
+        let source = "let y =\n    ...\n";
+        sources.add(Some("b.chi".to_string()), source.to_string());
+        let hole = Hole {
+            span: Span::new(source, 1, 12, 15),
+            expected: TypeSchema::Monotype(tp!(Int)),
+            bindings: vec![],
+        };
+        assert_eq!(sources.hole(&hole), "hole at b.chi:2:5 has type `Int`");
+        assert_eq!(Sources::single(source).hole(&hole), "hole at 2:5 has type `Int`");
+    }
+
     #[test]
     fn empty_program() {
         let source = r"";
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(result, Ok(vec![]))
     }
 
@@ -120,13 +409,14 @@ mod tests {
     fn definition_int() {
         let source = "let answer = 42\n";
         dbg!(source.len());
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(
             result,
             Ok(vec![Item {
-                attrs: vec![],
+                doc: None,
+                attr: None,
                 kind: ItemKind::Definition {
                     name: "answer".to_string(),
                     ann: None,
@@ -140,13 +430,14 @@ mod tests {
     fn definition_bool() {
         let source = "let truth = true\n";
         dbg!(source.len());
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(
             result,
             Ok(vec![Item {
-                attrs: vec![],
+                doc: None,
+                attr: None,
                 kind: ItemKind::Definition {
                     name: "truth".to_string(),
                     ann: None,
@@ -160,13 +451,14 @@ mod tests {
     fn definition_char() {
         let source = "let most_iconic_lang = 'C'\n";
         dbg!(source.len());
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(
             result,
             Ok(vec![Item {
-                attrs: vec![],
+                doc: None,
+                attr: None,
                 kind: ItemKind::Definition {
                     name: "most_iconic_lang".to_string(),
                     ann: None,
@@ -180,17 +472,18 @@ mod tests {
     fn definition_str() {
         let source = "let hello = \"Hello, World!\"\n";
         dbg!(source.len());
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(
             result,
             Ok(vec![Item {
-                attrs: vec![],
+                doc: None,
+                attr: None,
                 kind: ItemKind::Definition {
                     name: "hello".to_string(),
                     ann: None,
-                    expr: Expr::Str("Hello, World!".to_string()),
+                    expr: Expr::List("Hello, World!".chars().map(Expr::Char).collect()),
                 },
             }])
         )
@@ -200,13 +493,14 @@ mod tests {
     fn definition_ident() {
         let source = "let hello = hi\n";
         dbg!(source.len());
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(
             result,
             Ok(vec![Item {
-                attrs: vec![],
+                doc: None,
+                attr: None,
                 kind: ItemKind::Definition {
                     name: "hello".to_string(),
                     ann: None,
@@ -220,18 +514,22 @@ mod tests {
     fn definition_branch() {
         let source = "let one = if true then 1 end\n";
         dbg!(source.len());
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(
             result,
             Ok(vec![Item {
-                attrs: vec![],
+                doc: None,
+                attr: None,
                 kind: ItemKind::Definition {
-                    name: "hello".to_string(),
+                    name: "one".to_string(),
                     ann: None,
                     expr: Expr::Branch {
-                        paths: vec![(Expr::Bool(true), vec![Stmt::Expr(Expr::Int(1))])]
+                        paths: vec![
+                            (Expr::Bool(true), vec![Stmt::Expr(Expr::Int(1))]),
+                            (Expr::Bool(true), vec![Stmt::Expr(Expr::Void)]),
+                        ]
                     },
                 },
             }])
@@ -240,18 +538,19 @@ mod tests {
 
     #[test]
     fn definition_with_attr() {
-        let source = "@[intrinsic(())]\nlet name_with_attr: Void = ()\n";
+        let source = "@[intrinsic(unit)]\nlet name_with_attr: Void = ()\n";
         dbg!(source.len());
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(
             result,
             Ok(vec![Item {
-                attrs: vec![Attr {
+                doc: None,
+                attr: Some(Attr {
                     name: "intrinsic".to_string(),
-                    args: vec![Expr::Void],
-                }],
+                    args: vec!["unit".to_string()],
+                }),
                 kind: ItemKind::Definition {
                     name: "name_with_attr".to_string(),
                     ann: Some(ptp!(Void)),
@@ -270,13 +569,14 @@ mod tests {
             Down {},
         end
         ";
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(
             result,
             Ok(vec![Item {
-                attrs: vec![],
+                doc: None,
+                attr: None,
                 kind: ItemKind::DataType {
                     schema: ptp!(Direction),
                     variants: vec![
@@ -300,13 +600,14 @@ mod tests {
             },
         end
         ";
-        let lexer = Lexer::new(&source);
+        let lexer = Lexer::new(source);
         let result =
-            crate::grammar::ProgramParser::new().parse(&source, &mut HashMap::new(), lexer);
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
         assert_eq!(
             result,
             Ok(vec![Item {
-                attrs: vec![],
+                doc: None,
+                attr: None,
                 kind: ItemKind::DataType {
                     schema: ptp!(Person),
                     variants: vec![(
@@ -321,4 +622,444 @@ mod tests {
             }])
         )
     }
+
+    #[test]
+    fn definition_with_doc() {
+        let source = "--- The answer.\n--- To everything.\nlet answer = 42\n";
+        let lexer = Lexer::new(source);
+        let result =
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
+        assert_eq!(
+            result,
+            Ok(vec![Item {
+                doc: Some("The answer.\nTo everything.".to_string()),
+                attr: None,
+                kind: ItemKind::Definition {
+                    name: "answer".to_string(),
+                    ann: None,
+                    expr: Expr::Int(42),
+                },
+            }])
+        )
+    }
+
+    #[test]
+    fn undocumenting_comments() {
+        // Only items and operations are documented, `---` lines elsewhere
+        // are ordinary comments.
+        let source = "\
+effect Log
+    --- Logs a line.
+    log : List Char -> Void
+    --- Nothing more.
+end
+let main = |_| do
+    --- Statements aren't documented.
+    log \"start\"
+    --- Local definitions are.
+    let x = 1
+    x
+    --- Nor is the end of a block.
+end
+--- Nor the end of the file.
+";
+        let result = parse(source, &mut Fixities::default()).unwrap();
+        match &result[0].kind {
+            ItemKind::Effect { operations, .. } => {
+                assert_eq!(operations[0].doc.as_deref(), Some("Logs a line."))
+            }
+            _ => unreachable!(),
+        }
+        match &result[1].kind {
+            ItemKind::Definition { expr: Expr::Lambda { expr, .. }, .. } => match &**expr {
+                Expr::Block { body } => match &body[1] {
+                    Stmt::Item(item) => {
+                        assert_eq!(item.doc.as_deref(), Some("Local definitions are."))
+                    }
+                    _ => unreachable!(),
+                },
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn nested_block_comment() {
+        let source = "{- outer {- inner -} still outer -}\nlet answer = 42 {- trailing -}\n";
+        let lexer = Lexer::new(source);
+        let result =
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
+        assert_eq!(
+            result,
+            Ok(vec![Item {
+                doc: None,
+                attr: None,
+                kind: ItemKind::Definition {
+                    name: "answer".to_string(),
+                    ann: None,
+                    expr: Expr::Int(42),
+                },
+            }])
+        )
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let source = "{- {- -}\nlet answer = 42\n";
+        let lexer = Lexer::new(source);
+        let result =
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
+        assert_eq!(
+            result,
+            Err(ParseError::User {
+                error: LexicalError::UnterminatedComment(0)
+            })
+        )
+    }
+
+    #[test]
+    fn invalid_character() {
+        for source in ["let c = '\\q'\n", "let c = '", "let c = '\\", "let c = 'ab'\n"] {
+            let result = parse(source, &mut Fixities::default());
+            assert!(matches!(result, Err(Error::Syntax(_))), "{:?}", source);
+        }
+        let lexer = Lexer::new("'é' '\\n'");
+        let tokens = lexer.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(
+            tokens,
+            vec![(0, Tok::CharLiteral('é'), 4), (5, Tok::CharLiteral('\n'), 9)]
+        );
+    }
+
+    #[test]
+    fn definition_sections() {
+        let source = "let halve = (/ 2)\nlet tenth = (1 /)\n";
+        let lexer = Lexer::new(source);
+        let result =
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
+        let name = |name: &str| Box::new(Expr::Name(name.to_string()));
+        let right = Expr::Apply {
+            left: Box::new(Expr::Lambda {
+                param: OPERAND_PARAM.to_string(),
+                ann: None,
+                expr: Box::new(Expr::Lambda {
+                    param: SECTION_PARAM.to_string(),
+                    ann: None,
+                    expr: Box::new(Expr::Apply {
+                        left: Box::new(Expr::Apply {
+                            left: name("(/)"),
+                            right: name(SECTION_PARAM),
+                        }),
+                        right: name(OPERAND_PARAM),
+                    }),
+                }),
+            }),
+            right: Box::new(Expr::Int(2)),
+        };
+        let left = Expr::Apply {
+            left: name("(/)"),
+            right: Box::new(Expr::Int(1)),
+        };
+        assert_eq!(
+            result,
+            Ok(vec![
+                Item {
+                    doc: None,
+                    attr: None,
+                    kind: ItemKind::Definition {
+                        name: "halve".to_string(),
+                        ann: None,
+                        expr: right,
+                    },
+                },
+                Item {
+                    doc: None,
+                    attr: None,
+                    kind: ItemKind::Definition {
+                        name: "tenth".to_string(),
+                        ann: None,
+                        expr: left,
+                    },
+                },
+            ])
+        )
+    }
+
+    #[test]
+    fn definition_negation() {
+        let source = "let x = (- 4)\nlet y = -f x\n";
+        let lexer = Lexer::new(source);
+        let result =
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
+        assert_eq!(
+            result,
+            Ok(vec![
+                Item {
+                    doc: None,
+                    attr: None,
+                    kind: ItemKind::Definition {
+                        name: "x".to_string(),
+                        ann: None,
+                        expr: Expr::Int(-4),
+                    },
+                },
+                Item {
+                    doc: None,
+                    attr: None,
+                    kind: ItemKind::Definition {
+                        name: "y".to_string(),
+                        ann: None,
+                        expr: Expr::Negate {
+                            expr: Box::new(located(
+                                source,
+                                "f x",
+                                Expr::Apply {
+                                    left: Box::new(Expr::Name("f".to_string())),
+                                    right: Box::new(Expr::Name("x".to_string())),
+                                },
+                            )),
+                        },
+                    },
+                },
+            ])
+        )
+    }
+
+    #[test]
+    fn negative_literals() {
+        let source = "let min = -9223372036854775808\nlet max = 9223372036854775807\n\
+                      let y = x -1\nlet z = (- -9223372036854775808)\n";
+        let result = parse(source, &mut Fixities::default()).unwrap();
+        let exprs = result
+            .into_iter()
+            .map(|item| match item.kind {
+                ItemKind::Definition { expr, .. } => expr,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(exprs[0], Expr::Int(i64::MIN));
+        assert_eq!(exprs[1], Expr::Int(i64::MAX));
+        match &exprs[2] {
+            Expr::Located { expr, .. } => assert!(matches!(**expr, Expr::Apply { .. })),
+            expr => panic!("{:?} is not located", expr),
+        }
+        // Its negation overflows, at runtime.
+        assert_eq!(exprs[3], Expr::Negate { expr: Box::new(Expr::Int(i64::MIN)) });
+        for source in ["let x = 9223372036854775808\n", "let x = -9223372036854775809\n"] {
+            let result = parse(source, &mut Fixities::default());
+            assert!(matches!(result, Err(Error::Syntax(_))), "{:?}", source);
+        }
+    }
+
+    #[test]
+    fn definition_annotated_lambda() {
+        let source = "let f = |x: Int, y| (y : Bool)\n";
+        let lexer = Lexer::new(source);
+        let result =
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
+        assert_eq!(
+            result,
+            Ok(vec![Item {
+                doc: None,
+                attr: None,
+                kind: ItemKind::Definition {
+                    name: "f".to_string(),
+                    ann: None,
+                    expr: Expr::Lambda {
+                        param: "x".to_string(),
+                        ann: Some(tp!(Int)),
+                        expr: Box::new(Expr::Lambda {
+                            param: "y".to_string(),
+                            ann: None,
+                            expr: Box::new(located(
+                                source,
+                                "y : Bool",
+                                Expr::Ascribe {
+                                    expr: Box::new(Expr::Name("y".to_string())),
+                                    ann: tp!(Bool),
+                                },
+                            )),
+                        }),
+                    },
+                },
+            }])
+        )
+    }
+
+    #[test]
+    fn definition_implicit_forall() {
+        let source = "let const : a -> b -> a = ...\n";
+        let lexer = Lexer::new(source);
+        let result =
+            crate::grammar::ProgramParser::new().parse(source, 0, &mut Default::default(), lexer);
+        assert_eq!(
+            result,
+            Ok(vec![Item {
+                doc: None,
+                attr: None,
+                kind: ItemKind::Definition {
+                    name: "const".to_string(),
+                    ann: Some(TypeSchema::Polytype {
+                        variable: 0,
+                        body: Box::new(TypeSchema::Polytype {
+                            variable: 1,
+                            // The implicit row variable of the annotation,
+                            // then the one of the curried outer arrow.
+                            body: Box::new(TypeSchema::Polytype {
+                                variable: 2,
+                                body: Box::new(TypeSchema::Polytype {
+                                    variable: 3,
+                                    body: Box::new(TypeSchema::Monotype(row::arrow(
+                                        tp!(0),
+                                        row::arrow(tp!(1), tp!(0), tp!(2)),
+                                        tp!(3),
+                                    ))),
+                                }),
+                            }),
+                        }),
+                    }),
+                    expr: Expr::Ellipsis(Span::new(source, 0, 26, 29)),
+                },
+            }])
+        )
+    }
+
+    #[test]
+    fn effect_and_handler() {
+        let source = "\
+effect State s
+    get : Void -> s
+end
+let x = handle get () with
+    get _ resume -> resume 0
+end
+";
+        let result = parse(source, &mut Fixities::default()).unwrap();
+        let get = match &result[0].kind {
+            ItemKind::Effect { schema, operations } => {
+                assert_eq!(
+                    schema,
+                    &TypeSchema::Polytype {
+                        variable: 0,
+                        body: Box::new(TypeSchema::Monotype(tp!(State(tp!(0))))),
+                    }
+                );
+                operations[0].ann.clone()
+            }
+            _ => unreachable!(),
+        };
+        // The parameter of the effect is in scope, the row variable is not.
+        assert_eq!(
+            get,
+            TypeSchema::Polytype {
+                variable: 1,
+                body: Box::new(TypeSchema::Monotype(row::arrow(tp!(Void), tp!(0), tp!(1)))),
+            }
+        );
+        match &result[1].kind {
+            ItemKind::Definition { expr: Expr::Handle { clauses, .. }, .. } => assert_eq!(
+                clauses,
+                &vec![Clause {
+                    operation: "get".to_string(),
+                    param: "_".to_string(),
+                    resume: "resume".to_string(),
+                    body: located(
+                        source,
+                        "resume 0",
+                        Expr::Apply {
+                            left: Box::new(Expr::Name("resume".to_string())),
+                            right: Box::new(Expr::Int(0)),
+                        },
+                    ),
+                }]
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn explicit_effects() {
+        let source = "let f : Void -> {State Int | e} Int = ...\n";
+        let result = parse(source, &mut Fixities::default()).unwrap();
+        match &result[0].kind {
+            ItemKind::Definition { ann: Some(ts), .. } => assert_eq!(
+                ts,
+                &TypeSchema::Polytype {
+                    variable: 0,
+                    body: Box::new(TypeSchema::Monotype(row::arrow(
+                        tp!(Void),
+                        tp!(Int),
+                        row::extend(tp!(State(tp!(Int))), tp!(0)),
+                    ))),
+                }
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn try_catch() {
+        let source = "let x = try\n    head xs\ncatch e -> 0\nend\n";
+        let result = parse(source, &mut Fixities::default()).unwrap();
+        match &result[0].kind {
+            ItemKind::Definition { expr, .. } => assert_eq!(
+                expr,
+                &Expr::Try {
+                    expr: Box::new(located(
+                        source,
+                        "head xs",
+                        Expr::Apply {
+                            left: Box::new(Expr::Name("head".to_string())),
+                            right: Box::new(Expr::Name("xs".to_string())),
+                        },
+                    )),
+                    pattern: Pattern::Name("e".to_string()),
+                    handler: Box::new(Expr::Int(0)),
+                }
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn match_arms() {
+        let source = "let x = match o with\n    Some x -> x\n    _ -> Zero\nend\n";
+        let result = parse(source, &mut Fixities::default()).unwrap();
+        match &result[0].kind {
+            ItemKind::Definition { expr, .. } => assert_eq!(
+                expr,
+                &Expr::Match {
+                    expr: Box::new(Expr::Name("o".to_string())),
+                    arms: vec![
+                        Arm {
+                            pattern: Pattern::Constructor {
+                                name: "Some".to_string(),
+                                fields: vec!["x".to_string()],
+                            },
+                            body: Expr::Name("x".to_string()),
+                        },
+                        Arm {
+                            pattern: Pattern::Name("_".to_string()),
+                            body: Expr::Name("Zero".to_string()),
+                        },
+                    ],
+                }
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn array_literal() {
+        let source = "let x = #[1, [2]]\n";
+        let result = parse(source, &mut Fixities::default()).unwrap();
+        match &result[0].kind {
+            ItemKind::Definition { expr, .. } => assert_eq!(
+                expr,
+                &Expr::Array(vec![Expr::Int(1), Expr::List(vec![Expr::Int(2)])])
+            ),
+            _ => unreachable!(),
+        }
+    }
 }
@@ -0,0 +1,154 @@
+/// Native functions, written in Rust, which `@[intrinsic(name)]` definitions
+/// are bound to. The registry takes care of currying them: a native of arity
+/// `n` is given its `n` arguments at once, when the last one is applied.
+/// The registry is shared by the interpreters of a thread, as compiled code
+/// has no way to get at the interpreter it runs in.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::attribute;
+use crate::code::{CompiledCode, Env, Outcome, WoEnv};
+use crate::error::Error;
+use crate::gc;
+use crate::sandbox;
+use crate::value::{Value, WoValue};
+
+type Native = Rc<dyn Fn(&[WoValue]) -> Outcome>;
+
+#[derive(Default)]
+pub struct Registry {
+    natives: HashMap<String, (usize, Native)>,
+}
+
+thread_local! {
+    static REGISTRY: RefCell<Registry> = RefCell::new(builtins());
+}
+
+fn builtins() -> Registry {
+    let mut registry = Registry::default();
+    attribute::builtins(&mut registry);
+    registry
+}
+
+impl Registry {
+    /// Defines `name` as a native function of `arity` arguments,
+    /// or as a constant if its arity is zero.
+    pub fn define(
+        &mut self,
+        name: &str,
+        arity: usize,
+        native: impl Fn(&[WoValue]) -> Outcome + 'static,
+    ) {
+        self.natives.insert(name.to_string(), (arity, Rc::new(native)));
+    }
+
+    /// Defines a native like `define`, which is denied unless the limits
+    /// of the program allow I/O.
+    pub fn define_io(
+        &mut self,
+        name: &str,
+        arity: usize,
+        native: impl Fn(&[WoValue]) -> Outcome + 'static,
+    ) {
+        let intrinsic = name.to_string();
+        self.define(name, arity, move |args| {
+            sandbox::check_io(&intrinsic)?;
+            native(args)
+        });
+    }
+}
+
+// The parameters are numbered, with names that can't clash with the ones
+// of the program as they aren't valid names.
+fn curry(arity: usize, applied: usize, native: Native, closure: WoEnv) -> Value {
+    gc::track(&closure);
+    Value::Lambda {
+        param: format!("arg#{}", applied),
+        body: Rc::new(CompiledCode::new(move |env| {
+            if applied + 1 < arity {
+                return Ok(curry(arity, applied + 1, native.clone(), env).into());
+            }
+            let args = (0..arity)
+                .map(|i| Env::get_name(env.clone(), &format!("arg#{}", i)))
+                .collect::<Result<Vec<_>, _>>()?;
+            native(&args)
+        })),
+        closure,
+    }
+}
+
+/// Defines a native for the rest of the thread, see `Registry::define`.
+/// As natives are shared by the interpreters of a thread, the name must not
+/// be taken, whether by a builtin or by a native defined earlier.
+pub fn define(
+    name: &str,
+    arity: usize,
+    native: impl Fn(&[WoValue]) -> Outcome + 'static,
+) -> Result<(), Error> {
+    REGISTRY.with(|registry| {
+        let mut registry = registry.borrow_mut();
+        if registry.natives.contains_key(name) {
+            return Err(Error::Redefined(name.to_string()));
+        }
+        registry.define(name, arity, native);
+        Ok(())
+    })
+}
+
+/// Replaces the native `name` for the rest of the thread, builtins included,
+/// so that tests can observe what programs do.
+#[cfg(test)]
+pub fn replace(name: &str, arity: usize, native: impl Fn(&[WoValue]) -> Outcome + 'static) {
+    REGISTRY.with(|registry| registry.borrow_mut().define(name, arity, native));
+}
+
+/// The value of the native `name`.
+pub fn intrinsic(name: &str) -> Outcome {
+    // The registry isn't borrowed while a constant is computed.
+    let value = REGISTRY.with(|registry| {
+        let registry = registry.borrow();
+        registry.natives.get(name).map(|(arity, native)| (*arity, native.clone()))
+    });
+    match value {
+        Some((0, native)) => native(&[]),
+        Some((arity, native)) => {
+            Ok(curry(arity, 0, native, Env::new(None)).into())
+        }
+        None => attribute::fault(&format!("unknown intrinsic attribute {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::apply;
+
+    #[test]
+    fn curried_natives() {
+        define("sum3", 3, |args| {
+            let sum = args.iter().map(|arg| match *arg.borrow() {
+                Value::Int(i) => i,
+                _ => unreachable!(),
+            });
+            Ok(Value::Int(sum.sum()).into())
+        })
+        .unwrap();
+        let sum3 = intrinsic("sum3").unwrap();
+        let partial = apply(&sum3, Value::Int(1).into()).unwrap();
+        let partial = apply(&partial, Value::Int(2).into()).unwrap();
+        // A partial application can be shared.
+        let four = apply(&partial, Value::Int(1).into()).unwrap();
+        let six = apply(&partial, Value::Int(3).into()).unwrap();
+        assert_eq!(*four.borrow(), Value::Int(4));
+        assert_eq!(*six.borrow(), Value::Int(6));
+        define("answer", 0, |_| Ok(Value::Int(42).into())).unwrap();
+        assert_eq!(*intrinsic("answer").unwrap().borrow(), Value::Int(42));
+        // Nothing is replaced, the builtins least of all.
+        let add = define("add", 2, |_| Ok(Value::Int(0).into()));
+        assert!(matches!(add, Err(Error::Redefined(_))));
+        let answer = define("answer", 0, |_| Ok(Value::Int(0).into()));
+        assert!(matches!(answer, Err(Error::Redefined(_))));
+        assert_eq!(*intrinsic("answer").unwrap().borrow(), Value::Int(42));
+    }
+}
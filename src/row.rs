@@ -0,0 +1,188 @@
+/// Effect rows, which extend function types with the effects they perform.
+/// A function of type `a -> {State Int, Console} b` is represented as
+/// `→(a, b, ⟨State Int | ⟨Console | ε⟩⟩)`, where the tail `ε` stands for
+/// whatever other effects the function is used with. Rows are unified
+/// up to the order of their labels, which polytype knows nothing about.
+use polytype::{Context, Type, UnificationError, Variable};
+
+/// This is how polytype names function types, whose third argument is
+/// the row of effects the function performs.
+pub const ARROW: &str = "→";
+
+/// The row `⟨label | tail⟩`, which extends `tail` with `label`.
+pub const EXTEND: &str = "⟨|⟩";
+
+pub fn arrow(param: Type, result: Type, row: Type) -> Type {
+    Type::Constructed(ARROW, vec![param, result, row])
+}
+
+/// The parameter, result and effect row of a function type.
+pub fn as_arrow(t: &Type) -> Option<(&Type, &Type, &Type)> {
+    match t {
+        Type::Constructed(name, args) if *name == ARROW && args.len() == 3 => {
+            Some((&args[0], &args[1], &args[2]))
+        }
+        _ => None,
+    }
+}
+
+pub fn extend(label: Type, row: Type) -> Type {
+    Type::Constructed(EXTEND, vec![label, row])
+}
+
+/// The labels of `row` from left to right, followed by its tail.
+pub fn labels(row: &Type) -> (Vec<&Type>, &Type) {
+    let mut labels = Vec::new();
+    let mut row = row;
+    while let Type::Constructed(name, args) = row {
+        if *name != EXTEND {
+            break;
+        }
+        labels.push(&args[0]);
+        row = &args[1];
+    }
+    (labels, row)
+}
+
+/// The effect a label stands for, regardless of its arguments.
+pub fn effect(label: &Type) -> Option<&'static str> {
+    match label {
+        Type::Constructed(name, _) => Some(name),
+        Type::Variable(_) => None,
+    }
+}
+
+/// Unifies `t1` with `t2` like `Context::unify`, except that effect rows
+/// are equal whenever they have the same labels, in any order.
+/// The context is left untouched if they don't unify.
+pub fn unify(ctx: &mut Context, t1: &Type, t2: &Type) -> Result<(), UnificationError> {
+    let snapshot = ctx.clone();
+    let result = unify_internal(ctx, t1, t2);
+    if result.is_err() {
+        *ctx = snapshot;
+    }
+    result
+}
+
+fn unify_internal(ctx: &mut Context, t1: &Type, t2: &Type) -> Result<(), UnificationError> {
+    let t1 = t1.apply(ctx);
+    let t2 = t2.apply(ctx);
+    if t1 == t2 {
+        return Ok(());
+    }
+    match (t1, t2) {
+        (Type::Variable(v), t) | (t, Type::Variable(v)) => {
+            if occurs(&t, v) {
+                Err(UnificationError::Occurs(v))
+            } else {
+                ctx.extend(v, t);
+                Ok(())
+            }
+        }
+        (Type::Constructed(n1, a1), Type::Constructed(n2, a2))
+            if n1 == EXTEND && n2 == EXTEND =>
+        {
+            unify_rows(ctx, &a1[0], &a1[1], &Type::Constructed(n2, a2))
+        }
+        (Type::Constructed(n1, a1), Type::Constructed(n2, a2)) => {
+            if n1 != n2 || a1.len() != a2.len() {
+                return Err(UnificationError::Failure(
+                    Type::Constructed(n1, a1),
+                    Type::Constructed(n2, a2),
+                ));
+            }
+            for (t1, t2) in a1.iter().zip(&a2) {
+                unify_internal(ctx, t1, t2)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+// See: Daan Leijen, "Extensible records with scoped labels", 2005.
+// `row` is rewritten so that `label` comes first, then the labels and the
+// tails are unified. The tail of `⟨label | tail⟩` must not be bound in the
+// process, as in `⟨A | e⟩ ~ ⟨B | e⟩`, which has no finite solution.
+fn unify_rows(
+    ctx: &mut Context,
+    label: &Type,
+    tail: &Type,
+    row: &Type,
+) -> Result<(), UnificationError> {
+    let tail_variable = match labels(tail).1 {
+        Type::Variable(v) => Some(*v),
+        Type::Constructed(..) => None,
+    };
+    let (found, rest) = rewrite(ctx, label, row)?;
+    if let Some(v) = tail_variable {
+        if Type::Variable(v).apply(ctx) != Type::Variable(v) {
+            return Err(UnificationError::Occurs(v));
+        }
+    }
+    unify_internal(ctx, label, &found)?;
+    unify_internal(ctx, tail, &rest)
+}
+
+/// Finds the label of the same effect as `label` in `row`, along with the
+/// rest of `row`. An open row is extended with `label` if need be.
+fn rewrite(ctx: &mut Context, label: &Type, row: &Type) -> Result<(Type, Type), UnificationError> {
+    match row.apply(ctx) {
+        Type::Constructed(name, args) if name == EXTEND => {
+            if effect(&args[0]) == effect(label) {
+                Ok((args[0].clone(), args[1].clone()))
+            } else {
+                let (found, rest) = rewrite(ctx, label, &args[1])?;
+                Ok((found, extend(args[0].clone(), rest)))
+            }
+        }
+        Type::Variable(v) => {
+            let rest = ctx.new_variable();
+            ctx.extend(v, extend(label.clone(), rest.clone()));
+            Ok((label.clone(), rest))
+        }
+        row => Err(UnificationError::Failure(label.clone(), row)),
+    }
+}
+
+fn occurs(t: &Type, v: Variable) -> bool {
+    match t {
+        Type::Variable(w) => *w == v,
+        Type::Constructed(_, args) => args.iter().any(|a| occurs(a, v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use polytype::tp;
+
+    use super::*;
+
+    #[test]
+    fn rows_unify_in_any_order() {
+        let mut ctx = Context::default();
+        let tail = ctx.new_variable();
+        let left = extend(tp!(State), extend(tp!(Console), tail));
+        let right = extend(tp!(Console), extend(tp!(State), tp!(0)));
+        assert_eq!(unify(&mut ctx, &left, &right), Ok(()));
+    }
+
+    #[test]
+    fn missing_effect() {
+        let mut ctx = Context::default();
+        // The rigid tail of an annotation, which stands for no other effect.
+        let rigid = Type::Constructed("'t0", vec![]);
+        let left = extend(tp!(State), rigid.clone());
+        let right = extend(tp!(Console), rigid);
+        assert!(unify(&mut ctx, &left, &right).is_err());
+    }
+
+    #[test]
+    fn recursive_row() {
+        // ⟨State | e⟩ ~ ⟨Console | e⟩ would require `e` to contain both.
+        let mut ctx = Context::default();
+        let tail = ctx.new_variable();
+        let left = extend(tp!(State), tail.clone());
+        let right = extend(tp!(Console), tail);
+        assert!(unify(&mut ctx, &left, &right).is_err());
+    }
+}
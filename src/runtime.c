@@ -0,0 +1,1760 @@
+/* The runtime of Chimera programs compiled to C by `chimera build`, which
+ * the generated code follows. It mirrors the interpreter: values, the
+ * handlers of effects and the natives behave the same, and print the same.
+ * Memory is never freed, and there are no resource limits.
+ *
+ * Build it with: cc -O2 -pthread -o program program.c
+ */
+#define _POSIX_C_SOURCE 200809L
+
+#include <dirent.h>
+#include <errno.h>
+#include <inttypes.h>
+#include <pthread.h>
+#include <stdarg.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/stat.h>
+#include <time.h>
+#include <unistd.h>
+
+typedef struct Value Value;
+typedef struct Frame Frame;
+typedef struct Cell Cell;
+typedef struct Node Node;
+
+/* The result of running some code: a value, or a non-local exit that is
+ * returned up to the code that expects it. See `Unwind` in the interpreter. */
+typedef enum { OK, RESUME, ABORT, RAISE, EXIT } Status;
+
+typedef struct {
+    Status status;
+    Value *value;
+    size_t handler;
+    int code;
+} Outcome;
+
+/* The slots of the names bound in a scope, which is nested in `outer`. */
+struct Frame {
+    Frame *outer;
+    Value *slots[];
+};
+
+typedef Outcome (*Code)(Frame *closure, Value *arg);
+typedef Outcome (*NativeCode)(Value **args);
+
+typedef struct {
+    const char *name;
+    int arity;
+    NativeCode code;
+} Native;
+
+typedef enum {
+    VOID, INT, BOOL, CHAR, LIST, LAMBDA, NATIVE, OPERATION, CONTINUATION,
+    DATA, CONSTRUCTOR, ARRAY, MAP, SET
+} Tag;
+
+/* Lists are chains of cells, the empty list being NULL. */
+struct Cell {
+    Value *head;
+    Cell *tail;
+};
+
+/* The nodes of the persistent AVL trees behind maps and sets. */
+struct Node {
+    Value *key;
+    Value *value;
+    Node *left;
+    Node *right;
+    int height;
+    size_t size;
+};
+
+struct Value {
+    Tag tag;
+    union {
+        int64_t i;
+        int b;
+        uint32_t c;
+        Cell *list;
+        struct {
+            Code code;
+            Frame *closure;
+        } lambda;
+        /* A native waiting for the rest of its arguments. */
+        struct {
+            const Native *native;
+            int applied;
+            Value **args;
+        } native;
+        struct {
+            const char *name;
+            Value *fallback;
+        } operation;
+        size_t handler;
+        /* Both data and the constructors still waiting for fields. */
+        struct {
+            const char *name;
+            int arity;
+            int count;
+            Value **fields;
+        } data;
+        struct {
+            size_t len;
+            Value **elems;
+        } array;
+        Node *tree;
+    } as;
+};
+
+static _Noreturn void panic(const char *format, ...) {
+    va_list args;
+    fflush(stdout);
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fputc('\n', stderr);
+    exit(101);
+}
+
+static void *alloc(size_t size) {
+    void *memory = calloc(1, size > 0 ? size : 1);
+    if (memory == NULL) {
+        panic("chimera: out of memory");
+    }
+    return memory;
+}
+
+static Frame *frame(Frame *outer, size_t slots) {
+    Frame *frame = alloc(sizeof(Frame) + slots * sizeof(Value *));
+    frame->outer = outer;
+    return frame;
+}
+
+static Value UNIT_VALUE = { VOID };
+#define UNIT (&UNIT_VALUE)
+
+static Value *make(Tag tag) {
+    Value *value = alloc(sizeof(Value));
+    value->tag = tag;
+    return value;
+}
+
+static Value *integer(int64_t i) {
+    Value *value = make(INT);
+    value->as.i = i;
+    return value;
+}
+
+static Value *boolean(int b) {
+    Value *value = make(BOOL);
+    value->as.b = b;
+    return value;
+}
+
+static Value *character(uint32_t c) {
+    Value *value = make(CHAR);
+    value->as.c = c;
+    return value;
+}
+
+static Value *list(Cell *cells) {
+    Value *value = make(LIST);
+    value->as.list = cells;
+    return value;
+}
+
+static Cell *cons(Value *head, Cell *tail) {
+    Cell *cell = alloc(sizeof(Cell));
+    cell->head = head;
+    cell->tail = tail;
+    return cell;
+}
+
+static Value *list_of(size_t len, Value **elems) {
+    Cell *cells = NULL;
+    while (len > 0) {
+        cells = cons(elems[--len], cells);
+    }
+    return list(cells);
+}
+
+static Value *array_of(size_t len, Value **elems) {
+    Value *value = make(ARRAY);
+    value->as.array.len = len;
+    value->as.array.elems = alloc(len * sizeof(Value *));
+    if (elems != NULL && len > 0) {
+        memcpy(value->as.array.elems, elems, len * sizeof(Value *));
+    }
+    return value;
+}
+
+static Value *lambda(Code code, Frame *closure) {
+    Value *value = make(LAMBDA);
+    value->as.lambda.code = code;
+    value->as.lambda.closure = closure;
+    return value;
+}
+
+static Value *data(const char *name, int count, Value **fields) {
+    Value *value = make(DATA);
+    value->as.data.name = name;
+    value->as.data.arity = count;
+    value->as.data.count = count;
+    value->as.data.fields = fields;
+    return value;
+}
+
+/* The constructor `name` of a data type, or its value if it has no fields. */
+static Value *constructor(const char *name, int arity) {
+    if (arity == 0) {
+        return data(name, 0, NULL);
+    }
+    Value *value = make(CONSTRUCTOR);
+    value->as.data.name = name;
+    value->as.data.arity = arity;
+    return value;
+}
+
+static Value *some(Value *value) {
+    Value **fields = alloc(sizeof(Value *));
+    fields[0] = value;
+    return data("Some", 1, fields);
+}
+
+static Value *none(void) {
+    return data("None", 0, NULL);
+}
+
+static Value *chars(const char *string);
+
+/* A `Failure` exception, as declared in the prelude, described by `message`. */
+static Value *exception(const char *message) {
+    Value **fields = alloc(sizeof(Value *));
+    fields[0] = chars(message);
+    return data("Failure", 1, fields);
+}
+
+static Outcome ok(Value *value) {
+    return (Outcome){ OK, value, 0, 0 };
+}
+
+static Outcome raise(Value *exception) {
+    return (Outcome){ RAISE, exception, 0, 0 };
+}
+
+/* Raises an exception for a runtime fault a program may recover from. */
+static Outcome fault(const char *message) {
+    return raise(exception(message));
+}
+
+/* Returns from the current function unless `outcome` is a value, which is
+ * then bound to `var`. */
+#define TRY(var, outcome)                                                     \
+    Outcome var##_ = (outcome);                                               \
+    if (var##_.status != OK) {                                                \
+        return var##_;                                                        \
+    }                                                                         \
+    Value *var = var##_.value
+
+/* The first of two slots that is set. */
+static Value *either(Value *first, Value *second) {
+    return first != NULL ? first : second;
+}
+
+/* Strings, which are lists of characters. */
+
+typedef struct {
+    char *data;
+    size_t len;
+    size_t cap;
+} Buffer;
+
+static void append(Buffer *buffer, const char *bytes, size_t len) {
+    if (buffer->len + len + 1 > buffer->cap) {
+        buffer->cap = 2 * (buffer->len + len + 1);
+        buffer->data = realloc(buffer->data, buffer->cap);
+        if (buffer->data == NULL) {
+            panic("chimera: out of memory");
+        }
+    }
+    memcpy(buffer->data + buffer->len, bytes, len);
+    buffer->len += len;
+    buffer->data[buffer->len] = '\0';
+}
+
+static void append_string(Buffer *buffer, const char *string) {
+    append(buffer, string, strlen(string));
+}
+
+static void append_char(Buffer *buffer, uint32_t c) {
+    char bytes[4];
+    size_t len;
+    if (c < 0x80) {
+        bytes[0] = (char)c;
+        len = 1;
+    } else if (c < 0x800) {
+        bytes[0] = (char)(0xc0 | c >> 6);
+        bytes[1] = (char)(0x80 | (c & 0x3f));
+        len = 2;
+    } else if (c < 0x10000) {
+        bytes[0] = (char)(0xe0 | c >> 12);
+        bytes[1] = (char)(0x80 | (c >> 6 & 0x3f));
+        bytes[2] = (char)(0x80 | (c & 0x3f));
+        len = 3;
+    } else {
+        bytes[0] = (char)(0xf0 | c >> 18);
+        bytes[1] = (char)(0x80 | (c >> 12 & 0x3f));
+        bytes[2] = (char)(0x80 | (c >> 6 & 0x3f));
+        bytes[3] = (char)(0x80 | (c & 0x3f));
+        len = 4;
+    }
+    append(buffer, bytes, len);
+}
+
+/* Decodes the UTF-8 character at `bytes`, of which there are `len`, and
+ * returns how many bytes it takes. Invalid bytes decode to U+FFFD. */
+static size_t decode(const unsigned char *bytes, size_t len, uint32_t *c) {
+    size_t width;
+    uint32_t min;
+    if (bytes[0] < 0x80) {
+        *c = bytes[0];
+        return 1;
+    } else if (bytes[0] >= 0xf0 && bytes[0] < 0xf5) {
+        width = 4, min = 0x10000, *c = bytes[0] & 0x07;
+    } else if (bytes[0] >= 0xe0 && bytes[0] < 0xf0) {
+        width = 3, min = 0x800, *c = bytes[0] & 0x0f;
+    } else if (bytes[0] >= 0xc2 && bytes[0] < 0xe0) {
+        width = 2, min = 0x80, *c = bytes[0] & 0x1f;
+    } else {
+        *c = 0xfffd;
+        return 1;
+    }
+    for (size_t i = 1; i < width; i++) {
+        if (i >= len || (bytes[i] & 0xc0) != 0x80) {
+            *c = 0xfffd;
+            return i;
+        }
+        *c = *c << 6 | (bytes[i] & 0x3f);
+    }
+    if (*c < min || *c > 0x10ffff || (*c >= 0xd800 && *c < 0xe000)) {
+        *c = 0xfffd;
+    }
+    return width;
+}
+
+static int valid_utf8(const char *bytes, size_t len) {
+    size_t i = 0;
+    while (i < len) {
+        uint32_t c;
+        size_t width = decode((const unsigned char *)bytes + i, len - i, &c);
+        if (c == 0xfffd && memcmp(bytes + i, "\xef\xbf\xbd", width < 3 ? width : 3) != 0) {
+            return 0;
+        }
+        i += width;
+    }
+    return 1;
+}
+
+/* The `List Char` value of the UTF-8 encoded `bytes`. */
+static Value *chars_of(const char *bytes, size_t len) {
+    Cell *cells = NULL, **last = &cells;
+    size_t i = 0;
+    while (i < len) {
+        uint32_t c;
+        i += decode((const unsigned char *)bytes + i, len - i, &c);
+        *last = cons(character(c), NULL);
+        last = &(*last)->tail;
+    }
+    return list(cells);
+}
+
+static Value *chars(const char *string) {
+    return chars_of(string, strlen(string));
+}
+
+/* The UTF-8 encoded string a `List Char` value stands for. */
+static char *string(Value *value) {
+    Buffer buffer = { NULL, 0, 0 };
+    append(&buffer, "", 0);
+    for (Cell *cell = value->as.list; cell != NULL; cell = cell->tail) {
+        append_char(&buffer, cell->head->as.c);
+    }
+    return buffer.data;
+}
+
+/* Maps and sets, see `tree.rs`. */
+
+static int height(Node *tree) {
+    return tree == NULL ? 0 : tree->height;
+}
+
+static size_t size(Node *tree) {
+    return tree == NULL ? 0 : tree->size;
+}
+
+static Node *node(Value *key, Value *value, Node *left, Node *right) {
+    Node *node = alloc(sizeof(Node));
+    node->key = key;
+    node->value = value;
+    node->left = left;
+    node->right = right;
+    node->height = 1 + (height(left) > height(right) ? height(left) : height(right));
+    node->size = 1 + size(left) + size(right);
+    return node;
+}
+
+static Node *balance(Value *key, Value *value, Node *left, Node *right) {
+    if (height(left) > height(right) + 1) {
+        Node *l = left;
+        if (height(l->left) >= height(l->right)) {
+            return node(l->key, l->value, l->left, node(key, value, l->right, right));
+        }
+        Node *lr = l->right;
+        return node(lr->key, lr->value,
+                    node(l->key, l->value, l->left, lr->left),
+                    node(key, value, lr->right, right));
+    } else if (height(right) > height(left) + 1) {
+        Node *r = right;
+        if (height(r->right) >= height(r->left)) {
+            return node(r->key, r->value, node(key, value, left, r->left), r->right);
+        }
+        Node *rl = r->left;
+        return node(rl->key, rl->value,
+                    node(key, value, left, rl->left),
+                    node(r->key, r->value, rl->right, r->right));
+    }
+    return node(key, value, left, right);
+}
+
+static int compare(Value *x, Value *y);
+
+static Value *tree_get(Node *tree, Value *key) {
+    while (tree != NULL) {
+        int order = compare(key, tree->key);
+        if (order == 0) {
+            return tree->value;
+        }
+        tree = order < 0 ? tree->left : tree->right;
+    }
+    return NULL;
+}
+
+static Node *tree_insert(Node *tree, Value *key, Value *value) {
+    if (tree == NULL) {
+        return node(key, value, NULL, NULL);
+    }
+    int order = compare(key, tree->key);
+    if (order < 0) {
+        return balance(tree->key, tree->value, tree_insert(tree->left, key, value), tree->right);
+    } else if (order > 0) {
+        return balance(tree->key, tree->value, tree->left, tree_insert(tree->right, key, value));
+    }
+    return node(key, value, tree->left, tree->right);
+}
+
+static Node *tree_remove_min(Node *tree, Node **min) {
+    if (tree->left == NULL) {
+        *min = tree;
+        return tree->right;
+    }
+    Node *left = tree_remove_min(tree->left, min);
+    return balance(tree->key, tree->value, left, tree->right);
+}
+
+static Node *tree_remove(Node *tree, Value *key) {
+    if (tree == NULL) {
+        return NULL;
+    }
+    int order = compare(key, tree->key);
+    if (order < 0) {
+        return balance(tree->key, tree->value, tree_remove(tree->left, key), tree->right);
+    } else if (order > 0) {
+        return balance(tree->key, tree->value, tree->left, tree_remove(tree->right, key));
+    } else if (tree->right == NULL) {
+        return tree->left;
+    }
+    Node *min;
+    Node *right = tree_remove_min(tree->right, &min);
+    return balance(min->key, min->value, tree->left, right);
+}
+
+/* Stores the nodes of `tree` in order from `nodes[i]` on, and returns the
+ * index after the last one. */
+static size_t flatten(Node *tree, Node **nodes, size_t i) {
+    if (tree == NULL) {
+        return i;
+    }
+    i = flatten(tree->left, nodes, i);
+    nodes[i++] = tree;
+    return flatten(tree->right, nodes, i);
+}
+
+static Node **nodes_of(Node *tree) {
+    Node **nodes = alloc((size(tree) + 1) * sizeof(Node *));
+    flatten(tree, nodes, 0);
+    return nodes;
+}
+
+static Node *tree_union(Node *tree, Node *other) {
+    Node **nodes = nodes_of(other);
+    for (size_t i = 0; i < size(other); i++) {
+        if (tree_get(tree, nodes[i]->key) == NULL) {
+            tree = tree_insert(tree, nodes[i]->key, nodes[i]->value);
+        }
+    }
+    free(nodes);
+    return tree;
+}
+
+static Value *tree_value(Tag tag, Node *tree) {
+    Value *value = make(tag);
+    value->as.tree = tree;
+    return value;
+}
+
+/* Equality and order, see `Value`'s `PartialEq` and `tree::compare`. */
+
+static size_t length(Cell *cells) {
+    size_t len = 0;
+    for (; cells != NULL; cells = cells->tail) {
+        len++;
+    }
+    return len;
+}
+
+static int equal(Value *x, Value *y);
+
+static int equal_all(size_t len, Value **xs, Value **ys) {
+    for (size_t i = 0; i < len; i++) {
+        if (!equal(xs[i], ys[i])) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+static int equal(Value *x, Value *y) {
+    if (x->tag != y->tag) {
+        return 0;
+    }
+    switch (x->tag) {
+    case VOID:
+        return 1;
+    case INT:
+        return x->as.i == y->as.i;
+    case BOOL:
+        return x->as.b == y->as.b;
+    case CHAR:
+        return x->as.c == y->as.c;
+    case LIST: {
+        Cell *xs = x->as.list, *ys = y->as.list;
+        for (; xs != NULL && ys != NULL; xs = xs->tail, ys = ys->tail) {
+            if (!equal(xs->head, ys->head)) {
+                return 0;
+            }
+        }
+        return xs == NULL && ys == NULL;
+    }
+    /* Functions are never equal, nor are operations with a default. */
+    case LAMBDA:
+    case NATIVE:
+        return 0;
+    case OPERATION:
+        return x->as.operation.fallback == NULL && y->as.operation.fallback == NULL
+            && strcmp(x->as.operation.name, y->as.operation.name) == 0;
+    case CONTINUATION:
+        return x->as.handler == y->as.handler;
+    case DATA:
+    case CONSTRUCTOR:
+        return strcmp(x->as.data.name, y->as.data.name) == 0
+            && x->as.data.arity == y->as.data.arity
+            && x->as.data.count == y->as.data.count
+            && equal_all(x->as.data.count, x->as.data.fields, y->as.data.fields);
+    case ARRAY:
+        return x->as.array.len == y->as.array.len
+            && equal_all(x->as.array.len, x->as.array.elems, y->as.array.elems);
+    case MAP:
+    case SET: {
+        if (size(x->as.tree) != size(y->as.tree)) {
+            return 0;
+        }
+        Node **xs = nodes_of(x->as.tree), **ys = nodes_of(y->as.tree);
+        int same = 1;
+        for (size_t i = 0; same && i < size(x->as.tree); i++) {
+            same = equal(xs[i]->key, ys[i]->key) && equal(xs[i]->value, ys[i]->value);
+        }
+        free(xs);
+        free(ys);
+        return same;
+    }
+    }
+    return 0;
+}
+
+/* Whether a value can be ordered, which is the case unless it holds a
+ * function. */
+static int comparable(Value *value) {
+    switch (value->tag) {
+    case VOID:
+    case INT:
+    case BOOL:
+    case CHAR:
+        return 1;
+    case LIST:
+        for (Cell *cell = value->as.list; cell != NULL; cell = cell->tail) {
+            if (!comparable(cell->head)) {
+                return 0;
+            }
+        }
+        return 1;
+    case ARRAY:
+        for (size_t i = 0; i < value->as.array.len; i++) {
+            if (!comparable(value->as.array.elems[i])) {
+                return 0;
+            }
+        }
+        return 1;
+    case DATA:
+        for (int i = 0; i < value->as.data.count; i++) {
+            if (!comparable(value->as.data.fields[i])) {
+                return 0;
+            }
+        }
+        return 1;
+    case MAP:
+    case SET: {
+        Node **nodes = nodes_of(value->as.tree);
+        int all = 1;
+        for (size_t i = 0; all && i < size(value->as.tree); i++) {
+            all = comparable(nodes[i]->key) && comparable(nodes[i]->value);
+        }
+        free(nodes);
+        return all;
+    }
+    default:
+        return 0;
+    }
+}
+
+static int order(int64_t x, int64_t y) {
+    return (x > y) - (x < y);
+}
+
+static int compare_all(size_t xlen, Value **xs, size_t ylen, Value **ys) {
+    for (size_t i = 0; i < xlen && i < ylen; i++) {
+        int ordering = compare(xs[i], ys[i]);
+        if (ordering != 0) {
+            return ordering;
+        }
+    }
+    return order((int64_t)xlen, (int64_t)ylen);
+}
+
+static Value **bindings(Node *tree) {
+    Node **nodes = nodes_of(tree);
+    Value **values = alloc((2 * size(tree) + 1) * sizeof(Value *));
+    for (size_t i = 0; i < size(tree); i++) {
+        values[2 * i] = nodes[i]->key;
+        values[2 * i + 1] = nodes[i]->value;
+    }
+    free(nodes);
+    return values;
+}
+
+/* The structural order of two comparable values of the same type. */
+static int compare(Value *x, Value *y) {
+    if (x->tag != y->tag) {
+        panic("chimera: cannot compare values of different types");
+    }
+    switch (x->tag) {
+    case VOID:
+        return 0;
+    case INT:
+        return order(x->as.i, y->as.i);
+    case BOOL:
+        return order(x->as.b, y->as.b);
+    case CHAR:
+        return order(x->as.c, y->as.c);
+    case LIST: {
+        Cell *xs = x->as.list, *ys = y->as.list;
+        for (; xs != NULL && ys != NULL; xs = xs->tail, ys = ys->tail) {
+            int ordering = compare(xs->head, ys->head);
+            if (ordering != 0) {
+                return ordering;
+            }
+        }
+        return (xs != NULL) - (ys != NULL);
+    }
+    case ARRAY:
+        return compare_all(x->as.array.len, x->as.array.elems,
+                           y->as.array.len, y->as.array.elems);
+    case DATA: {
+        int ordering = strcmp(x->as.data.name, y->as.data.name);
+        if (ordering != 0) {
+            return (ordering > 0) - (ordering < 0);
+        }
+        return compare_all((size_t)x->as.data.count, x->as.data.fields,
+                           (size_t)y->as.data.count, y->as.data.fields);
+    }
+    case MAP:
+    case SET: {
+        Value **xs = bindings(x->as.tree), **ys = bindings(y->as.tree);
+        int ordering = compare_all(2 * size(x->as.tree), xs, 2 * size(y->as.tree), ys);
+        free(xs);
+        free(ys);
+        return ordering;
+    }
+    default:
+        panic("chimera: cannot compare functions");
+    }
+}
+
+/* How values are printed, see `Value`'s `Display`. */
+
+static void show(Buffer *buffer, Value *value);
+
+static void show_tree(Buffer *buffer, Node *tree, int values) {
+    Node **nodes = nodes_of(tree);
+    append_string(buffer, "{");
+    for (size_t i = 0; i < size(tree); i++) {
+        if (i > 0) {
+            append_string(buffer, ", ");
+        }
+        show(buffer, nodes[i]->key);
+        if (values) {
+            append_string(buffer, ": ");
+            show(buffer, nodes[i]->value);
+        }
+    }
+    append_string(buffer, "}");
+    free(nodes);
+}
+
+static void show(Buffer *buffer, Value *value) {
+    char number[32];
+    switch (value->tag) {
+    case VOID:
+        append_string(buffer, "()");
+        break;
+    case INT:
+        snprintf(number, sizeof(number), "%" PRId64, value->as.i);
+        append_string(buffer, number);
+        break;
+    case BOOL:
+        append_string(buffer, value->as.b ? "true" : "false");
+        break;
+    case CHAR:
+        append_char(buffer, value->as.c);
+        break;
+    case LIST:
+        for (Cell *cell = value->as.list; cell != NULL; cell = cell->tail) {
+            show(buffer, cell->head);
+        }
+        break;
+    /* The interpreter debug-prints functions, with their environment. */
+    case LAMBDA:
+    case NATIVE:
+        append_string(buffer, "<function>");
+        break;
+    case CONTINUATION:
+        snprintf(number, sizeof(number), "%zu", value->as.handler);
+        append_string(buffer, "Continuation {\n    handler: ");
+        append_string(buffer, number);
+        append_string(buffer, ",\n}");
+        break;
+    case OPERATION:
+        append_string(buffer, value->as.operation.name);
+        break;
+    case DATA:
+        append_string(buffer, value->as.data.name);
+        for (int i = 0; i < value->as.data.count; i++) {
+            Value *field = value->as.data.fields[i];
+            if (field->tag == DATA && field->as.data.count > 0) {
+                append_string(buffer, " (");
+                show(buffer, field);
+                append_string(buffer, ")");
+            } else {
+                append_string(buffer, " ");
+                show(buffer, field);
+            }
+        }
+        break;
+    case CONSTRUCTOR:
+        append_string(buffer, value->as.data.name);
+        break;
+    case ARRAY:
+        append_string(buffer, "#[");
+        for (size_t i = 0; i < value->as.array.len; i++) {
+            if (i > 0) {
+                append_string(buffer, ", ");
+            }
+            show(buffer, value->as.array.elems[i]);
+        }
+        append_string(buffer, "]");
+        break;
+    case MAP:
+        show_tree(buffer, value->as.tree, 1);
+        break;
+    case SET:
+        show_tree(buffer, value->as.tree, 0);
+        break;
+    }
+}
+
+static char *to_string(Value *value) {
+    Buffer buffer = { NULL, 0, 0 };
+    append(&buffer, "", 0);
+    show(&buffer, value);
+    return buffer.data;
+}
+
+/* Effect handlers, see `effect.rs`. The clauses of a handler run right where
+ * the operation is performed, outside of the handlers installed since. */
+
+typedef Outcome (*ClauseCode)(Frame *closure, Value *param, Value *resume);
+
+typedef struct {
+    const char *operation;
+    ClauseCode code;
+} Clause;
+
+typedef struct {
+    size_t id;
+    const Clause *clauses;
+    size_t count;
+    Frame *env;
+} Handler;
+
+static Handler *handlers;
+static size_t handlers_len, handlers_cap, next_handler;
+
+static void push_handlers(const Handler *pushed, size_t count) {
+    if (handlers_len + count > handlers_cap) {
+        handlers_cap = 2 * (handlers_len + count);
+        handlers = realloc(handlers, handlers_cap * sizeof(Handler));
+        if (handlers == NULL) {
+            panic("chimera: out of memory");
+        }
+    }
+    memcpy(handlers + handlers_len, pushed, count * sizeof(Handler));
+    handlers_len += count;
+}
+
+static const Clause *find_clause(const Handler *handler, const char *operation) {
+    for (size_t i = 0; i < handler->count; i++) {
+        if (strcmp(handler->clauses[i].operation, operation) == 0) {
+            return &handler->clauses[i];
+        }
+    }
+    return NULL;
+}
+
+/* Runs `body` with `clauses` handling the operations it performs. */
+static Outcome handle(const Clause *clauses, size_t count, Frame *env, Outcome (*body)(Frame *)) {
+    Handler handler = { next_handler++, clauses, count, env };
+    push_handlers(&handler, 1);
+    Outcome outcome = body(env);
+    handlers_len--;
+    if (outcome.status == ABORT && outcome.handler == handler.id) {
+        return ok(outcome.value);
+    }
+    return outcome;
+}
+
+static Outcome apply(Value *function, Value *arg);
+
+static Outcome perform(const char *name, Value *arg, Value *fallback) {
+    size_t index = handlers_len;
+    while (index > 0 && find_clause(&handlers[index - 1], name) == NULL) {
+        index--;
+    }
+    if (index == 0) {
+        if (fallback != NULL) {
+            return apply(fallback, arg);
+        }
+        Buffer buffer = { NULL, 0, 0 };
+        append_string(&buffer, "unhandled effect operation `");
+        append_string(&buffer, name);
+        append_string(&buffer, "`");
+        return fault(buffer.data);
+    }
+    index--;
+    size_t count = handlers_len - index;
+    Handler *inner = alloc(count * sizeof(Handler));
+    memcpy(inner, handlers + index, count * sizeof(Handler));
+    handlers_len = index;
+    Value *resume = make(CONTINUATION);
+    resume->as.handler = inner[0].id;
+    Outcome outcome = find_clause(&inner[0], name)->code(inner[0].env, arg, resume);
+    push_handlers(inner, count);
+    size_t id = inner[0].id;
+    free(inner);
+    if (outcome.status == RESUME && outcome.handler == id) {
+        return ok(outcome.value);
+    } else if (outcome.status == OK) {
+        return (Outcome){ ABORT, outcome.value, id, 0 };
+    }
+    return outcome;
+}
+
+/* Applies a function to `arg`, which is either a lambda, a native, an
+ * operation that is performed, a continuation, or a constructor. */
+static Outcome apply(Value *function, Value *arg) {
+    switch (function->tag) {
+    case LAMBDA:
+        return function->as.lambda.code(function->as.lambda.closure, arg);
+    case NATIVE: {
+        const Native *native = function->as.native.native;
+        int applied = function->as.native.applied;
+        Value **args = alloc((size_t)(applied + 1) * sizeof(Value *));
+        if (applied > 0) {
+            memcpy(args, function->as.native.args, (size_t)applied * sizeof(Value *));
+        }
+        args[applied] = arg;
+        if (applied + 1 == native->arity) {
+            return native->code(args);
+        }
+        Value *partial = make(NATIVE);
+        partial->as.native.native = native;
+        partial->as.native.applied = applied + 1;
+        partial->as.native.args = args;
+        return ok(partial);
+    }
+    case OPERATION:
+        return perform(function->as.operation.name, arg, function->as.operation.fallback);
+    case CONTINUATION:
+        return (Outcome){ RESUME, arg, function->as.handler, 0 };
+    case CONSTRUCTOR: {
+        int count = function->as.data.count;
+        Value **fields = alloc((size_t)(count + 1) * sizeof(Value *));
+        if (count > 0) {
+            memcpy(fields, function->as.data.fields, (size_t)count * sizeof(Value *));
+        }
+        fields[count] = arg;
+        Value *value = make(count + 1 == function->as.data.arity ? DATA : CONSTRUCTOR);
+        value->as.data.name = function->as.data.name;
+        value->as.data.arity = function->as.data.arity;
+        value->as.data.count = count + 1;
+        value->as.data.fields = fields;
+        return ok(value);
+    }
+    default:
+        panic("chimera: applied a value that is not a function");
+    }
+}
+
+/* The result of an integer operation, which raises `Overflow` if it
+ * `overflowed`. */
+static Outcome arithmetic(int overflowed, int64_t result) {
+    if (overflowed) {
+        return raise(data("Overflow", 0, NULL));
+    }
+    return ok(integer(result));
+}
+
+static Outcome negate(Value *value) {
+    int64_t result;
+    int overflowed = __builtin_sub_overflow(0, value->as.i, &result);
+    return arithmetic(overflowed, result);
+}
+
+/* Whether `value` was built by the constructor `name`. */
+static int is(Value *value, const char *name) {
+    return value->tag == DATA && strcmp(value->as.data.name, name) == 0;
+}
+
+/* The message describing `exception`, as by the prelude's `message`. */
+static char *message(Value *exception) {
+    if (is(exception, "Failure")) {
+        return string(exception->as.data.fields[0]);
+    } else if (is(exception, "DivisionByZero")) {
+        return "division by zero";
+    } else if (is(exception, "Overflow")) {
+        return "integer overflow";
+    }
+    return to_string(exception);
+}
+
+static Outcome need(Value *value, const char *name) {
+    if (value == NULL) {
+        Buffer buffer = { NULL, 0, 0 };
+        append_string(&buffer, "`");
+        append_string(&buffer, name);
+        append_string(&buffer, "` is not a defined (mutable) name");
+        return fault(buffer.data);
+    }
+    return ok(value);
+}
+
+static Outcome hole(const char *span) {
+    Buffer buffer = { NULL, 0, 0 };
+    append_string(&buffer, "reached unimplemented hole at ");
+    append_string(&buffer, span);
+    return fault(buffer.data);
+}
+
+static Outcome no_arm(Value *value) {
+    Buffer buffer = { NULL, 0, 0 };
+    append_string(&buffer, "no arm matches the value ");
+    append_string(&buffer, to_string(value));
+    return fault(buffer.data);
+}
+
+/* The natives that `@[intrinsic(name)]` definitions are bound to, see
+ * `attribute.rs`. */
+
+static char **program_args;
+static int program_argc;
+static uint64_t seed;
+static struct timespec started;
+
+static struct timespec clock_now(clockid_t clock) {
+    struct timespec now;
+    clock_gettime(clock, &now);
+    return now;
+}
+
+/* A `Result (List Char) a` value, whose error describes `errno`. */
+static Value *result(Value *value) {
+    Value **fields = alloc(sizeof(Value *));
+    if (value != NULL) {
+        fields[0] = value;
+        return data("Ok", 1, fields);
+    }
+    char message[256];
+    snprintf(message, sizeof(message), "%s (os error %d)", strerror(errno), errno);
+    fields[0] = chars(message);
+    return data("Err", 1, fields);
+}
+
+static Value *key_error(const char *intrinsic) {
+    Buffer buffer = { NULL, 0, 0 };
+    append_string(&buffer, intrinsic);
+    append_string(&buffer, ": functions cannot be compared");
+    return exception(buffer.data);
+}
+
+#define KEY(intrinsic, value)                                                 \
+    if (!comparable(value)) {                                                 \
+        return raise(key_error(intrinsic));                                   \
+    }
+
+static Outcome native_print(Value **args) {
+    char *text = to_string(args[0]);
+    fputs(text, stdout);
+    free(text);
+    return ok(UNIT);
+}
+
+/* Raises the error of reading standard input, or writing standard output,
+ * for `intrinsic`. */
+static Outcome stdio_error(const char *intrinsic) {
+    char message[256];
+    snprintf(message, sizeof(message), "%s: %s (os error %d)", intrinsic, strerror(errno), errno);
+    return fault(message);
+}
+
+static Outcome native_flush(Value **args) {
+    (void)args;
+    if (fflush(stdout) != 0) {
+        return stdio_error("flush");
+    }
+    return ok(UNIT);
+}
+
+static Outcome native_read(Value **args) {
+    (void)args;
+    Buffer buffer = { NULL, 0, 0 };
+    append(&buffer, "", 0);
+    char chunk[4096];
+    size_t len;
+    while ((len = fread(chunk, 1, sizeof(chunk), stdin)) > 0) {
+        append(&buffer, chunk, len);
+    }
+    if (ferror(stdin)) {
+        free(buffer.data);
+        return stdio_error("read");
+    }
+    Value *value = chars_of(buffer.data, buffer.len);
+    free(buffer.data);
+    return ok(value);
+}
+
+static Outcome native_read_line(Value **args) {
+    (void)args;
+    Buffer buffer = { NULL, 0, 0 };
+    append(&buffer, "", 0);
+    int c;
+    while ((c = getchar()) != EOF) {
+        char byte = (char)c;
+        append(&buffer, &byte, 1);
+        if (c == '\n') {
+            break;
+        }
+    }
+    if (ferror(stdin)) {
+        free(buffer.data);
+        return stdio_error("read_line");
+    }
+    if (buffer.len == 0) {
+        free(buffer.data);
+        return ok(none());
+    }
+    if (buffer.len > 0 && buffer.data[buffer.len - 1] == '\n') {
+        buffer.len--;
+        if (buffer.len > 0 && buffer.data[buffer.len - 1] == '\r') {
+            buffer.len--;
+        }
+    }
+    Value *line = chars_of(buffer.data, buffer.len);
+    free(buffer.data);
+    return ok(some(line));
+}
+
+static Outcome native_read_char(Value **args) {
+    (void)args;
+    int first = getchar();
+    if (first == EOF) {
+        return ferror(stdin) ? stdio_error("read_char") : ok(none());
+    }
+    unsigned char bytes[4] = { (unsigned char)first };
+    size_t width = first < 0x80 ? 1 : first >= 0xf0 ? 4 : first >= 0xe0 ? 3 : 2;
+    size_t len = 1;
+    for (int c; len < width && (c = getchar()) != EOF; len++) {
+        bytes[len] = (unsigned char)c;
+    }
+    uint32_t c;
+    decode(bytes, len, &c);
+    return ok(some(character(c)));
+}
+
+static Outcome native_read_bytes(Value **args) {
+    Cell *cells = NULL, **last = &cells;
+    int c;
+    for (int64_t n = args[0]->as.i; n > 0 && (c = getchar()) != EOF; n--) {
+        *last = cons(integer(c), NULL);
+        last = &(*last)->tail;
+    }
+    if (ferror(stdin)) {
+        return stdio_error("read_bytes");
+    }
+    return ok(list(cells));
+}
+
+static Outcome native_end_of_input(Value **args) {
+    (void)args;
+    int c = getchar();
+    if (c == EOF) {
+        return ferror(stdin) ? stdio_error("end_of_input") : ok(boolean(1));
+    }
+    ungetc(c, stdin);
+    return ok(boolean(0));
+}
+
+static Outcome native_cmp(Value **args) {
+    return ok(boolean(equal(args[0], args[1])));
+}
+
+/* Arithmetic raises `Overflow` rather than wrapping around. */
+static Outcome native_add(Value **args) {
+    int64_t result;
+    int overflowed = __builtin_add_overflow(args[0]->as.i, args[1]->as.i, &result);
+    return arithmetic(overflowed, result);
+}
+
+static Outcome native_sub(Value **args) {
+    int64_t result;
+    int overflowed = __builtin_sub_overflow(args[0]->as.i, args[1]->as.i, &result);
+    return arithmetic(overflowed, result);
+}
+
+static Outcome native_mul(Value **args) {
+    int64_t result;
+    int overflowed = __builtin_mul_overflow(args[0]->as.i, args[1]->as.i, &result);
+    return arithmetic(overflowed, result);
+}
+
+static Outcome native_div(Value **args) {
+    int64_t x = args[0]->as.i, y = args[1]->as.i;
+    if (y == 0) {
+        return raise(data("DivisionByZero", 0, NULL));
+    }
+    if (x == INT64_MIN && y == -1) {
+        return raise(data("Overflow", 0, NULL));
+    }
+    return ok(integer(x / y));
+}
+
+/* Unlike the quotient, the remainder of `INT64_MIN` by -1 is defined. */
+static Outcome native_modulus(Value **args) {
+    int64_t x = args[0]->as.i, y = args[1]->as.i;
+    if (y == 0) {
+        return raise(data("DivisionByZero", 0, NULL));
+    }
+    return ok(integer(y == -1 ? 0 : x % y));
+}
+
+static Outcome native_cons(Value **args) {
+    return ok(list(cons(args[0], args[1]->as.list)));
+}
+
+static Outcome native_head(Value **args) {
+    if (args[0]->as.list == NULL) {
+        return fault("head: empty list");
+    }
+    return ok(args[0]->as.list->head);
+}
+
+static Outcome native_tail(Value **args) {
+    if (args[0]->as.list == NULL) {
+        return fault("tail: empty list");
+    }
+    return ok(list(args[0]->as.list->tail));
+}
+
+static Outcome native_raise(Value **args) {
+    return raise(args[0]);
+}
+
+static Outcome native_message(Value **args) {
+    return ok(chars(message(args[0])));
+}
+
+static Outcome native_read_file(Value **args) {
+    FILE *file = fopen(string(args[0]), "r");
+    if (file == NULL) {
+        return ok(result(NULL));
+    }
+    Buffer buffer = { NULL, 0, 0 };
+    append(&buffer, "", 0);
+    char chunk[4096];
+    size_t len;
+    while ((len = fread(chunk, 1, sizeof(chunk), file)) > 0) {
+        append(&buffer, chunk, len);
+    }
+    int failed = ferror(file);
+    fclose(file);
+    if (failed) {
+        free(buffer.data);
+        return ok(result(NULL));
+    }
+    if (!valid_utf8(buffer.data, buffer.len)) {
+        free(buffer.data);
+        Value **fields = alloc(sizeof(Value *));
+        fields[0] = chars("stream did not contain valid UTF-8");
+        return ok(data("Err", 1, fields));
+    }
+    Value *contents = chars_of(buffer.data, buffer.len);
+    free(buffer.data);
+    return ok(result(contents));
+}
+
+static Outcome write_to(Value *path, Value *contents, const char *mode) {
+    FILE *file = fopen(string(path), mode);
+    if (file == NULL) {
+        return ok(result(NULL));
+    }
+    char *text = string(contents);
+    size_t len = strlen(text);
+    int failed = fwrite(text, 1, len, file) < len;
+    failed |= fclose(file) != 0;
+    free(text);
+    return ok(result(failed ? NULL : UNIT));
+}
+
+static Outcome native_write_file(Value **args) {
+    return write_to(args[0], args[1], "w");
+}
+
+static Outcome native_append_file(Value **args) {
+    return write_to(args[0], args[1], "a");
+}
+
+static Outcome native_file_exists(Value **args) {
+    struct stat status;
+    return ok(boolean(stat(string(args[0]), &status) == 0));
+}
+
+static int by_name(const void *x, const void *y) {
+    return strcmp(*(char *const *)x, *(char *const *)y);
+}
+
+static Outcome native_list_dir(Value **args) {
+    DIR *dir = opendir(string(args[0]));
+    if (dir == NULL) {
+        return ok(result(NULL));
+    }
+    char **names = NULL;
+    size_t len = 0;
+    struct dirent *entry;
+    while ((entry = readdir(dir)) != NULL) {
+        if (strcmp(entry->d_name, ".") == 0 || strcmp(entry->d_name, "..") == 0) {
+            continue;
+        }
+        names = realloc(names, (len + 1) * sizeof(char *));
+        names[len++] = strdup(entry->d_name);
+    }
+    closedir(dir);
+    /* The order of the entries is up to the platform otherwise. */
+    qsort(names, len, sizeof(char *), by_name);
+    Value **entries = alloc((len + 1) * sizeof(Value *));
+    for (size_t i = 0; i < len; i++) {
+        entries[i] = chars(names[i]);
+        free(names[i]);
+    }
+    free(names);
+    return ok(result(list_of(len, entries)));
+}
+
+static Outcome native_remove_file(Value **args) {
+    return ok(result(unlink(string(args[0])) == 0 ? UNIT : NULL));
+}
+
+/* Creates the directory `path` along with its missing parents. */
+static int create_dirs(const char *path) {
+    if (*path == '\0' || mkdir(path, 0777) == 0) {
+        return 0;
+    }
+    if (errno == ENOENT) {
+        const char *slash = strrchr(path, '/');
+        if (slash != NULL && slash != path) {
+            char *parent = strndup(path, (size_t)(slash - path));
+            int failed = create_dirs(parent);
+            free(parent);
+            if (failed) {
+                return -1;
+            }
+            if (mkdir(path, 0777) == 0) {
+                return 0;
+            }
+        }
+    }
+    int error = errno;
+    struct stat status;
+    if (stat(path, &status) == 0 && S_ISDIR(status.st_mode)) {
+        return 0;
+    }
+    errno = error;
+    return -1;
+}
+
+static Outcome native_create_dir(Value **args) {
+    return ok(result(create_dirs(string(args[0])) == 0 ? UNIT : NULL));
+}
+
+static Outcome native_args(Value **args) {
+    (void)args;
+    Value **values = alloc(((size_t)program_argc + 1) * sizeof(Value *));
+    for (int i = 0; i < program_argc; i++) {
+        values[i] = chars(program_args[i]);
+    }
+    return ok(list_of((size_t)program_argc, values));
+}
+
+static Outcome native_get_env(Value **args) {
+    const char *value = getenv(string(args[0]));
+    return ok(value == NULL ? none() : some(chars(value)));
+}
+
+static Outcome native_set_env(Value **args) {
+    setenv(string(args[0]), string(args[1]), 1);
+    return ok(UNIT);
+}
+
+/* The status must fit in the byte of it that every system keeps. */
+static Outcome native_exit(Value **args) {
+    int64_t code = args[0]->as.i;
+    if (code < 0 || code > 255) {
+        char message[64];
+        snprintf(message, sizeof message,
+                 "the exit status %" PRId64 " is not between 0 and 255", code);
+        return fault(message);
+    }
+    return (Outcome){ EXIT, NULL, 0, (int)code };
+}
+
+/* SplitMix64, see https://prng.di.unimi.it/splitmix64.c */
+static uint64_t random_next(void) {
+    uint64_t z = seed += UINT64_C(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)) * UINT64_C(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)) * UINT64_C(0x94d049bb133111eb);
+    return z ^ (z >> 31);
+}
+
+/* The high half of the 128-bit product of `x` and `y`. */
+static uint64_t multiply_high(uint64_t x, uint64_t y) {
+    uint64_t xl = x & 0xffffffff, xh = x >> 32, yl = y & 0xffffffff, yh = y >> 32;
+    uint64_t low = xl * yl, middle1 = xh * yl, middle2 = xl * yh;
+    uint64_t carry = ((low >> 32) + (middle1 & 0xffffffff) + (middle2 & 0xffffffff)) >> 32;
+    return xh * yh + (middle1 >> 32) + (middle2 >> 32) + carry;
+}
+
+/* A random integer between `lo` and `hi`, both included, scaled rather
+ * than taken modulo. */
+static int64_t random_int(int64_t lo, int64_t hi) {
+    uint64_t span = (uint64_t)hi - (uint64_t)lo;
+    uint64_t random = random_next();
+    uint64_t offset = span == UINT64_MAX ? random : multiply_high(random, span + 1);
+    return (int64_t)((uint64_t)lo + offset);
+}
+
+static Outcome native_random_int(Value **args) {
+    if (args[0]->as.i > args[1]->as.i) {
+        return fault("random_int: empty range");
+    }
+    return ok(integer(random_int(args[0]->as.i, args[1]->as.i)));
+}
+
+static Outcome native_shuffle(Value **args) {
+    size_t len = length(args[0]->as.list);
+    Value **elems = alloc((len + 1) * sizeof(Value *));
+    size_t i = 0;
+    for (Cell *cell = args[0]->as.list; cell != NULL; cell = cell->tail) {
+        elems[i++] = cell->head;
+    }
+    for (i = len; i > 1; i--) {
+        size_t j = (size_t)random_int(0, (int64_t)(i - 1));
+        Value *swapped = elems[i - 1];
+        elems[i - 1] = elems[j];
+        elems[j] = swapped;
+    }
+    return ok(list_of(len, elems));
+}
+
+static Outcome native_now_millis(Value **args) {
+    (void)args;
+    struct timespec now = clock_now(CLOCK_REALTIME);
+    return ok(integer((int64_t)now.tv_sec * 1000 + now.tv_nsec / 1000000));
+}
+
+static Outcome native_monotonic_millis(Value **args) {
+    (void)args;
+    struct timespec now = clock_now(CLOCK_MONOTONIC);
+    int64_t millis = ((int64_t)now.tv_sec - (int64_t)started.tv_sec) * 1000
+        + (now.tv_nsec - started.tv_nsec) / 1000000;
+    return ok(integer(millis));
+}
+
+static Outcome native_sleep(Value **args) {
+    int64_t millis = args[0]->as.i > 0 ? args[0]->as.i : 0;
+    struct timespec duration = { (time_t)(millis / 1000), (long)(millis % 1000) * 1000000 };
+    while (nanosleep(&duration, &duration) != 0 && errno == EINTR) {
+    }
+    return ok(UNIT);
+}
+
+/* The `Int` value `index`, if it is below `len`. */
+static int in_bounds(Value *index, size_t len) {
+    return index->as.i >= 0 && (uint64_t)index->as.i < len;
+}
+
+static Value *array_copy(Value *array, size_t extra) {
+    Value *copy = array_of(array->as.array.len, array->as.array.elems);
+    if (extra > 0) {
+        copy->as.array.elems = realloc(copy->as.array.elems,
+                                       (copy->as.array.len + extra) * sizeof(Value *));
+    }
+    return copy;
+}
+
+static Outcome native_array_get(Value **args) {
+    if (!in_bounds(args[0], args[1]->as.array.len)) {
+        return fault("array_get: index out of bounds");
+    }
+    return ok(args[1]->as.array.elems[args[0]->as.i]);
+}
+
+static Outcome native_array_set(Value **args) {
+    if (!in_bounds(args[0], args[2]->as.array.len)) {
+        return fault("array_set: index out of bounds");
+    }
+    Value *array = array_copy(args[2], 0);
+    array->as.array.elems[args[0]->as.i] = args[1];
+    return ok(array);
+}
+
+static Outcome native_array_push(Value **args) {
+    Value *array = array_copy(args[1], 1);
+    array->as.array.elems[array->as.array.len++] = args[0];
+    return ok(array);
+}
+
+static Outcome native_array_slice(Value **args) {
+    Value *array = args[2];
+    size_t len = array->as.array.len;
+    if (!in_bounds(args[0], len + 1) || !in_bounds(args[1], len + 1)
+        || args[0]->as.i > args[1]->as.i) {
+        return fault("array_slice: index out of bounds");
+    }
+    size_t start = (size_t)args[0]->as.i, end = (size_t)args[1]->as.i;
+    return ok(array_of(end - start, array->as.array.elems + start));
+}
+
+static Outcome native_array_len(Value **args) {
+    return ok(integer((int64_t)args[0]->as.array.len));
+}
+
+static Outcome native_array_of_list(Value **args) {
+    size_t len = length(args[0]->as.list);
+    Value *array = array_of(len, NULL);
+    size_t i = 0;
+    for (Cell *cell = args[0]->as.list; cell != NULL; cell = cell->tail) {
+        array->as.array.elems[i++] = cell->head;
+    }
+    return ok(array);
+}
+
+static Outcome native_array_to_list(Value **args) {
+    return ok(list_of(args[0]->as.array.len, args[0]->as.array.elems));
+}
+
+static Outcome native_empty_map(Value **args) {
+    (void)args;
+    return ok(tree_value(MAP, NULL));
+}
+
+static Outcome native_map_insert(Value **args) {
+    KEY("map_insert", args[0]);
+    return ok(tree_value(MAP, tree_insert(args[2]->as.tree, args[0], args[1])));
+}
+
+static Outcome native_map_remove(Value **args) {
+    KEY("map_remove", args[0]);
+    return ok(tree_value(MAP, tree_remove(args[1]->as.tree, args[0])));
+}
+
+static Outcome native_map_get(Value **args) {
+    KEY("map_get", args[0]);
+    Value *value = tree_get(args[1]->as.tree, args[0]);
+    return ok(value == NULL ? none() : some(value));
+}
+
+static Outcome native_map_contains(Value **args) {
+    KEY("map_contains", args[0]);
+    return ok(boolean(tree_get(args[1]->as.tree, args[0]) != NULL));
+}
+
+/* The keys of a tree, or its values. */
+static Value *tree_list(Node *tree, int values) {
+    Node **nodes = nodes_of(tree);
+    Value **elems = alloc((size(tree) + 1) * sizeof(Value *));
+    for (size_t i = 0; i < size(tree); i++) {
+        elems[i] = values ? nodes[i]->value : nodes[i]->key;
+    }
+    free(nodes);
+    return list_of(size(tree), elems);
+}
+
+static Outcome native_map_keys(Value **args) {
+    return ok(tree_list(args[0]->as.tree, 0));
+}
+
+static Outcome native_map_values(Value **args) {
+    return ok(tree_list(args[0]->as.tree, 1));
+}
+
+static Outcome native_map_size(Value **args) {
+    return ok(integer((int64_t)size(args[0]->as.tree)));
+}
+
+static Outcome native_map_union(Value **args) {
+    return ok(tree_value(MAP, tree_union(args[0]->as.tree, args[1]->as.tree)));
+}
+
+static Outcome native_empty_set(Value **args) {
+    (void)args;
+    return ok(tree_value(SET, NULL));
+}
+
+static Outcome native_set_insert(Value **args) {
+    KEY("set_insert", args[0]);
+    return ok(tree_value(SET, tree_insert(args[1]->as.tree, args[0], UNIT)));
+}
+
+static Outcome native_set_remove(Value **args) {
+    KEY("set_remove", args[0]);
+    return ok(tree_value(SET, tree_remove(args[1]->as.tree, args[0])));
+}
+
+static Outcome native_set_contains(Value **args) {
+    KEY("set_contains", args[0]);
+    return ok(boolean(tree_get(args[1]->as.tree, args[0]) != NULL));
+}
+
+static Outcome native_set_elements(Value **args) {
+    return ok(tree_list(args[0]->as.tree, 0));
+}
+
+static Outcome native_set_size(Value **args) {
+    return ok(integer((int64_t)size(args[0]->as.tree)));
+}
+
+static Outcome native_set_union(Value **args) {
+    return ok(tree_value(SET, tree_union(args[0]->as.tree, args[1]->as.tree)));
+}
+
+static const Native NATIVES[] = {
+    { "print", 1, native_print },
+    { "flush", 1, native_flush },
+    { "read", 1, native_read },
+    { "read_line", 1, native_read_line },
+    { "read_char", 1, native_read_char },
+    { "read_bytes", 1, native_read_bytes },
+    { "end_of_input", 1, native_end_of_input },
+    { "cmp", 2, native_cmp },
+    { "add", 2, native_add },
+    { "sub", 2, native_sub },
+    { "mul", 2, native_mul },
+    { "div", 2, native_div },
+    { "modulus", 2, native_modulus },
+    { "cons", 2, native_cons },
+    { "head", 1, native_head },
+    { "tail", 1, native_tail },
+    { "raise", 1, native_raise },
+    { "message", 1, native_message },
+    { "read_file", 1, native_read_file },
+    { "write_file", 2, native_write_file },
+    { "append_file", 2, native_append_file },
+    { "file_exists", 1, native_file_exists },
+    { "list_dir", 1, native_list_dir },
+    { "remove_file", 1, native_remove_file },
+    { "create_dir", 1, native_create_dir },
+    { "args", 1, native_args },
+    { "get_env", 1, native_get_env },
+    { "set_env", 2, native_set_env },
+    { "exit", 1, native_exit },
+    { "random_int", 2, native_random_int },
+    { "shuffle", 1, native_shuffle },
+    { "now_millis", 1, native_now_millis },
+    { "monotonic_millis", 1, native_monotonic_millis },
+    { "sleep", 1, native_sleep },
+    { "array_get", 2, native_array_get },
+    { "array_set", 3, native_array_set },
+    { "array_push", 2, native_array_push },
+    { "array_slice", 3, native_array_slice },
+    { "array_len", 1, native_array_len },
+    { "array_of_list", 1, native_array_of_list },
+    { "array_to_list", 1, native_array_to_list },
+    { "empty_map", 0, native_empty_map },
+    { "map_insert", 3, native_map_insert },
+    { "map_remove", 2, native_map_remove },
+    { "map_get", 2, native_map_get },
+    { "map_contains", 2, native_map_contains },
+    { "map_keys", 1, native_map_keys },
+    { "map_values", 1, native_map_values },
+    { "map_size", 1, native_map_size },
+    { "map_union", 2, native_map_union },
+    { "empty_set", 0, native_empty_set },
+    { "set_insert", 2, native_set_insert },
+    { "set_remove", 2, native_set_remove },
+    { "set_contains", 2, native_set_contains },
+    { "set_elements", 1, native_set_elements },
+    { "set_size", 1, native_set_size },
+    { "set_union", 2, native_set_union },
+};
+
+/* The value of the native `name`, a constant if its arity is zero. */
+static Outcome intrinsic(const char *name) {
+    for (size_t i = 0; i < sizeof(NATIVES) / sizeof(NATIVES[0]); i++) {
+        if (strcmp(NATIVES[i].name, name) == 0) {
+            if (NATIVES[i].arity == 0) {
+                return NATIVES[i].code(NULL);
+            }
+            Value *value = make(NATIVE);
+            value->as.native.native = &NATIVES[i];
+            return ok(value);
+        }
+    }
+    panic("chimera: unknown intrinsic attribute %s", name);
+}
+
+/* The operation `name` of an effect, implemented by the native `fallback`
+ * when there is no handler for it. */
+static Outcome operation(const char *name, const char *fallback) {
+    Value *value = make(OPERATION);
+    value->as.operation.name = name;
+    if (fallback != NULL) {
+        TRY(native, intrinsic(fallback));
+        value->as.operation.fallback = native;
+    }
+    return ok(value);
+}
+
+/* Running the program. */
+
+typedef struct {
+    Outcome (*items)(Frame *);
+    size_t globals;
+    long main;
+    int status;
+} Program;
+
+/* Runs the items of the program, then calls `main` with `()` if it is a
+ * function. The program exits with the status `main` returns, if it is an
+ * `Int`, or with 1 if something went wrong. */
+static void *run(void *argument) {
+    Program *program = argument;
+    Frame *globals = frame(NULL, program->globals);
+    Outcome outcome = program->items(globals);
+    if (outcome.status == OK && program->main >= 0 && globals->slots[program->main] != NULL) {
+        Value *main = globals->slots[program->main];
+        outcome = main->tag == LAMBDA || main->tag == NATIVE ? apply(main, UNIT) : ok(main);
+    }
+    if (outcome.status == OK && outcome.value->tag == INT) {
+        outcome = native_exit(&outcome.value);
+    }
+    switch (outcome.status) {
+    case OK:
+        program->status = 0;
+        break;
+    case EXIT:
+        program->status = outcome.code;
+        break;
+    case RAISE:
+        fflush(stdout);
+        fprintf(stderr, "Error: uncaught exception: %s\n", message(outcome.value));
+        program->status = 1;
+        break;
+    case RESUME:
+        fflush(stdout);
+        fprintf(stderr, "Error: resumed an operation outside of its handler\n");
+        program->status = 1;
+        break;
+    case ABORT:
+        fflush(stdout);
+        fprintf(stderr, "Error: returned from a handler that is not running\n");
+        program->status = 1;
+        break;
+    }
+    return NULL;
+}
+
+/* The stack programs run on, as deep as the interpreter's. */
+#define STACK_SIZE ((size_t)1 << 30)
+
+static int start(int argc, char **argv, Outcome (*items)(Frame *), size_t globals, long main) {
+    Program program = { items, globals, main, 0 };
+    program_args = argv + 1;
+    program_argc = argc - 1;
+    started = clock_now(CLOCK_MONOTONIC);
+    struct timespec now = clock_now(CLOCK_REALTIME);
+    seed = (uint64_t)now.tv_sec * 1000000000 + (uint64_t)now.tv_nsec;
+    /* Stdout is line buffered, like the interpreter's. */
+    setvbuf(stdout, NULL, _IOLBF, 0);
+    pthread_attr_t attributes;
+    pthread_t thread;
+    pthread_attr_init(&attributes);
+    pthread_attr_setstacksize(&attributes, STACK_SIZE);
+    if (pthread_create(&thread, &attributes, run, &program) != 0) {
+        run(&program);
+    } else {
+        pthread_join(thread, NULL);
+    }
+    fflush(stdout);
+    return program.status;
+}
+
+/* The compiled program. */
@@ -0,0 +1,215 @@
+/// Limits on the resources a program may use, so that untrusted code can be
+/// run without hanging or crashing its host. The program is halted as soon as
+/// it goes over one of them, with an `Unwind::Halt` that it can't catch.
+/// Every interpreter has limits of its own, which it installs here for the
+/// code it runs.
+use std::cell::Cell;
+use std::hint;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::code::Unwind;
+use crate::error::Limit;
+
+/// The recursion depth allowed by default.
+pub const DEFAULT_DEPTH: usize = 100_000;
+
+/// The stack allowed by default, in bytes: half of what a thread spawned
+/// by the standard library gets, which leaves room for the host. How deep
+/// a program can recurse within it depends on its functions and on how the
+/// interpreter was built, hence a limit in bytes as well as the depth.
+pub const DEFAULT_STACK: usize = 1 << 20;
+
+/// The resources a program may use, counted afresh every time the host
+/// runs some code. Steps are function applications, and values are counted
+/// as they are created, whether or not they are still alive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// The number of steps the program may take.
+    pub fuel: Option<u64>,
+    /// How deeply function applications may be nested.
+    pub depth: usize,
+    /// How many bytes of the stack the program may use, which must be less
+    /// than the thread it runs on has left.
+    pub stack: usize,
+    /// The number of values the program may create.
+    pub values: Option<u64>,
+    /// How long the program may run for.
+    pub timeout: Option<Duration>,
+    /// Whether the program may read its input, files and environment,
+    /// or write to files. Denying it doesn't prevent printing.
+    pub io: bool,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            fuel: None,
+            depth: DEFAULT_DEPTH,
+            stack: DEFAULT_STACK,
+            values: None,
+            timeout: None,
+            io: true,
+        }
+    }
+}
+
+thread_local! {
+    static LIMITS: Cell<Limits> = Cell::new(Limits::default());
+    static STEPS: Cell<u64> = const { Cell::new(0) };
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static VALUES: Cell<u64> = const { Cell::new(0) };
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+    // Where the stack was when the host started running code, if it has.
+    static BASE: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+// Reading the clock at every step would be too slow.
+const CLOCK_PERIOD: u64 = 1024;
+
+pub fn set_limits(limits: Limits) {
+    LIMITS.with(|l| l.set(limits));
+}
+
+/// Starts counting the resources used by some code the host runs.
+pub fn start() {
+    let timeout = LIMITS.with(Cell::get).timeout;
+    STEPS.with(|s| s.set(0));
+    VALUES.with(|v| v.set(0));
+    DEADLINE.with(|d| d.set(timeout.map(|timeout| Instant::now() + timeout)));
+    // A native calling back into an interpreter runs on what is left of the
+    // stack of the code that called it.
+    if DEPTH.with(Cell::get) == 0 {
+        BASE.with(|b| b.set(Some(stack_pointer())));
+    }
+}
+
+/// Runs the body of a function as one more step, nested in the current one.
+pub fn step<T>(body: impl FnOnce() -> Result<T, Unwind>) -> Result<T, Unwind> {
+    let limits = LIMITS.with(Cell::get);
+    let steps = STEPS.with(|s| s.get()) + 1;
+    STEPS.with(|s| s.set(steps));
+    match limits.fuel {
+        Some(fuel) if steps > fuel => return halt(Limit::Fuel(fuel)),
+        _ => {}
+    }
+    match limits.values {
+        Some(values) if VALUES.with(Cell::get) > values => return halt(Limit::Values(values)),
+        _ => {}
+    }
+    // `u64::is_multiple_of` is too recent for the toolchains the crate supports.
+    #[allow(clippy::manual_is_multiple_of)]
+    if steps % CLOCK_PERIOD == 0 {
+        check_deadline()?;
+    }
+    nest(body)
+}
+
+/// Runs `body` nested in the current step, without counting a step of its own,
+/// as when resuming what is left of a step, see `effect`.
+pub fn nest<T>(body: impl FnOnce() -> Result<T, Unwind>) -> Result<T, Unwind> {
+    let limits = LIMITS.with(Cell::get);
+    let depth = DEPTH.with(Cell::get);
+    if depth >= limits.depth {
+        return halt(Limit::Depth(limits.depth));
+    }
+    match BASE.with(Cell::get) {
+        Some(base) if base.abs_diff(stack_pointer()) > limits.stack => {
+            return halt(Limit::Stack(limits.stack))
+        }
+        _ => {}
+    }
+    DEPTH.with(|d| d.set(depth + 1));
+    let result = body();
+    DEPTH.with(|d| d.set(depth));
+    result
+}
+
+/// Roughly where the stack is, as the address of a local variable.
+#[inline(never)]
+fn stack_pointer() -> usize {
+    let local = 0u8;
+    hint::black_box(&local) as *const u8 as usize
+}
+
+/// Counts a new value.
+pub fn allocated() {
+    VALUES.with(|v| v.set(v.get() + 1));
+}
+
+/// Fails unless the program may do I/O, which `intrinsic` needs.
+pub fn check_io(intrinsic: &str) -> Result<(), Unwind> {
+    if LIMITS.with(Cell::get).io {
+        Ok(())
+    } else {
+        halt(Limit::Denied(intrinsic.to_string()))
+    }
+}
+
+/// Sleeps for `duration`, or until the program runs out of time.
+pub fn sleep(duration: Duration) -> Result<(), Unwind> {
+    match DEADLINE.with(Cell::get) {
+        Some(deadline) if Instant::now() + duration >= deadline => {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            check_deadline()
+        }
+        _ => {
+            thread::sleep(duration);
+            Ok(())
+        }
+    }
+}
+
+fn check_deadline() -> Result<(), Unwind> {
+    match DEADLINE.with(Cell::get) {
+        Some(deadline) if Instant::now() >= deadline => {
+            halt(Limit::Timeout(LIMITS.with(Cell::get).timeout.unwrap()))
+        }
+        _ => Ok(()),
+    }
+}
+
+fn halt<T>(limit: Limit) -> Result<T, Unwind> {
+    Err(Unwind::Halt { limit })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nest(n: usize) -> Result<usize, Unwind> {
+        match n {
+            0 => Ok(DEPTH.with(Cell::get)),
+            n => step(|| nest(n - 1)),
+        }
+    }
+
+    #[test]
+    fn counted_steps() {
+        set_limits(Limits {
+            fuel: Some(15),
+            depth: 5,
+            ..Limits::default()
+        });
+        start();
+        assert_eq!(nest(5).unwrap(), 5);
+        assert!(matches!(nest(6), Err(Unwind::Halt { limit: Limit::Depth(5) })));
+        // The depth is restored however the steps end.
+        assert_eq!(DEPTH.with(Cell::get), 0);
+        assert!(matches!(nest(5), Err(Unwind::Halt { limit: Limit::Fuel(15) })));
+        start();
+        assert!(nest(5).is_ok());
+    }
+
+    #[test]
+    fn stack() {
+        set_limits(Limits {
+            stack: 4096,
+            ..Limits::default()
+        });
+        start();
+        assert!(nest(1).is_ok());
+        assert!(matches!(nest(1000), Err(Unwind::Halt { limit: Limit::Stack(4096) })));
+        assert_eq!(DEPTH.with(Cell::get), 0);
+    }
+}
@@ -0,0 +1,331 @@
+/// Persistent ordered maps, which back the `Map` and `Set` values. They are
+/// AVL trees whose nodes are shared between versions: inserting or removing
+/// a key copies the O(log n) nodes on its path and nothing else, so the old
+/// map stays valid and cheap to keep around.
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::value::{List, Value, WoValue};
+
+#[derive(Debug, Clone, Default)]
+pub struct Tree(Option<Rc<Node>>);
+
+#[derive(Debug)]
+pub struct Node {
+    key: WoValue,
+    value: WoValue,
+    left: Tree,
+    right: Tree,
+    height: usize,
+    size: usize,
+}
+
+impl Tree {
+    pub fn len(&self) -> usize {
+        self.0.as_ref().map_or(0, |n| n.size)
+    }
+
+    fn height(&self) -> usize {
+        self.0.as_ref().map_or(0, |n| n.height)
+    }
+
+    pub fn get(&self, key: &Value) -> Option<&WoValue> {
+        let mut tree = self;
+        while let Some(node) = &tree.0 {
+            tree = match compare(key, &node.key.borrow()) {
+                Ordering::Less => &node.left,
+                Ordering::Greater => &node.right,
+                Ordering::Equal => return Some(&node.value),
+            };
+        }
+        None
+    }
+
+    /// A copy of the tree where `key` is bound to `value`, in place of the
+    /// value it had if any.
+    pub fn insert(&self, key: WoValue, value: WoValue) -> Tree {
+        match &self.0 {
+            None => Tree::node(key, value, Tree::default(), Tree::default()),
+            Some(node) => match compare(&key.borrow(), &node.key.borrow()) {
+                Ordering::Less => Tree::balance(
+                    node.key.clone(),
+                    node.value.clone(),
+                    node.left.insert(key.clone(), value),
+                    node.right.clone(),
+                ),
+                Ordering::Greater => Tree::balance(
+                    node.key.clone(),
+                    node.value.clone(),
+                    node.left.clone(),
+                    node.right.insert(key.clone(), value),
+                ),
+                Ordering::Equal => {
+                    Tree::node(key.clone(), value, node.left.clone(), node.right.clone())
+                }
+            },
+        }
+    }
+
+    /// A copy of the tree without `key`.
+    pub fn remove(&self, key: &Value) -> Tree {
+        match &self.0 {
+            None => Tree::default(),
+            Some(node) => match compare(key, &node.key.borrow()) {
+                Ordering::Less => Tree::balance(
+                    node.key.clone(),
+                    node.value.clone(),
+                    node.left.remove(key),
+                    node.right.clone(),
+                ),
+                Ordering::Greater => Tree::balance(
+                    node.key.clone(),
+                    node.value.clone(),
+                    node.left.clone(),
+                    node.right.remove(key),
+                ),
+                Ordering::Equal => match node.right.remove_min() {
+                    None => node.left.clone(),
+                    Some((key, value, right)) => {
+                        Tree::balance(key, value, node.left.clone(), right)
+                    }
+                },
+            },
+        }
+    }
+
+    /// The union of both trees, where the bindings of `self` take precedence.
+    pub fn union(&self, other: &Tree) -> Tree {
+        other.iter().fold(self.clone(), |tree, (key, value)| {
+            if tree.get(&key.borrow()).is_some() {
+                tree
+            } else {
+                tree.insert(key.clone(), value.clone())
+            }
+        })
+    }
+
+    /// The bindings of the tree, in increasing order of their keys.
+    pub fn iter(&self) -> Iter<'_> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left(self);
+        iter
+    }
+
+    /// The node at the root of the tree, which may be shared with other trees.
+    pub fn root(&self) -> Option<&Rc<Node>> {
+        self.0.as_ref()
+    }
+
+    pub fn keys(&self) -> List {
+        self.iter().map(|(k, _)| k.clone()).collect::<Vec<_>>().into()
+    }
+
+    pub fn values(&self) -> List {
+        self.iter().map(|(_, v)| v.clone()).collect::<Vec<_>>().into()
+    }
+
+    fn remove_min(&self) -> Option<(WoValue, WoValue, Tree)> {
+        let node = self.0.as_ref()?;
+        Some(match node.left.remove_min() {
+            None => (node.key.clone(), node.value.clone(), node.right.clone()),
+            Some((key, value, left)) => (
+                key,
+                value,
+                Tree::balance(node.key.clone(), node.value.clone(), left, node.right.clone()),
+            ),
+        })
+    }
+
+    fn node(key: WoValue, value: WoValue, left: Tree, right: Tree) -> Tree {
+        Tree(Some(Rc::new(Node {
+            height: 1 + left.height().max(right.height()),
+            size: 1 + left.len() + right.len(),
+            key,
+            value,
+            left,
+            right,
+        })))
+    }
+
+    // Builds a node whose subtrees differ in height by at most 2,
+    // rotating them so that they differ by at most 1.
+    fn balance(key: WoValue, value: WoValue, left: Tree, right: Tree) -> Tree {
+        if left.height() > right.height() + 1 {
+            let l = left.0.as_ref().unwrap();
+            if l.left.height() >= l.right.height() {
+                let right = Tree::node(key, value, l.right.clone(), right);
+                Tree::node(l.key.clone(), l.value.clone(), l.left.clone(), right)
+            } else {
+                let lr = l.right.0.as_ref().unwrap();
+                Tree::node(
+                    lr.key.clone(),
+                    lr.value.clone(),
+                    Tree::node(l.key.clone(), l.value.clone(), l.left.clone(), lr.left.clone()),
+                    Tree::node(key, value, lr.right.clone(), right),
+                )
+            }
+        } else if right.height() > left.height() + 1 {
+            let r = right.0.as_ref().unwrap();
+            if r.right.height() >= r.left.height() {
+                let left = Tree::node(key, value, left, r.left.clone());
+                Tree::node(r.key.clone(), r.value.clone(), left, r.right.clone())
+            } else {
+                let rl = r.left.0.as_ref().unwrap();
+                Tree::node(
+                    rl.key.clone(),
+                    rl.value.clone(),
+                    Tree::node(key, value, left, rl.left.clone()),
+                    Tree::node(r.key.clone(), r.value.clone(), rl.right.clone(), r.right.clone()),
+                )
+            }
+        } else {
+            Tree::node(key, value, left, right)
+        }
+    }
+}
+
+impl Node {
+    /// The binding of the node, then its subtrees.
+    pub fn parts(&self) -> (&WoValue, &WoValue, &Tree, &Tree) {
+        (&self.key, &self.value, &self.left, &self.right)
+    }
+}
+
+/// Trees are equal when they have the same bindings, however they are shaped.
+impl PartialEq for Tree {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .zip(other.iter())
+                .all(|((k1, v1), (k2, v2))| k1 == k2 && v1 == v2)
+    }
+}
+
+pub struct Iter<'a> {
+    stack: Vec<&'a Node>,
+}
+
+impl<'a> Iter<'a> {
+    fn push_left(&mut self, mut tree: &'a Tree) {
+        while let Some(node) = &tree.0 {
+            self.stack.push(node);
+            tree = &node.left;
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a WoValue, &'a WoValue);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left(&node.right);
+        Some((&node.key, &node.value))
+    }
+}
+
+/// Whether a value can be ordered, which is the case unless it holds a function.
+pub fn comparable(value: &Value) -> bool {
+    match value {
+        Value::Void | Value::Int(_) | Value::Bool(_) | Value::Char(_) => true,
+        Value::List(list) => Vec::from(list.clone()).iter().all(|v| comparable(&v.borrow())),
+        Value::Array(elems) => elems.iter().all(|v| comparable(&v.borrow())),
+        Value::Data { fields, .. } => fields.iter().all(|v| comparable(&v.borrow())),
+        Value::Map(tree) | Value::Set(tree) => tree
+            .iter()
+            .all(|(k, v)| comparable(&k.borrow()) && comparable(&v.borrow())),
+        Value::Lambda { .. }
+        | Value::Operation { .. }
+        | Value::Continuation(_)
+        | Value::Constructor { .. } => false,
+    }
+}
+
+/// The structural order of two comparable values of the same type. Lists
+/// and maps are ordered lexicographically, data by constructor then fields.
+pub fn compare(x: &Value, y: &Value) -> Ordering {
+    match (x, y) {
+        (Value::Void, Value::Void) => Ordering::Equal,
+        (Value::Int(x), Value::Int(y)) => x.cmp(y),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (Value::Char(x), Value::Char(y)) => x.cmp(y),
+        (Value::List(x), Value::List(y)) => {
+            compare_all(&Vec::from(x.clone()), &Vec::from(y.clone()))
+        }
+        (Value::Array(x), Value::Array(y)) => compare_all(x, y),
+        (
+            Value::Data {
+                constructor: c1,
+                fields: f1,
+            },
+            Value::Data {
+                constructor: c2,
+                fields: f2,
+            },
+        ) => c1.cmp(c2).then_with(|| compare_all(f1, f2)),
+        (Value::Map(x), Value::Map(y)) | (Value::Set(x), Value::Set(y)) => {
+            let bindings = |t: &Tree| -> Vec<WoValue> {
+                t.iter().flat_map(|(k, v)| [k.clone(), v.clone()]).collect()
+            };
+            compare_all(&bindings(x), &bindings(y))
+        }
+        _ => unreachable!("chimera: cannot compare {:?} with {:?}", x, y),
+    }
+}
+
+fn compare_all(xs: &[WoValue], ys: &[WoValue]) -> Ordering {
+    xs.iter()
+        .zip(ys)
+        .map(|(x, y)| compare(&x.borrow(), &y.borrow()))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or_else(|| xs.len().cmp(&ys.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(i: i64) -> WoValue {
+        Value::Int(i).into()
+    }
+
+    fn tree(keys: impl IntoIterator<Item = i64>) -> Tree {
+        keys.into_iter()
+            .fold(Tree::default(), |tree, k| tree.insert(int(k), int(k * 10)))
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let t = tree([3, 1, 2]);
+        assert_eq!(t.len(), 3);
+        assert_eq!(t.get(&Value::Int(2)), Some(&int(20)));
+        assert_eq!(t.get(&Value::Int(4)), None);
+        let removed = t.remove(&Value::Int(1));
+        assert_eq!(removed.get(&Value::Int(1)), None);
+        // The original tree is left untouched.
+        assert_eq!(t.get(&Value::Int(1)), Some(&int(10)));
+        assert_eq!(t.insert(int(2), int(0)).get(&Value::Int(2)), Some(&int(0)));
+    }
+
+    #[test]
+    fn balanced_and_ordered() {
+        let t = tree(0..1000);
+        assert!(t.height() <= 15);
+        let keys = Vec::from(t.keys());
+        assert_eq!(keys, (0..1000).map(int).collect::<Vec<_>>());
+        let t = (0..1000).step_by(2).fold(t, |t, k| t.remove(&Value::Int(k)));
+        assert_eq!(t.len(), 500);
+        assert!(t.height() <= 14);
+    }
+
+    #[test]
+    fn structural_equality() {
+        assert_eq!(tree([1, 2, 3, 4]), tree([4, 3, 2, 1]));
+        assert_ne!(tree([1, 2]), tree([1, 2, 3]));
+        let left = tree([1, 2]).insert(int(3), int(0));
+        let union = left.union(&tree([3, 4]));
+        assert_eq!(union.get(&Value::Int(3)), Some(&int(0)));
+        assert_eq!(union.len(), 4);
+    }
+}
@@ -1,10 +1,18 @@
+use std::collections::hash_map::Entry;
+use std::fmt::Display;
 use std::{cell::RefCell, collections::HashMap};
 
 use anyhow::Result;
-use polytype::{Context, Infer, tp, Type, TypeSchema};
+use polytype::{Context, Infer, tp, Type, TypeSchema, Variable};
 
-use crate::ast::{Expr, Item, ItemKind, Stmt};
+use crate::ast::{
+    self, intern, Arm, Clause, Expr, Item, ItemKind, Pattern, Span, Stmt, OPERAND_PARAM,
+    SECTION_PARAM,
+};
+use crate::dependency::{self, Groups, Step};
 use crate::error::TypeError;
+use crate::row;
+use crate::typed;
 
 #[derive(Default, Clone)]
 pub struct Lexicon<'a> {
@@ -13,16 +21,32 @@ pub struct Lexicon<'a> {
     // when you call `.unify()` and friends.
     ctx: RefCell<Context>,
     assumptions: RefCell<HashMap<String, TypeSchema>>,
-    // TODO: Is there a use for keeping track of infered types?
-    // Maybe we could put them back into the AST and provide them
-    // to the user on demand. As it stands now, we are only making
-    // use of the type-checking ability of algorith J.
-    // types: RefCell<HashMap<String, Type>>,
+    holes: RefCell<Vec<Hole>>,
+    // The variables of the annotations being checked, by their skolems.
+    scoped: RefCell<HashMap<Variable, Type>>,
+    // The implicit variables of the local annotations of the definition
+    // being checked, by the type they stand for.
+    implicit: RefCell<HashMap<Variable, Type>>,
+    // The parameters of the lambdas being inferred in this scope.
+    params: RefCell<Vec<String>>,
+    // The effect rows of the lambdas and handlers being inferred, innermost
+    // last, preceded by the one of the top-level once it is needed.
+    effects: RefCell<Vec<Type>>,
+    // The operations of every effect declared so far, by name.
+    operations: RefCell<HashMap<String, Signature>>,
+    // The constructors of every data type declared so far, by name.
+    constructors: RefCell<HashMap<String, Constructor>>,
     outer: Option<&'a Lexicon<'a>>,
 }
 
-// See: https://en.wikipedia.org/wiki/Hindley-Milner_type_system#Algorithm_J
 impl<'a> Infer<Lexicon<'a>, TypeError> for Expr {
+    fn infer(&self, lexicon: &Lexicon<'a>) -> Result<Type, TypeError> {
+        self.typed(lexicon).map(|te| te.ty)
+    }
+}
+
+// See: https://en.wikipedia.org/wiki/Hindley-Milner_type_system#Algorithm_J
+impl Expr {
     // The Type inference algorithm is called J, for some reason.
     // NOTE: According to Milner:
     //   "As it stands, W is hardly an efficient algorithm;
@@ -33,19 +57,47 @@ impl<'a> Infer<Lexicon<'a>, TypeError> for Expr {
     // `let name0 = expr0 in let name1 = expr1 in ... let nameN = exprN in ()`.
     // This means that Chimera's Let-syntax is different from the polymorphic
     // lambda calculus' Let-polymorphism, but is still equivalent to it.
-    fn infer(&self, lexicon: &Lexicon<'a>) -> Result<Type, TypeError> {
+    // Inference builds the typed tree of the expression as it goes, each
+    // node with its type as far as it is known by then, see `typed_items`.
+    fn typed(&self, lexicon: &Lexicon) -> Result<typed::Expr, TypeError> {
+        use typed::ExprKind as Kind;
+        let typed = |kind, ty| Ok(typed::Expr { kind, ty });
         match &self {
             // Boring hard-coded primitive types, nothing to see here!
-            Expr::Void => Ok(tp!(Void)),
-            Expr::Int(_) => Ok(tp!(Int)),
-            Expr::Bool(_) => Ok(tp!(Bool)),
-            Expr::Char(_) => Ok(tp!(Char)),
+            Expr::Void => typed(Kind::Void, tp!(Void)),
+            Expr::Int(int) => typed(Kind::Int(*int), tp!(Int)),
+            Expr::Bool(boolean) => typed(Kind::Bool(*boolean), tp!(Bool)),
+            Expr::Char(ch) => typed(Kind::Char(*ch), tp!(Char)),
+            // An ellipsis is a hole that stands for any expression, hence it can
+            // have any type. The type expected of it is only known once checking
+            // is done, so it is recorded along with the bindings in scope.
+            Expr::Ellipsis(span) => {
+                let th = lexicon.ctx().borrow_mut().new_variable();
+                let hole = Hole {
+                    span: *span,
+                    expected: TypeSchema::Monotype(th.clone()),
+                    bindings: lexicon.locals(),
+                };
+                lexicon.holes().borrow_mut().push(hole);
+                typed(Kind::Hole(*span), th)
+            }
+            // All the elements of a list must be of the same type `te`, the list
+            // itself is then of type `List te`. An empty list is a `List` of _anything_.
+            Expr::List(elems) => {
+                let (elems, te) = lexicon.infer_elems(elems)?;
+                typed(Kind::List(elems), tp!(List(te)).apply(&lexicon.ctx().borrow()))
+            }
+            // Likewise for arrays.
+            Expr::Array(elems) => {
+                let (elems, te) = lexicon.infer_elems(elems)?;
+                typed(Kind::Array(elems), tp!(Array(te)).apply(&lexicon.ctx().borrow()))
+            }
             // This corresponds to the [VAR] rule:
             // We check the lexicon for an assumption about `name` which gives us
             // a polytype `ts`, otherwise the algorithm fails.
             // We then specialize `ts` to a monotype `t` by replacing the bounded type
             // variables by fresh new ones; `t` is then the type of `name`.
-            Expr::Name(name) => lexicon.get(name),
+            Expr::Name(name) => typed(Kind::Name(name.clone()), lexicon.get(name)?),
             // This corresponds to the [APP] rule:
             // Only this rule forces refinement of the type variables introduced.
             // We recursively call J to infer the type of `left` and `right`,
@@ -55,59 +107,105 @@ impl<'a> Infer<Lexicon<'a>, TypeError> for Expr {
             // left is a function that takes a right and produces _something_,
             // if successful, we determine the type of the resuling expression: `ta`,
             // by applying to it the subsititutions that follow from the Unification.
+            // Calling `left` performs its effects, which must be those of the context.
+            // Its parameter `tp` is unified on its own, so that a wrong argument
+            // is reported as such rather than as the wrong function.
             Expr::Apply { left, right } => {
-                let tl = left.infer(lexicon)?;
-                let tr = right.infer(lexicon)?;
-                let mut ctx = lexicon.ctx.borrow_mut();
+                let left = left.typed(lexicon)?;
+                let right = right.typed(lexicon)?;
+                let effect = lexicon.effect();
+                let mut ctx = lexicon.ctx().borrow_mut();
+                let tp = ctx.new_variable();
                 let ta = ctx.new_variable();
-                ctx.unify(&tl, &Type::arrow(tr, ta.clone()))?;
-                Ok(ta.apply(&ctx))
+                expect(&mut ctx, &left.ty, &row::arrow(tp.clone(), ta.clone(), effect))?;
+                expect(&mut ctx, &right.ty, &tp)?;
+                let (left, right) = (Box::new(left), Box::new(right));
+                typed(Kind::Apply { left, right }, ta.apply(&ctx))
             }
             // This corresponds to the [ABS] rule (it stands for abstraction):
             // We start by generating a fresh type variable `tp` and assign it to the
             // function parameter, which is added as an assumption in the lexicon.
             // Then we use the new information to infer the type of `expr`, say `te`.
             // If successful, we know that the lambda is of type `tp -> te`.
-            Expr::Lambda { param, expr } => {
-                let tp = lexicon.ctx.borrow_mut().new_variable();
-                lexicon
-                    .assumptions
-                    .borrow_mut()
-                    .insert(param.clone(), TypeSchema::Monotype(tp.clone()));
-                let te = expr.infer(lexicon)?;
-                // The assumption about the parameter is no longer of any use,
-                // and should be removed before continuing. Otherwise it would
-                // pollute the namespace and lead to contradictions.
-                // NOTE: this is not explicit in the description of algorithm J.
-                lexicon.assumptions.borrow_mut().remove_entry(param);
-                Ok(Type::arrow(tp, te))
+            // An annotated parameter starts off with its annotation instead.
+            // The effects performed by `expr` are those of the lambda's type.
+            Expr::Lambda { param, ann, expr } => {
+                let tp = match ann {
+                    None => lexicon.ctx().borrow_mut().new_variable(),
+                    Some(t) => lexicon.annotation(t),
+                };
+                let effect = lexicon.ctx().borrow_mut().new_variable();
+                let expr = lexicon.with_locals(vec![(param.clone(), tp.clone())], || {
+                    lexicon.with_effect(effect.clone(), || expr.typed(lexicon))
+                })?;
+                let ty = row::arrow(tp, expr.ty.clone(), effect);
+                typed(Kind::Lambda { param: param.clone(), expr: Box::new(expr) }, ty)
+            }
+            // The ascribed expression must have the type of the annotation.
+            // The ascription itself is gone from the typed tree.
+            Expr::Ascribe { expr, ann } => {
+                let expr = expr.typed(lexicon)?;
+                let ta = lexicon.annotation(ann);
+                let mut ctx = lexicon.ctx().borrow_mut();
+                expect(&mut ctx, &expr.ty, &ta)?;
+                typed(expr.kind, ta.apply(&ctx))
+            }
+            // Negation is only defined on integers.
+            Expr::Negate { expr } => {
+                let expr = expr.typed(lexicon)?;
+                expect(&mut lexicon.ctx().borrow_mut(), &expr.ty, &tp!(Int))?;
+                typed(Kind::Negate { expr: Box::new(expr) }, tp!(Int))
             }
             // If the last block is a statement-expression, then that determines
             // the type of the block, otherwise a Void type is assumed.
-            // Currently all Item's are ignored.
             Expr::Block { body } => {
-                let local_lexicon = Lexicon { outer: Some(lexicon), ..Default::default() };
-                // FIXME: this clones the entire "list" of lexicons
-                // each time it entered a new block, not good.
-                let (last, init) = body.split_last().unwrap();
-                for stmt in init {
-                    match stmt {
-                        Stmt::Expr(expr) => {
-                            expr.infer(&local_lexicon)?;
-                        }
-                        Stmt::Item(item) => {
-                            // TODO: make a Check Trait for items.
-                            local_lexicon.check(item)?;
-                        }
-                    }
-                }
-                if let Stmt::Expr(expr) = last {
-                    expr.infer(&local_lexicon)
-                } else {
-                    Ok(tp!(Void))
+                let (body, tb) = lexicon.infer_body(body)?;
+                typed(Kind::Block { body }, tb)
+            }
+            // Every condition must be a `Bool`, and every path must produce
+            // a value of the same type `tb`, which is the type of the branch.
+            Expr::Branch { paths } => {
+                let tb = lexicon.ctx().borrow_mut().new_variable();
+                let mut typed_paths = Vec::new();
+                for (cond, body) in paths {
+                    let cond = cond.typed(lexicon)?;
+                    expect(&mut lexicon.ctx().borrow_mut(), &cond.ty, &tp!(Bool))?;
+                    let (body, t) = lexicon.infer_body(body)?;
+                    expect(&mut lexicon.ctx().borrow_mut(), &t, &tb)?;
+                    typed_paths.push((cond, body));
                 }
+                let tb = tb.apply(&lexicon.ctx().borrow());
+                typed(Kind::Branch { paths: typed_paths }, tb)
+            }
+            Expr::Handle { expr, clauses } => lexicon.infer_handler(expr, clauses),
+            // The handler is evaluated in place of the expression, with the
+            // names of the pattern bound to the exception it raised.
+            Expr::Try { expr, pattern, handler } => {
+                let expr = expr.typed(lexicon)?;
+                let bindings = lexicon.pattern(pattern, &tp!(Exception))?;
+                let handler = lexicon.with_locals(bindings, || handler.typed(lexicon))?;
+                let mut ctx = lexicon.ctx().borrow_mut();
+                expect(&mut ctx, &handler.ty, &expr.ty)?;
+                let te = expr.ty.apply(&ctx);
+                let (expr, handler) = (Box::new(expr), Box::new(handler));
+                typed(Kind::Try { expr, pattern: pattern.clone(), handler }, te)
+            }
+            Expr::Match { expr, arms } => lexicon.infer_match(expr, arms),
+            Expr::Field { .. } | Expr::Assign { .. } => {
+                Err(TypeError::Unsupported("fields and assignments"))
             }
-            _ => unimplemented!("the expression {:?} is not type-checked!", self),
+            Expr::Infix { .. } => unreachable!("infix expressions are resolved by the fixity pass"),
+            // The type errors of `expr` are located here, unless they are
+            // located further in.
+            Expr::Located { span, expr } => expr.typed(lexicon).map_err(|error| match error {
+                TypeError::Mismatch { expected, found, span: None } => {
+                    TypeError::Mismatch { expected, found, span: Some(*span) }
+                }
+                TypeError::ScopeError { name, span: None } => {
+                    TypeError::ScopeError { name, span: Some(*span) }
+                }
+                error => error,
+            }),
         }
     }
 }
@@ -116,62 +214,1038 @@ impl<'a> Lexicon<'a> {
     pub fn get(&self, name: &str) -> Result<Type, TypeError> {
         match self.assumptions.borrow().get(name) {
             None => match self.outer {
-                None => Err(TypeError::ScopeError(name.to_string())),
+                None => Err(TypeError::ScopeError { name: name.to_string(), span: None }),
                 Some(l) => l.get(name),
             },
-            Some(ts) => Ok(ts.instantiate(&mut self.ctx.borrow_mut())),
+            Some(ts) => Ok(ts.instantiate(&mut self.ctx().borrow_mut())),
         }
     }
 
+    /// The outermost lexicon, which holds the state shared by all the others.
+    fn root(&self) -> &Lexicon<'a> {
+        match self.outer {
+            None => self,
+            Some(l) => l.root(),
+        }
+    }
+
+    /// The substitutions are shared by all the nested lexicons,
+    /// otherwise type variables would clash between scopes.
+    fn ctx(&self) -> &RefCell<Context> {
+        &self.root().ctx
+    }
+
+    /// The holes met so far, they are shared by all the nested lexicons.
+    fn holes(&self) -> &RefCell<Vec<Hole>> {
+        &self.root().holes
+    }
+
+    /// The effects performed by the expression being inferred: those of the
+    /// innermost lambda or handler, or else those of the top-level.
+    fn effect(&self) -> Type {
+        let mut effects = self.root().effects.borrow_mut();
+        if effects.is_empty() {
+            effects.push(self.ctx().borrow_mut().new_variable());
+        }
+        effects.last().unwrap().clone()
+    }
+
+    /// Runs `f` with `effect` as the effects of the expression being inferred.
+    fn with_effect<T>(&self, effect: Type, f: impl FnOnce() -> T) -> T {
+        self.effect();
+        self.root().effects.borrow_mut().push(effect);
+        let result = f();
+        self.root().effects.borrow_mut().pop();
+        result
+    }
+
+    /// Runs `f` with the monomorphic `bindings` in scope, as parameters.
+    fn with_locals<T>(&self, bindings: Vec<(String, Type)>, f: impl FnOnce() -> T) -> T {
+        let shadowed = bindings
+            .iter()
+            .map(|(name, t)| {
+                self.params.borrow_mut().push(name.clone());
+                let ts = TypeSchema::Monotype(t.clone());
+                (name, self.assumptions.borrow_mut().insert(name.clone(), ts))
+            })
+            .collect::<Vec<_>>();
+        let result = f();
+        // The assumptions about the parameters are no longer of any use,
+        // and should be removed before continuing. Otherwise they would
+        // pollute the namespace and lead to contradictions. Any name
+        // a parameter shadowed is visible again.
+        // NOTE: this is not explicit in the description of algorithm J.
+        let mut assumptions = self.assumptions.borrow_mut();
+        for (name, shadowed) in shadowed.into_iter().rev() {
+            self.params.borrow_mut().pop();
+            match shadowed {
+                None => assumptions.remove(name),
+                Some(ts) => assumptions.insert(name.clone(), ts),
+            };
+        }
+        result
+    }
+
+    /// Infers the elements of a list or an array, which share a type.
+    fn infer_elems(&self, elems: &[Expr]) -> Result<(Vec<typed::Expr>, Type), TypeError> {
+        let te = self.ctx().borrow_mut().new_variable();
+        let mut typed = Vec::new();
+        for elem in elems {
+            let elem = elem.typed(self)?;
+            expect(&mut self.ctx().borrow_mut(), &elem.ty, &te)?;
+            typed.push(elem);
+        }
+        Ok((typed, te))
+    }
+
+    /// The type of the operation `name`, wherever its effect is declared.
+    pub fn operation(&self, name: &str) -> Option<TypeSchema> {
+        let operations = self.root().operations.borrow();
+        let signature = operations.get(name)?;
+        Some(signature.arrow().generalize(&[]))
+    }
+
+    /// The holes met so far, with what is known of their types by now.
+    pub fn resolved_holes(&self) -> Vec<Hole> {
+        let ctx = self.ctx().borrow();
+        self.holes()
+            .borrow()
+            .iter()
+            .map(|hole| Hole {
+                span: hole.span,
+                expected: apply_schema(&hole.expected, &ctx),
+                bindings: hole
+                    .bindings
+                    .iter()
+                    .map(|(name, ts)| (name.clone(), apply_schema(ts, &ctx)))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// The local bindings in scope, innermost first. Those of the outermost
+    /// scope are global definitions, except for lambda parameters.
+    fn locals(&self) -> Vec<(String, TypeSchema)> {
+        let mut locals = match self.outer {
+            None => Vec::new(),
+            Some(l) => l.locals(),
+        };
+        let mut scope = self
+            .assumptions
+            .borrow()
+            .iter()
+            .filter(|(name, _)| self.outer.is_some() || self.params.borrow().contains(name))
+            .filter(|(name, _)| *name != SECTION_PARAM && *name != OPERAND_PARAM)
+            .map(|(name, ts)| (name.clone(), ts.clone()))
+            .collect::<Vec<_>>();
+        scope.sort_by(|a, b| a.0.cmp(&b.0));
+        locals.retain(|(name, _)| !scope.iter().any(|(n, _)| n == name));
+        scope.extend(locals);
+        scope
+    }
+
+    /// The type variables that are free in the assumptions of every scope,
+    /// or in the effects being inferred, after applying the current
+    /// substitutions to them.
+    fn free_vars(&self) -> Vec<Variable> {
+        let ctx = self.ctx().borrow();
+        let mut vars = self
+            .assumptions
+            .borrow()
+            .values()
+            .flat_map(|ts| ts.free_vars())
+            .flat_map(|v| Type::Variable(v).apply(&ctx).vars())
+            .collect::<Vec<_>>();
+        if self.outer.is_none() {
+            let effects = self.effects.borrow();
+            vars.extend(effects.iter().flat_map(|row| row.apply(&ctx).vars()));
+        }
+        drop(ctx);
+        if let Some(l) = self.outer {
+            vars.extend(l.free_vars());
+        }
+        vars
+    }
+
+    /// Whether any of the `skolems` was substituted for a type variable
+    /// that is free in the assumptions of some scope.
+    fn escapes(&self, skolems: &[&'static str]) -> bool {
+        let ctx = self.ctx().borrow();
+        let escaped = self
+            .assumptions
+            .borrow()
+            .values()
+            .flat_map(|ts| ts.free_vars())
+            .any(|v| mentions(&Type::Variable(v).apply(&ctx), skolems));
+        drop(ctx);
+        escaped || self.outer.is_some_and(|l| l.escapes(skolems))
+    }
+
+    /// The type variables in the annotations of parameters and ascriptions
+    /// stand for _some_ type, to be determined by unification. They are
+    /// replaced by fresh variables so as not to clash with existing ones,
+    /// the same ones throughout the definition being checked.
+    /// Those that refer to the variables of an enclosing definition's
+    /// annotation stand for the same type as they do in there.
+    fn annotation(&self, t: &Type) -> Type {
+        let t = t.substitute(&self.root().scoped.borrow());
+        let mut implicit = self.root().implicit.borrow_mut();
+        for v in t.vars() {
+            implicit
+                .entry(v)
+                .or_insert_with(|| self.ctx().borrow_mut().new_variable());
+        }
+        t.substitute(&implicit)
+    }
+
+    /// Infers the body of a definition, which has local annotations of its own.
+    fn infer_definition(&self, expr: &Expr) -> Result<typed::Expr, TypeError> {
+        let enclosing = self.root().implicit.take();
+        let typed = expr.typed(self);
+        self.root().implicit.replace(enclosing);
+        typed
+    }
+
+    /// The type schema assumed for `name`, if it is in scope.
+    pub fn schema(&self, name: &str) -> Option<TypeSchema> {
+        match self.assumptions.borrow().get(name) {
+            None => self.outer.and_then(|l| l.schema(name)),
+            Some(ts) => Some(ts.clone()),
+        }
+    }
+
+    /// Infers the type of a sequence of statements in a new scope.
+    fn infer_body(&self, body: &[Stmt]) -> Result<(Vec<typed::Stmt>, Type), TypeError> {
+        if let Some(name) = dependency::used_early(&Step::body(body)) {
+            return Err(TypeError::UsedEarly(name));
+        }
+        let local_lexicon = Lexicon { outer: Some(self), ..Default::default() };
+        let groups = Groups::new(
+            body.iter()
+                .filter_map(|stmt| match stmt {
+                    Stmt::Item(item) => Some(item),
+                    Stmt::Expr(_) => None,
+                })
+                .collect(),
+        );
+        // Items are checked as their group is reached, along with the groups
+        // it depends on, which come before it.
+        let mut checked = 0;
+        let mut items = 0;
+        let mut checked_items = Vec::new();
+        let mut typed_body = Vec::new();
+        let mut te = tp!(Void);
+        for stmt in body {
+            te = match stmt {
+                Stmt::Expr(expr) => {
+                    let expr = expr.typed(&local_lexicon)?;
+                    let te = expr.ty.clone();
+                    typed_body.push(typed::Stmt::Expr(expr));
+                    te
+                }
+                Stmt::Item(_) => {
+                    let group = groups.group_of(items);
+                    while checked <= group {
+                        checked_items.extend(local_lexicon.check_group(&groups, checked)?);
+                        checked += 1;
+                    }
+                    let position = checked_items.iter().position(|(i, _)| *i == items);
+                    if let (_, Some(item)) = checked_items.swap_remove(position.unwrap()) {
+                        typed_body.push(typed::Stmt::Item(item));
+                    }
+                    items += 1;
+                    // If the last statement is an item, the body is of type Void.
+                    tp!(Void)
+                }
+            };
+        }
+        Ok((typed_body, te))
+    }
+
+    /// Checks that the annotation `ts` of `name` is an instance of the type
+    /// inferred for `expr`: it may be less general, but never wrong.
+    /// The quantified variables of `ts` are replaced by rigid types (skolems)
+    /// which only unify with themselves, so that `expr` can't specialize them.
+    /// Returns the typed tree of `expr`, in which the skolems are replaced by
+    /// the variables they were named after, along with `ts` quantified over
+    /// those same variables.
+    fn subsume(
+        &self,
+        name: &str,
+        expr: &Expr,
+        ts: &TypeSchema,
+    ) -> Result<(typed::Expr, TypeSchema), TypeError> {
+        let mut skolems = Vec::new();
+        let mut fresh = Vec::new();
+        let mut schema = ts;
+        let mut substitution = HashMap::new();
+        while let TypeSchema::Polytype { variable, body } = schema {
+            // A fresh variable serves as a unique name for the skolem.
+            let skolem = match self.ctx().borrow_mut().new_variable() {
+                Type::Variable(v) => {
+                    fresh.push(v);
+                    skolem(v)
+                }
+                Type::Constructed(..) => unreachable!(),
+            };
+            skolems.push(skolem);
+            substitution.insert(*variable, Type::Constructed(skolem, vec![]));
+            schema = body;
+        }
+        // The variables of `ts` scope over `expr`, where annotations may refer
+        // to them, as may `ts` refer to the ones of enclosing definitions.
+        let bound = substitution.keys().copied().collect::<Vec<_>>();
+        self.root().scoped.borrow_mut().extend(substitution.clone());
+        substitution.extend(self.root().scoped.borrow().clone());
+        let typed = self.infer_definition(expr);
+        self.root().scoped.borrow_mut().retain(|v, _| !bound.contains(v));
+        let mut typed = typed?;
+        let te = typed.ty.clone();
+        let ta = match schema {
+            TypeSchema::Monotype(t) => t.substitute(&substitution),
+            TypeSchema::Polytype { .. } => unreachable!(),
+        };
+        let unified = row::unify(&mut self.ctx().borrow_mut(), &te, &ta);
+        // A skolem may not escape into the enclosing scopes either, as in
+        // `|x| do let f : forall a. a -> a = |y| x end`.
+        if unified.is_err() || self.escapes(&skolems) {
+            let found = te
+                .apply(&self.ctx().borrow())
+                .generalize(&self.free_vars());
+            return Err(TypeError::AnnotationMismatch {
+                name: name.to_string(),
+                expected: Pretty(ts).to_string(),
+                found: Pretty(&found).to_string(),
+            });
+        }
+        let variables = skolems
+            .into_iter()
+            .zip(fresh.iter().map(|v| Type::Variable(*v)))
+            .collect::<HashMap<_, _>>();
+        let ctx = self.ctx().borrow();
+        typed.map_types(&|t, bound| unskolemize(&resolve(t, bound, &ctx), &variables));
+        let schema = fresh.into_iter().rev().fold(
+            TypeSchema::Monotype(unskolemize(&ta, &variables)),
+            |body, variable| TypeSchema::Polytype { variable, body: Box::new(body) },
+        );
+        Ok((typed, schema))
+    }
+
     pub fn check(&self, item: &Item) -> Result<(), TypeError> {
-        match &item.kind {
-            ItemKind::Module { items, .. } => {
-                for item in items {
-                    self.check(item)?;
+        self.typed_item(item).map(|_| ())
+    }
+
+    /// Checks an item and returns its typed tree, if it has one: fixities
+    /// are of no concern past parsing.
+    fn typed_item(&self, item: &Item) -> Result<Option<typed::Item>, TypeError> {
+        let kind = match &item.kind {
+            ItemKind::Module { name, items } => typed::ItemKind::Module {
+                name: name.clone(),
+                items: self.infer_items(items)?,
+            },
+            ItemKind::Definition { .. } => return Ok(self.check_definitions(&[item])?.pop()),
+            ItemKind::DataType { schema, variants } => {
+                self.check_data(schema, variants)?;
+                typed::ItemKind::DataType {
+                    schema: schema.clone(),
+                    variants: variants.clone(),
                 }
             }
-            ItemKind::Definition { name, ann, expr } => {
-                if let Some(ts) = ann {
-                    // We introduce type annotations into the algorithm
-                    // without any checks, as if it it infered them itself.
-                    // It is up to the user to make sure their annotations
-                    // are correct, that is if they choose to add them.
-                    // Annotations are almost always unecessary. Keep It Simple.
-                    self.assumptions
-                        .borrow_mut()
-                        .insert(name.clone(), ts.clone());
-                } else {
-                    // This corresponds to the [LET] rule:
-                    // We first find the most general type `te` for `expr`,
-                    // Then we "clone" the type `te` by universally quantifying
-                    // all the free type variables within it that are NOT also
-                    // free in the assumptions, the resulting polytype `ts` is
-                    // then added to the assumptions as the type of `name`.
-                    let te = expr.infer(self)?;
-                    let free_variables = self
-                        .assumptions
-                        .borrow()
-                        .values()
-                        .flat_map(|ts| ts.free_vars())
-                        .collect::<Vec<_>>();
-                    // Variables specified by `bound` remain unquantified.
-                    let ts = te.generalize(&free_variables);
-                    self.assumptions.borrow_mut().insert(name.clone(), ts);
-                }
+            ItemKind::Effect { schema, operations } => typed::ItemKind::Effect {
+                schema: schema.clone(),
+                operations: self.check_effect(schema, operations)?,
+            },
+            ItemKind::Fixity { .. } => return Ok(None),
+        };
+        Ok(Some(typed::Item { attr: item.attr.clone(), kind }))
+    }
+
+    /// Checks a sequence of items, such as a whole program or a module,
+    /// in which definitions may refer to the ones that follow them.
+    pub fn check_items(&self, items: &[Item]) -> Result<(), TypeError> {
+        self.infer_items(items).map(|_| ())
+    }
+
+    /// Checks a sequence of items like `check_items`, and returns their typed
+    /// tree, in which every type is as known once they are all checked.
+    pub fn typed_items(&self, items: &[Item]) -> Result<Vec<typed::Item>, TypeError> {
+        let mut typed = self.infer_items(items)?;
+        let ctx = self.ctx().borrow();
+        for item in &mut typed {
+            item.map_types(&|t, bound| resolve(t, bound, &ctx));
+        }
+        Ok(typed)
+    }
+
+    /// The typed items, in source order, as far as their types are known by now.
+    fn infer_items(&self, items: &[Item]) -> Result<Vec<typed::Item>, TypeError> {
+        if let Some(name) = dependency::used_early(&Step::items(items)) {
+            return Err(TypeError::UsedEarly(name));
+        }
+        let groups = Groups::new(items.iter().collect());
+        let mut typed = Vec::new();
+        for g in 0..groups.len() {
+            typed.extend(self.check_group(&groups, g)?);
+        }
+        typed.sort_by_key(|(i, _)| *i);
+        Ok(typed.into_iter().filter_map(|(_, item)| item).collect())
+    }
+
+    /// Anything but a definition is always alone in its group. The typed
+    /// items come with their index among those of `groups`.
+    fn check_group(
+        &self,
+        groups: &Groups,
+        g: usize,
+    ) -> Result<Vec<(usize, Option<typed::Item>)>, TypeError> {
+        let indices = groups.indices(g).iter().copied();
+        match groups.members(g).collect::<Vec<_>>()[..] {
+            [item] if !matches!(item.kind, ItemKind::Definition { .. }) => {
+                Ok(indices.zip([self.typed_item(item)?]).collect())
             }
-            _ => unimplemented!("the item {:?} is not type-checked!", item),
+            ref definitions => {
+                let typed = self.check_definitions(definitions)?;
+                Ok(indices.zip(typed.into_iter().map(Some)).collect())
+            }
+        }
+    }
+
+    /// Checks a group of (mutually) recursive definitions.
+    fn check_definitions(&self, definitions: &[&Item]) -> Result<Vec<typed::Item>, TypeError> {
+        let mut inferred = Vec::new();
+        let mut annotated = Vec::new();
+        // Every name in the group is in scope while checking the group.
+        // Annotations are trusted at first, which allows polymorphic recursion,
+        // while the other names are bound to a fresh monotype `tn`.
+        for (i, item) in definitions.iter().enumerate() {
+            if let ItemKind::Definition { name, ann, expr } = &item.kind {
+                let ts = match ann {
+                    Some(ts) => {
+                        // Intrinsics have no body to speak of, their annotation
+                        // is the only thing we know about them and must be trusted.
+                        if !(is_intrinsic(item) && matches!(expr, Expr::Ellipsis(_))) {
+                            annotated.push((i, name, expr, ts));
+                        }
+                        substitute_schema(ts, &self.root().scoped.borrow())
+                    }
+                    None => {
+                        let tn = self.ctx().borrow_mut().new_variable();
+                        inferred.push((i, name, expr, tn.clone()));
+                        TypeSchema::Monotype(tn)
+                    }
+                };
+                self.assumptions.borrow_mut().insert(name.clone(), ts);
+            }
+        }
+        // This corresponds to the [LET] rule, extended to recursive groups:
+        // We first find the most general type `te` for each `expr`, which must
+        // agree with the monotype `tn` its name was used at within the group.
+        // Then we "clone" each type by universally quantifying all the free
+        // type variables within it that are NOT also free in the assumptions,
+        // the resulting polytype `ts` is then added to the assumptions as the
+        // type of `name`. The group's own monotypes don't count as assumptions.
+        let mut typed = vec![None; definitions.len()];
+        for (i, _, expr, tn) in &inferred {
+            let te = self.infer_definition(expr)?;
+            expect(&mut self.ctx().borrow_mut(), &te.ty, tn)?;
+            typed[*i] = Some(te);
+        }
+        for (_, name, _, _) in &inferred {
+            self.assumptions.borrow_mut().remove(*name);
+        }
+        let free_variables = self.free_vars();
+        for (_, name, _, tn) in inferred {
+            let tn = tn.apply(&self.ctx().borrow());
+            // Variables specified by `bound` remain unquantified.
+            let ts = tn.generalize(&free_variables);
+            self.assumptions.borrow_mut().insert(name.clone(), ts);
+        }
+        // Only then are the annotations checked against their definitions.
+        // Their schemas are the annotations, quantified over the variables
+        // the typed tree of their bodies refers to.
+        let mut schemas = vec![None; definitions.len()];
+        for (i, name, expr, ts) in annotated {
+            let (te, schema) = self.subsume(name, expr, ts)?;
+            typed[i] = Some(te);
+            schemas[i] = Some(schema);
         }
-        for (name, ts) in self.assumptions.borrow().iter() {
-            println!("[@typechecker] {} : {}", name, ts)
+        let typed = definitions.iter().zip(typed).zip(schemas).map(|((item, te), schema)| {
+            let (name, expr) = match &item.kind {
+                ItemKind::Definition { name, expr, .. } => (name, expr),
+                _ => unreachable!("a group of definitions only has definitions"),
+            };
+            let schema = schema.unwrap_or_else(|| self.assumptions.borrow()[name].clone());
+            let expr = match (te, expr) {
+                (Some(te), _) => te,
+                // The body of an intrinsic is not checked, its type is
+                // the one of the definition itself.
+                (None, Expr::Ellipsis(span)) => typed::Expr {
+                    kind: typed::ExprKind::Hole(*span),
+                    ty: body(&schema).clone(),
+                },
+                (None, _) => unreachable!("only intrinsics go unchecked"),
+            };
+            let name = name.clone();
+            let kind = typed::ItemKind::Definition { name, schema, expr };
+            typed::Item { attr: item.attr.clone(), kind }
+        });
+        Ok(typed.collect())
+    }
+
+    /// Brings the operations of an effect into scope. The effect itself isn't
+    /// part of their annotation, it is added to the effects of their type:
+    /// `get : Void -> s` in `effect State s` is `Void -> {State s} s`.
+    fn check_effect(
+        &self,
+        schema: &TypeSchema,
+        operations: &[ast::Operation],
+    ) -> Result<Vec<(String, TypeSchema)>, TypeError> {
+        let mut params = Vec::new();
+        let mut header = schema;
+        while let TypeSchema::Polytype { variable, body } = header {
+            params.push(*variable);
+            header = body;
+        }
+        let label = match header {
+            TypeSchema::Monotype(t) => t.clone(),
+            TypeSchema::Polytype { .. } => unreachable!(),
+        };
+        let mut typed = Vec::new();
+        for op in operations {
+            let (param, result, tail) = row::as_arrow(body(&op.ann))
+                .ok_or_else(|| TypeError::OperationType(op.name.clone()))?;
+            let signature = Signature {
+                label: label.clone(),
+                params: params.clone(),
+                param: param.clone(),
+                result: result.clone(),
+                tail: tail.clone(),
+            };
+            let ts = signature.arrow().generalize(&[]);
+            self.assumptions.borrow_mut().insert(op.name.clone(), ts.clone());
+            self.root()
+                .operations
+                .borrow_mut()
+                .insert(op.name.clone(), signature);
+            typed.push((op.name.clone(), ts));
+        }
+        Ok(typed)
+    }
+
+    /// Every variant of a data type is a constructor, which is a function of
+    /// its fields: `Some { value: a }` in `data Option a` is `a -> Option a`.
+    fn check_data(
+        &self,
+        schema: &TypeSchema,
+        variants: &[(String, Vec<(String, TypeSchema)>)],
+    ) -> Result<(), TypeError> {
+        let data = body(schema).clone();
+        let params = data.vars();
+        let names = variants.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+        for (name, fields) in variants {
+            // Any other variable would stand for a different type at every
+            // match, which nothing checks, as would the effects of a function.
+            for (field, ts) in fields {
+                if body(ts).vars().iter().any(|v| !params.contains(v)) {
+                    return Err(TypeError::UndeclaredVariable(name.clone(), field.clone()));
+                }
+            }
+            let constructor = Constructor {
+                data: data.clone(),
+                fields: fields.iter().map(|(_, ts)| body(ts).clone()).collect(),
+                variants: names.clone(),
+            };
+            self.assumptions
+                .borrow_mut()
+                .insert(name.clone(), constructor.function().generalize(&[]));
+            self.root()
+                .constructors
+                .borrow_mut()
+                .insert(name.clone(), constructor);
         }
         Ok(())
     }
+
+    /// The type of a match expression is the one shared by its arms. The
+    /// patterns of those must be constructors of the type of `expr`, which
+    /// are all covered unless an arm binds anything to a name.
+    fn infer_match(&self, expr: &Expr, arms: &[Arm]) -> Result<typed::Expr, TypeError> {
+        let expr = expr.typed(self)?;
+        let te = expr.ty.clone();
+        let mut typed_arms = Vec::new();
+        let tr = self.ctx().borrow_mut().new_variable();
+        let mut covered = Vec::new();
+        let mut variants = Vec::new();
+        let mut total = false;
+        for arm in arms {
+            let bindings = self.pattern(&arm.pattern, &te)?;
+            match &arm.pattern {
+                Pattern::Constructor { name, .. } => {
+                    covered.push(name.clone());
+                    variants = self.root().constructors.borrow()[name].variants.clone();
+                }
+                Pattern::Name(_) => total = true,
+            }
+            let body = self.with_locals(bindings, || arm.body.typed(self))?;
+            expect(&mut self.ctx().borrow_mut(), &body.ty, &tr)?;
+            typed_arms.push(typed::Arm { pattern: arm.pattern.clone(), body });
+        }
+        if !total {
+            if let Some(missing) = variants.into_iter().find(|v| !covered.contains(v)) {
+                return Err(TypeError::NonExhaustive(missing));
+            }
+        }
+        Ok(typed::Expr {
+            kind: typed::ExprKind::Match { expr: Box::new(expr), arms: typed_arms },
+            ty: tr.apply(&self.ctx().borrow()),
+        })
+    }
+
+    /// The types of the names `pattern` binds, which must match a value of
+    /// type `te`.
+    fn pattern(&self, pattern: &Pattern, te: &Type) -> Result<Vec<(String, Type)>, TypeError> {
+        match pattern {
+            Pattern::Constructor { name, fields } => {
+                let constructor = self
+                    .root()
+                    .constructors
+                    .borrow()
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| TypeError::NotAConstructor(name.clone()))?;
+                if fields.len() != constructor.fields.len() {
+                    let arity = constructor.fields.len();
+                    return Err(TypeError::ConstructorArity(name.clone(), arity, fields.len()));
+                }
+                let mut substitution = HashMap::new();
+                for v in constructor.data.vars() {
+                    substitution.insert(v, self.ctx().borrow_mut().new_variable());
+                }
+                let data = constructor.data.substitute(&substitution);
+                expect(&mut self.ctx().borrow_mut(), te, &data)?;
+                Ok(fields
+                    .iter()
+                    .cloned()
+                    .zip(constructor.fields.iter().map(|t| t.substitute(&substitution)))
+                    .collect())
+            }
+            Pattern::Name(name) => Ok(vec![(name.clone(), te.clone())]),
+        }
+    }
+
+    /// Checks that the clauses of a handler cover the operations of a single
+    /// effect, each exactly once, and returns the signatures of that effect's.
+    fn handled(&self, clauses: &[Clause]) -> Result<Vec<(String, Signature)>, TypeError> {
+        let operations = self.root().operations.borrow();
+        let signature = |name: &String| {
+            operations
+                .get(name)
+                .ok_or_else(|| TypeError::NotAnOperation(name.clone()))
+        };
+        let effect = row::effect(&signature(&clauses[0].operation)?.label);
+        let mut handled = Vec::new();
+        for clause in clauses {
+            let name = &clause.operation;
+            if row::effect(&signature(name)?.label) != effect {
+                let effect = effect.unwrap_or_default().to_string();
+                return Err(TypeError::ForeignOperation(name.clone(), effect));
+            }
+            if handled.contains(name) {
+                return Err(TypeError::DuplicateClause(name.clone()));
+            }
+            handled.push(name.clone());
+        }
+        let mut unhandled = operations
+            .iter()
+            .filter(|(name, op)| row::effect(&op.label) == effect && !handled.contains(name))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        unhandled.sort();
+        if let Some(name) = unhandled.into_iter().next() {
+            let effect = effect.unwrap_or_default().to_string();
+            return Err(TypeError::UnhandledOperation(effect, name));
+        }
+        Ok(handled
+            .into_iter()
+            .map(|name| {
+                let signature = operations[&name].clone();
+                (name, signature)
+            })
+            .collect())
+    }
+
+    /// Infers the type `tr` of a handler of the effect `E`. The handled `expr`
+    /// performs the effects of the context `ε` along with `E`, and must be of
+    /// type `tr`. Each clause handles an operation `a -> {E} b`: it is given
+    /// the argument `a` and the function `b -> {ε} tr` which resumes `expr`,
+    /// and must produce a `tr` itself. Clauses run in the context of the
+    /// handler, so they perform effects in `ε`.
+    fn infer_handler(&self, expr: &Expr, clauses: &[Clause]) -> Result<typed::Expr, TypeError> {
+        let handled = self.handled(clauses)?;
+        let outer = self.effect();
+        // The parameters of `E` are the same throughout the handler.
+        let mut substitution = HashMap::new();
+        for v in &handled[0].1.params {
+            substitution.insert(*v, self.ctx().borrow_mut().new_variable());
+        }
+        let label = handled[0].1.label.substitute(&substitution);
+        let inner = row::extend(label.clone(), outer.clone());
+        let expr = self.with_effect(inner.clone(), || expr.typed(self))?;
+        let tr = expr.ty.clone();
+        let mut typed_clauses = Vec::new();
+        for (clause, (name, op)) in clauses.iter().zip(handled) {
+            // The clause must work for any instance of the operation's own
+            // type variables, hence they are replaced by skolems.
+            let mut substitution = substitution.clone();
+            for v in op.tail.vars() {
+                let fresh = self.ctx().borrow_mut().new_variable();
+                substitution.entry(v).or_insert(fresh);
+            }
+            let mut skolems = Vec::new();
+            for v in op.param.vars().into_iter().chain(op.result.vars()) {
+                if let Entry::Vacant(entry) = substitution.entry(v) {
+                    let skolem = match self.ctx().borrow_mut().new_variable() {
+                        Type::Variable(v) => skolem(v),
+                        Type::Constructed(..) => unreachable!(),
+                    };
+                    skolems.push(skolem);
+                    entry.insert(Type::Constructed(skolem, vec![]));
+                }
+            }
+            let row = row::extend(label.clone(), op.tail.substitute(&substitution));
+            expect(&mut self.ctx().borrow_mut(), &inner, &row)?;
+            let tp = op.param.substitute(&substitution);
+            let tresult = op.result.substitute(&substitution);
+            let tresume = row::arrow(tresult, tr.clone(), outer.clone());
+            let bindings = vec![(clause.param.clone(), tp), (clause.resume.clone(), tresume)];
+            let body = self.with_locals(bindings, || clause.body.typed(self))?;
+            expect(&mut self.ctx().borrow_mut(), &body.ty, &tr)?;
+            let escaped = {
+                let ctx = self.ctx().borrow();
+                mentions(&tr.apply(&ctx), &skolems) || mentions(&outer.apply(&ctx), &skolems)
+            };
+            if escaped || self.escapes(&skolems) {
+                return Err(TypeError::ClauseEscape(name));
+            }
+            typed_clauses.push(typed::Clause {
+                operation: clause.operation.clone(),
+                param: clause.param.clone(),
+                resume: clause.resume.clone(),
+                body,
+            });
+        }
+        Ok(typed::Expr {
+            kind: typed::ExprKind::Handle { expr: Box::new(expr), clauses: typed_clauses },
+            ty: tr.apply(&self.ctx().borrow()),
+        })
+    }
+}
+
+/// The operation of a declared effect, which is the effect's `label` applied
+/// to its `params`, as in `State s`. The operation takes a `param` and produces
+/// a `result`, while performing `label` and the effects of the row `tail`.
+#[derive(Debug, Clone)]
+struct Signature {
+    label: Type,
+    params: Vec<Variable>,
+    param: Type,
+    result: Type,
+    tail: Type,
+}
+
+impl Signature {
+    /// The type of the operation, a function performing its effect.
+    fn arrow(&self) -> Type {
+        let row = row::extend(self.label.clone(), self.tail.clone());
+        row::arrow(self.param.clone(), self.result.clone(), row)
+    }
+}
+
+/// A constructor of the data type `data`, along with the types of its fields
+/// and the names of all the `variants` of the data type.
+#[derive(Debug, Clone)]
+struct Constructor {
+    data: Type,
+    fields: Vec<Type>,
+    variants: Vec<String>,
+}
+
+impl Constructor {
+    /// The type of the constructor, a curried function of its fields. It is
+    /// pure, so every arrow gets a row variable of its own, past the ones
+    /// of the data type.
+    fn function(&self) -> Type {
+        let vars = self.fields.iter().flat_map(Type::vars).chain(self.data.vars());
+        let next = vars.max().map_or(0, |v| v + 1);
+        self.fields
+            .iter()
+            .enumerate()
+            .rev()
+            .fold(self.data.clone(), |t, (i, field)| {
+                row::arrow(field.clone(), t, Type::Variable(next + i))
+            })
+    }
+}
+
+fn body(ts: &TypeSchema) -> &Type {
+    match ts {
+        TypeSchema::Monotype(t) => t,
+        TypeSchema::Polytype { body, .. } => self::body(body),
+    }
+}
+
+/// A typed hole, with the type expected of it and the local bindings in scope.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hole {
+    pub span: Span,
+    pub expected: TypeSchema,
+    pub bindings: Vec<(String, TypeSchema)>,
+}
+
+impl Hole {
+    /// Shows the hole as `Display` does, but at `location` rather than at
+    /// the line and column of its span, see `Sources::hole`.
+    pub fn located(&self, location: &str) -> String {
+        let mut shown = format!("hole at {} has type `{}`", location, Pretty(&self.expected));
+        if !self.bindings.is_empty() {
+            shown.push_str("\n  with local bindings:");
+        }
+        for (name, ts) in &self.bindings {
+            shown.push_str(&format!("\n    {} : {}", name, Pretty(ts)));
+        }
+        shown
+    }
+}
+
+impl Display for Hole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.located(&self.span.to_string()))
+    }
+}
+
+/// Applies the substitutions in `ctx` to the free variables of `ts` only,
+/// its bound variables might well share their number with unrelated ones.
+fn apply_schema(ts: &TypeSchema, ctx: &Context) -> TypeSchema {
+    let substitution = ts
+        .free_vars()
+        .into_iter()
+        .map(|v| (v, Type::Variable(v).apply(ctx)))
+        .collect::<HashMap<_, _>>();
+    substitute_schema(ts, &substitution)
+}
+
+fn substitute_schema(ts: &TypeSchema, substitution: &HashMap<Variable, Type>) -> TypeSchema {
+    match ts {
+        TypeSchema::Monotype(t) => TypeSchema::Monotype(t.substitute(substitution)),
+        TypeSchema::Polytype { variable, body } => TypeSchema::Polytype {
+            variable: *variable,
+            body: Box::new(substitute_schema(body, substitution)),
+        },
+    }
+}
+
+/// Applies the substitutions in `ctx` to the variables of `t` but the `bound` ones,
+/// see `apply_schema`.
+fn resolve(t: &Type, bound: &[Variable], ctx: &Context) -> Type {
+    let substitution = t
+        .vars()
+        .into_iter()
+        .filter(|v| !bound.contains(v))
+        .map(|v| (v, Type::Variable(v).apply(ctx)))
+        .collect::<HashMap<_, _>>();
+    t.substitute(&substitution)
+}
+
+/// Replaces the skolems in `t` by the annotation's variables they stand for.
+fn unskolemize(t: &Type, variables: &HashMap<&'static str, Type>) -> Type {
+    match t {
+        Type::Variable(_) => t.clone(),
+        Type::Constructed(name, args) => match variables.get(name) {
+            Some(v) => v.clone(),
+            None => Type::Constructed(
+                name,
+                args.iter().map(|a| unskolemize(a, variables)).collect(),
+            ),
+        },
+    }
+}
+
+fn is_intrinsic(item: &Item) -> bool {
+    matches!(&item.attr, Some(attr) if attr.name == "intrinsic")
+}
+
+/// A rigid type standing for the quantified variable `v` of an annotation.
+fn skolem(v: Variable) -> &'static str {
+    intern(&format!("'t{}", v))
+}
+
+fn mentions(t: &Type, skolems: &[&'static str]) -> bool {
+    match t {
+        Type::Variable(_) => false,
+        Type::Constructed(name, args) => {
+            skolems.contains(name) || args.iter().any(|a| mentions(a, skolems))
+        }
+    }
+}
+
+/// Unifies `found`, the type of an expression, with the type `expected` of it.
+/// If they don't unify, they are both shown as `Pretty` would, their variables
+/// named in order of appearance across the two.
+fn expect(ctx: &mut Context, found: &Type, expected: &Type) -> Result<(), TypeError> {
+    if row::unify(ctx, found, expected).is_ok() {
+        return Ok(());
+    }
+    // The context is untouched by the failure, so these are finite.
+    let (expected, found) = (expected.apply(ctx), found.apply(ctx));
+    let mut order = Vec::new();
+    appearance(&expected, &mut order);
+    appearance(&found, &mut order);
+    let names = order
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| (v, var_name(i)))
+        .collect::<HashMap<_, _>>();
+    Err(TypeError::Mismatch {
+        expected: show_type(&expected, &names, 0),
+        found: show_type(&found, &names, 0),
+        span: None,
+    })
+}
+
+/// Displays a `TypeSchema` in Chimera's own syntax, as opposed to polytype's.
+/// Quantified type variables are named `a`, `b`, `c`... in order of appearance.
+pub struct Pretty<'a>(pub &'a TypeSchema);
+
+impl Display for Pretty<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", show_schema(self.0, &HashMap::new()).0)
+    }
+}
+
+/// Shows `ts` like `Pretty` does, except that its free variables are named
+/// as in `enclosing`, which names the variables of the enclosing schemas,
+/// and that its quantified ones don't take the names of those. Returns
+/// `enclosing` along with the names of the quantified variables.
+pub fn show_schema(
+    ts: &TypeSchema,
+    enclosing: &HashMap<Variable, String>,
+) -> (String, HashMap<Variable, String>) {
+    let mut bound = Vec::new();
+    let mut schema = ts;
+    while let TypeSchema::Polytype { variable, body } = schema {
+        bound.push(*variable);
+        schema = body;
+    }
+    let t = match schema {
+        TypeSchema::Monotype(t) => t,
+        TypeSchema::Polytype { .. } => unreachable!(),
+    };
+    let mut order = Vec::new();
+    appearance(t, &mut order);
+    let taken = order
+        .iter()
+        .filter(|v| !bound.contains(v))
+        .filter_map(|v| enclosing.get(v))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut names = enclosing.clone();
+    let mut shown = String::new();
+    let mut next = 0;
+    for v in order.into_iter().filter(|v| bound.contains(v)) {
+        let name = loop {
+            let name = var_name(next);
+            next += 1;
+            if !taken.contains(&name) {
+                break name;
+            }
+        };
+        shown.push_str(&format!("forall {}. ", name));
+        names.insert(v, name);
+    }
+    shown.push_str(&show_type(t, &names, 0));
+    (shown, names)
+}
+
+fn appearance(t: &Type, order: &mut Vec<Variable>) {
+    match t {
+        Type::Variable(v) if !order.contains(v) => order.push(*v),
+        Type::Variable(_) => (),
+        Type::Constructed(_, args) => match row::as_arrow(t) {
+            // The tails of effect rows aren't displayed, so they go unnamed.
+            Some((alpha, beta, effects)) => {
+                appearance(alpha, order);
+                for label in row::labels(effects).0 {
+                    appearance(label, order);
+                }
+                appearance(beta, order);
+            }
+            None => args.iter().for_each(|a| appearance(a, order)),
+        },
+    }
+}
+
+fn var_name(n: usize) -> String {
+    let letter = (b'a' + (n % 26) as u8) as char;
+    match n / 26 {
+        0 => letter.to_string(),
+        i => format!("{}{}", letter, i),
+    }
+}
+
+// The precedence `prec` is 0 at the top, 1 on the left of an arrow
+// and 2 as the argument of a type constructor.
+// Only the labels of effect rows are shown, as in `a -> {Console} b`.
+fn show_type(t: &Type, names: &HashMap<Variable, String>, prec: u8) -> String {
+    match t {
+        Type::Variable(v) => match names.get(v) {
+            Some(name) => name.clone(),
+            None => format!("t{}", v),
+        },
+        Type::Constructed(name, args) => {
+            let (s, p) = if let Some((alpha, beta, effects)) = row::as_arrow(t) {
+                let alpha = show_type(alpha, names, 1);
+                let labels = row::labels(effects)
+                    .0
+                    .into_iter()
+                    .map(|l| show_type(l, names, 0))
+                    .collect::<Vec<_>>();
+                let effects = match labels.is_empty() {
+                    true => String::new(),
+                    false => format!("{{{}}} ", labels.join(", ")),
+                };
+                let beta = show_type(beta, names, 0);
+                (format!("{} -> {}{}", alpha, effects, beta), 1)
+            } else if args.is_empty() {
+                (name.to_string(), 3)
+            } else {
+                let args = args
+                    .iter()
+                    .map(|a| show_type(a, names, 2))
+                    .collect::<Vec<_>>();
+                (format!("{} {}", name, args.join(" ")), 2)
+            };
+            if prec >= p {
+                format!("({})", s)
+            } else {
+                s
+            }
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::fixity::Fixities;
+    use crate::parser::parse;
+
     use super::*;
 
+    fn check_source(source: &str) -> Result<(), TypeError> {
+        let lexicon = Lexicon::default();
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        items.iter().try_for_each(|item| lexicon.check(item))
+    }
+
     #[test]
     fn block_explicit_return() {
         let lexicon = Lexicon::default();
@@ -183,6 +1257,7 @@ mod tests {
                         ann: None,
                         expr: Expr::Int(42),
                     },
+                    doc: None,
                     attr: None,
                 }),
                 Stmt::Expr(Expr::Name("answer".to_string())),
@@ -199,8 +1274,14 @@ mod tests {
                 kind: ItemKind::Definition {
                     name: "chimera".to_string(),
                     ann: None,
-                    expr: Expr::Str("monstrous fire-breathing hybrid creature".to_string()),
+                    expr: Expr::List(
+                        "monstrous fire-breathing hybrid creature"
+                            .chars()
+                            .map(Expr::Char)
+                            .collect(),
+                    ),
                 },
+                doc: None,
                 attr: None,
             })],
         };
@@ -218,6 +1299,7 @@ mod tests {
                         ann: None,
                         expr: Expr::Bool(true),
                     },
+                    doc: None,
                     attr: None,
                 }),
                 Stmt::Expr(Expr::Block {
@@ -228,6 +1310,7 @@ mod tests {
                                 ann: None,
                                 expr: Expr::Int(0),
                             },
+                            doc: None,
                             attr: None,
                         }),
                         Stmt::Expr(Expr::Name("shadowed".to_string())),
@@ -237,4 +1320,400 @@ mod tests {
         };
         assert_eq!(block.infer(&lexicon), Ok(tp!(Int)));
     }
+
+    #[test]
+    fn pretty_schema() {
+        let ts = TypeSchema::Polytype {
+            variable: 1,
+            body: Box::new(TypeSchema::Polytype {
+                variable: 0,
+                body: Box::new(TypeSchema::Polytype {
+                    variable: 2,
+                    body: Box::new(TypeSchema::Monotype(row::arrow(
+                        row::arrow(tp!(1), tp!(0), tp!(2)),
+                        row::arrow(tp!(List(tp!(1))), tp!(List(tp!(0))), tp!(2)),
+                        tp!(3),
+                    ))),
+                }),
+            }),
+        };
+        assert_eq!(
+            Pretty(&ts).to_string(),
+            "forall a. forall b. (a -> b) -> List a -> List b"
+        );
+    }
+
+    #[test]
+    fn pretty_effects() {
+        let ts = TypeSchema::Polytype {
+            variable: 0,
+            body: Box::new(TypeSchema::Monotype(row::arrow(
+                tp!(Void),
+                tp!(0),
+                row::extend(tp!(State(tp!(0))), row::extend(tp!(Console), tp!(1))),
+            ))),
+        };
+        assert_eq!(
+            Pretty(&ts).to_string(),
+            "forall a. Void -> {State a, Console} a"
+        );
+    }
+
+    #[test]
+    fn negate_non_int() {
+        let lexicon = Lexicon::default();
+        let expr = Expr::Negate {
+            expr: Box::new(Expr::Bool(true)),
+        };
+        assert!(expr.infer(&lexicon).is_err());
+    }
+
+    #[test]
+    fn mismatch() {
+        let source = "let f = |x: Int| x\nlet y = [f true]\n";
+        let start = source.find("f true").unwrap();
+        assert_eq!(
+            check_source(source),
+            Err(TypeError::Mismatch {
+                expected: "Int".to_string(),
+                found: "Bool".to_string(),
+                span: Some(Span::new(source, 0, start, start + 6)),
+            })
+        );
+        // The variables of both types are named alike.
+        let source = "let f = |x| x x\n";
+        assert_eq!(
+            check_source(source).unwrap_err().to_string(),
+            "expected `a` but found `a -> b`"
+        );
+    }
+
+    #[test]
+    fn scope_error() {
+        let source = "let f = |x| x\nlet z = f nope\n";
+        let start = source.find("f nope").unwrap();
+        assert_eq!(
+            check_source(source),
+            Err(TypeError::ScopeError {
+                name: "nope".to_string(),
+                span: Some(Span::new(source, 0, start, start + 6)),
+            })
+        );
+    }
+
+    #[test]
+    fn lambda_shadowing() {
+        let lexicon = Lexicon::default();
+        // |x| (|x| x) x
+        let expr = Expr::Lambda {
+            param: "x".to_string(),
+            ann: None,
+            expr: Box::new(Expr::Apply {
+                left: Box::new(Expr::Lambda {
+                    param: "x".to_string(),
+                    ann: None,
+                    expr: Box::new(Expr::Name("x".to_string())),
+                }),
+                right: Box::new(Expr::Name("x".to_string())),
+            }),
+        };
+        assert!(expr.infer(&lexicon).is_ok());
+    }
+
+    #[test]
+    fn lambda_annotated_param() {
+        let lexicon = Lexicon::default();
+        // |x: Int| x
+        let expr = Expr::Lambda {
+            param: "x".to_string(),
+            ann: Some(tp!(Int)),
+            expr: Box::new(Expr::Name("x".to_string())),
+        };
+        let t = expr.infer(&lexicon).unwrap();
+        assert_eq!(Pretty(&TypeSchema::Monotype(t)).to_string(), "Int -> Int");
+    }
+
+    #[test]
+    fn ascription_mismatch() {
+        let lexicon = Lexicon::default();
+        // (true : Int)
+        let expr = Expr::Ascribe {
+            expr: Box::new(Expr::Bool(true)),
+            ann: tp!(Int),
+        };
+        assert!(expr.infer(&lexicon).is_err());
+    }
+
+    #[test]
+    fn ascription_empty_list() {
+        let lexicon = Lexicon::default();
+        // ([] : List Int)
+        let expr = Expr::Ascribe {
+            expr: Box::new(Expr::List(vec![])),
+            ann: tp!(List(tp!(Int))),
+        };
+        assert_eq!(expr.infer(&lexicon), Ok(tp!(List(tp!(Int)))));
+    }
+
+    #[test]
+    fn annotation_mismatch() {
+        assert_eq!(
+            check_source("let x : Int = true\n"),
+            Err(TypeError::AnnotationMismatch {
+                name: "x".to_string(),
+                expected: "Int".to_string(),
+                found: "Bool".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn annotation_less_general() {
+        assert_eq!(check_source("let id : Int -> Int = |x| x\n"), Ok(()));
+    }
+
+    #[test]
+    fn annotation_too_general() {
+        assert!(check_source("let f : forall a. a -> Int = |x| x\n").is_err());
+    }
+
+    #[test]
+    fn annotation_recursive() {
+        assert_eq!(check_source("let f : forall a. a -> a = |x| f x\n"), Ok(()));
+    }
+
+    #[test]
+    fn annotation_escape() {
+        let source = "let g = |x| do\n    let f : forall a. a -> a = |y| x\n    f\nend\n";
+        assert!(check_source(source).is_err());
+    }
+
+    #[test]
+    fn annotation_intrinsic_trusted() {
+        let source = "@[intrinsic(unit)]\nlet unit : forall a. a = ...\n";
+        assert_eq!(check_source(source), Ok(()));
+    }
+
+    #[test]
+    fn recursive_definition() {
+        let lexicon = Lexicon::default();
+        let source = "let count = |n| count (-n)\n";
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        assert_eq!(lexicon.check_items(&items), Ok(()));
+        assert_eq!(
+            Pretty(&lexicon.schema("count").unwrap()).to_string(),
+            "forall a. Int -> a"
+        );
+    }
+
+    #[test]
+    fn mutually_recursive_definitions() {
+        let lexicon = Lexicon::default();
+        let source = "\
+let ping = |x| pong x
+let pong = |y| if true then y else ping y end
+let both = [ping 1, pong 2]
+let same = (ping true : Bool)
+";
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        assert_eq!(lexicon.check_items(&items), Ok(()));
+        assert_eq!(
+            Pretty(&lexicon.schema("ping").unwrap()).to_string(),
+            "forall a. a -> a"
+        );
+    }
+
+    #[test]
+    fn recursive_block_definitions() {
+        let source = "\
+let f = |n| do
+    let even = |n| if true then true else odd n end
+    let odd = |n| even n
+    odd n
+end
+";
+        assert_eq!(check_source(source), Ok(()));
+    }
+
+    #[test]
+    fn used_before_definition() {
+        let check = |source: &str| {
+            let items = parse(source, &mut Fixities::default()).unwrap();
+            Lexicon::default().check_items(&items)
+        };
+        let early = |name: &str| Err(TypeError::UsedEarly(name.to_string()));
+        assert_eq!(check("let x = [x]\n"), early("x"));
+        assert_eq!(check("let y = [x]\nlet x = 1\n"), early("x"));
+        assert_eq!(check("let f = |_| do\n    g ()\n    let g = |_| 1\nend\n"), early("g"));
+        // Calling a function runs the definitions it refers to.
+        let source = "let f = |_| g ()\nlet y = f ()\nlet g = |_| 1\n";
+        assert_eq!(check(source), early("g"));
+        let source = "mod m\n    let y = x\nend\nlet x = 1\n";
+        assert_eq!(check(source), early("x"));
+        // Functions may refer to what is defined after them, until called.
+        assert_eq!(check("let f = |_| g ()\nlet g = |_| 1\nlet y = f ()\n"), Ok(()));
+    }
+
+    #[test]
+    fn prelude() {
+        let source = std::fs::read_to_string("core.chi").unwrap();
+        let items = parse(&source, &mut Fixities::default()).unwrap();
+        assert_eq!(Lexicon::default().check_items(&items), Ok(()));
+    }
+
+    #[test]
+    fn hole_expected_type() {
+        let lexicon = Lexicon::default();
+        let source = "let f = |x| ([x, ...] : List Bool)\n";
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        assert_eq!(lexicon.check_items(&items), Ok(()));
+        let holes = lexicon.resolved_holes();
+        assert_eq!(holes.len(), 1);
+        assert_eq!(
+            holes[0].to_string(),
+            "hole at 1:18 has type `Bool`\n  with local bindings:\n    x : Bool"
+        );
+    }
+
+    #[test]
+    fn annotation_implicit_forall() {
+        assert_eq!(check_source("let id : a -> a = |x| x\n"), Ok(()));
+        assert!(check_source("let id : a -> b = |x| x\n").is_err());
+    }
+
+    #[test]
+    fn annotation_scoped_variables() {
+        // Only a `forall` scopes over the body, as in Haskell.
+        let body = "|x| do\n    let y : a = x\n    y\nend\n";
+        let scoped = format!("let f : forall a. a -> a = {}", body);
+        assert_eq!(check_source(&scoped), Ok(()));
+        let implicit = format!("let f : a -> a = {}", body);
+        assert!(check_source(&implicit).is_err());
+    }
+
+    #[test]
+    fn annotation_shared_variables() {
+        // The implicit variables of a definition's local annotations are shared.
+        assert!(check_source("let f = |x: a, y: a| x\nlet g = f 1 true\n").is_err());
+        assert_eq!(check_source("let f = |x: a, y: b| x\nlet g = f 1 true\n"), Ok(()));
+        let ascribed = "let f = |x: a| |y| (y : a)\nlet g = f 1 true\n";
+        assert!(check_source(ascribed).is_err());
+        // But not with those of other definitions, nested ones included.
+        let separate = "let f = |x: a| x\nlet g = |y: a| y\nlet n = f 1\nlet b = g true\n";
+        assert_eq!(check_source(separate), Ok(()));
+        let nested = "let f = |x: a| do\n    let g = |y: a| y\n    g true\n    x\nend\n";
+        assert_eq!(check_source(&format!("{nested}let n = f 1\n")), Ok(()));
+    }
+
+    const EFFECTS: &str = "\
+effect Console
+    print : forall a. a -> Void
+end
+effect Ask a
+    ask : Void -> a
+end
+";
+
+    #[test]
+    fn handler_removes_effect() {
+        let handled = "let f : Void -> Int = |_| handle ask () with\n    ask _ k -> k 1\nend\n";
+        assert_eq!(check_source(&format!("{EFFECTS}{handled}")), Ok(()));
+        let unhandled = "let f : Void -> Int = |_| ask ()\n";
+        assert!(check_source(&format!("{EFFECTS}{unhandled}")).is_err());
+    }
+
+    #[test]
+    fn annotation_effects() {
+        let pure = "let f : Int -> Int = |x| do\n    print x\n    x\nend\n";
+        assert!(check_source(&format!("{EFFECTS}{pure}")).is_err());
+        let console = "let f : Int -> {Console} Int = |x| do\n    print x\n    x\nend\n";
+        assert_eq!(check_source(&format!("{EFFECTS}{console}")), Ok(()));
+    }
+
+    #[test]
+    fn handler_clauses() {
+        let foreign = "let x = handle 1 with\n    ask _ k -> k 1\n    print _ k -> k ()\nend\n";
+        assert_eq!(
+            check_source(&format!("{EFFECTS}{foreign}")),
+            Err(TypeError::ForeignOperation("print".to_string(), "Ask".to_string()))
+        );
+        let missing = "effect State s\n    get : Void -> s\n    put : s -> Void\nend\n\
+                       let x = handle 1 with\n    get _ k -> k 0\nend\n";
+        assert_eq!(
+            check_source(missing),
+            Err(TypeError::UnhandledOperation("State".to_string(), "put".to_string()))
+        );
+    }
+
+    /// The schemas of the top-level definitions in `source`, displayed.
+    fn schemas(source: &str) -> Vec<String> {
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        let typed = Lexicon::default().typed_items(&items).unwrap();
+        typed
+            .iter()
+            .filter_map(|item| match &item.kind {
+                typed::ItemKind::Definition { schema, .. } => Some(Pretty(schema).to_string()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn try_catch() {
+        let source = "let f = |x| try [x] catch e -> ([] : List Bool) end\n";
+        assert_eq!(schemas(source), ["Bool -> List Bool"]);
+        // The handler is given the exception, and must produce the same type.
+        assert!(check_source("let x = try 1 catch e -> e end\n").is_err());
+        // Its pattern must match an exception.
+        let data = "data Exception\n    Failure { message: List Char },\nend\n";
+        let source = format!("{}let f = |x| try x catch Failure m -> m end\n", data);
+        assert_eq!(schemas(&source), ["List Char -> List Char"]);
+        let source = format!("{}{}let x = try 1 catch Some x -> x end\n", data, OPTION);
+        assert!(check_source(&source).is_err());
+    }
+
+    const OPTION: &str = "data Option a\n    None {},\n    Some { value: a },\nend\n";
+
+    #[test]
+    fn constructors() {
+        let source = format!("{OPTION}let some = Some\nlet none = None\n");
+        assert_eq!(schemas(&source), ["forall a. a -> Option a", "forall a. Option a"]);
+    }
+
+    #[test]
+    fn match_arms() {
+        let f = "let f = |d o| match o with\n    Some x -> x\n    None -> d\nend\n";
+        assert_eq!(schemas(&format!("{OPTION}{f}")), ["forall a. a -> Option a -> a"]);
+    }
+
+    #[test]
+    fn match_errors() {
+        let missing = "let f = |o| match o with\n    Some x -> x\nend\n";
+        assert_eq!(
+            check_source(&format!("{OPTION}{missing}")),
+            Err(TypeError::NonExhaustive("None".to_string()))
+        );
+        let wildcard = "let f = |o| match o with\n    Some x -> x\n    _ -> 0\nend\n";
+        assert_eq!(check_source(&format!("{OPTION}{wildcard}")), Ok(()));
+        let arity = "let f = |o| match o with\n    Some x y -> x\n    _ -> 0\nend\n";
+        assert_eq!(
+            check_source(&format!("{OPTION}{arity}")),
+            Err(TypeError::ConstructorArity("Some".to_string(), 1, 2))
+        );
+        let unknown = "let f = |o| match o with\n    Nothing -> 0\nend\n";
+        assert_eq!(
+            check_source(unknown),
+            Err(TypeError::NotAConstructor("Nothing".to_string()))
+        );
+    }
+
+    #[test]
+    fn undeclared_field_variables() {
+        // The effects of a function field would be those of any function.
+        let data = "data Run\n    Run { f: Int -> Int },\nend\n";
+        assert_eq!(
+            check_source(data),
+            Err(TypeError::UndeclaredVariable("Run".to_string(), "f".to_string()))
+        );
+    }
 }
@@ -0,0 +1,315 @@
+/// The typed abstract syntax tree, produced once a program type-checks.
+/// Every expression carries its inferred type and every definition its
+/// generalized type schema, for the sake of the passes that need them.
+/// It is built by the type checker, see `Lexicon::typed_items`.
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use polytype::{Type, TypeSchema, Variable};
+
+use crate::ast::{self, Attr, Span};
+use crate::typechecker::show_schema;
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Item {
+    pub attr: Option<Attr>,
+    pub kind: ItemKind,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ItemKind {
+    Definition {
+        name: String,
+        schema: TypeSchema,
+        expr: Expr,
+    },
+    DataType {
+        schema: TypeSchema,
+        variants: Vec<(String, Vec<(String, TypeSchema)>)>,
+    },
+    /// Operations come with their full type schema, effect included.
+    Effect {
+        schema: TypeSchema,
+        operations: Vec<(String, TypeSchema)>,
+    },
+    Module {
+        name: String,
+        items: Vec<Item>,
+    },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Stmt {
+    Item(Item),
+    Expr(Expr),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub ty: Type,
+}
+
+/// Ascriptions are gone, as every expression is now annotated,
+/// and so are infix chains, which were resolved by the fixity pass.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ExprKind {
+    Hole(Span),
+    Void,
+    Int(i64),
+    Bool(bool),
+    Char(char),
+    Name(String),
+    List(Vec<Expr>),
+    Array(Vec<Expr>),
+    Lambda { param: String, expr: Box<Expr> },
+    Block { body: Vec<Stmt> },
+    Apply { left: Box<Expr>, right: Box<Expr> },
+    Negate { expr: Box<Expr> },
+    Branch { paths: Vec<(Expr, Vec<Stmt>)> },
+    Field { expr: Box<Expr>, name: String },
+    Assign { left: Box<Expr>, right: Box<Expr> },
+    Handle { expr: Box<Expr>, clauses: Vec<Clause> },
+    Try { expr: Box<Expr>, pattern: ast::Pattern, handler: Box<Expr> },
+    Match { expr: Box<Expr>, arms: Vec<Arm> },
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Arm {
+    pub pattern: ast::Pattern,
+    pub body: Expr,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Clause {
+    pub operation: String,
+    pub param: String,
+    pub resume: String,
+    pub body: Expr,
+}
+
+impl Item {
+    /// Replaces every type in the item by `f` of it, which is also given the
+    /// variables bound by the schema the type belongs to, if any.
+    pub fn map_types(&mut self, f: &dyn Fn(&Type, &[Variable]) -> Type) {
+        match &mut self.kind {
+            ItemKind::Definition { schema, expr, .. } => {
+                *schema = map_schema(schema, f, &mut Vec::new());
+                expr.map_types(f);
+            }
+            ItemKind::Module { items, .. } => items.iter_mut().for_each(|item| item.map_types(f)),
+            ItemKind::DataType { .. } | ItemKind::Effect { .. } => (),
+        }
+    }
+}
+
+impl Expr {
+    /// See `Item::map_types`.
+    pub fn map_types(&mut self, f: &dyn Fn(&Type, &[Variable]) -> Type) {
+        self.ty = f(&self.ty, &[]);
+        let body = |body: &mut Vec<Stmt>| {
+            body.iter_mut().for_each(|stmt| match stmt {
+                Stmt::Item(item) => item.map_types(f),
+                Stmt::Expr(expr) => expr.map_types(f),
+            })
+        };
+        match &mut self.kind {
+            ExprKind::Block { body: stmts } => body(stmts),
+            ExprKind::Branch { paths } => paths.iter_mut().for_each(|(cond, stmts)| {
+                cond.map_types(f);
+                body(stmts)
+            }),
+            ExprKind::List(elems) | ExprKind::Array(elems) => {
+                elems.iter_mut().for_each(|e| e.map_types(f))
+            }
+            ExprKind::Lambda { expr, .. }
+            | ExprKind::Negate { expr }
+            | ExprKind::Field { expr, .. } => expr.map_types(f),
+            ExprKind::Apply { left, right }
+            | ExprKind::Assign { left, right }
+            | ExprKind::Try { expr: left, handler: right, .. } => {
+                left.map_types(f);
+                right.map_types(f)
+            }
+            ExprKind::Handle { expr, clauses } => {
+                expr.map_types(f);
+                clauses.iter_mut().for_each(|clause| clause.body.map_types(f))
+            }
+            ExprKind::Match { expr, arms } => {
+                expr.map_types(f);
+                arms.iter_mut().for_each(|arm| arm.body.map_types(f))
+            }
+            ExprKind::Hole(_)
+            | ExprKind::Void
+            | ExprKind::Int(_)
+            | ExprKind::Bool(_)
+            | ExprKind::Char(_)
+            | ExprKind::Name(_) => (),
+        }
+    }
+}
+
+fn map_schema(
+    ts: &TypeSchema,
+    f: &dyn Fn(&Type, &[Variable]) -> Type,
+    bound: &mut Vec<Variable>,
+) -> TypeSchema {
+    match ts {
+        TypeSchema::Monotype(t) => TypeSchema::Monotype(f(t, bound)),
+        TypeSchema::Polytype { variable, body } => {
+            bound.push(*variable);
+            TypeSchema::Polytype {
+                variable: *variable,
+                body: Box::new(map_schema(body, f, bound)),
+            }
+        }
+    }
+}
+
+/// Displays the type schema of every definition in `items`, including the
+/// ones nested in blocks, which are indented under their enclosing definition.
+/// The type variables of nested definitions that are bound by an enclosing
+/// schema are named as they are in there.
+pub struct Bindings<'a>(pub &'a [Item]);
+
+type Names = HashMap<Variable, String>;
+
+impl Display for Bindings<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for item in self.0 {
+            fmt_item(item, 0, &Names::new(), f)?;
+        }
+        Ok(())
+    }
+}
+
+fn fmt_item(
+    item: &Item,
+    depth: usize,
+    names: &Names,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    let indent = "  ".repeat(depth);
+    match &item.kind {
+        ItemKind::Definition { name, schema, expr } => {
+            let (shown, names) = show_schema(schema, names);
+            writeln!(f, "{}{} : {}", indent, name, shown)?;
+            fmt_expr(expr, depth + 1, &names, f)
+        }
+        ItemKind::Module { name, items } => {
+            writeln!(f, "{}mod {}", indent, name)?;
+            items
+                .iter()
+                .try_for_each(|item| fmt_item(item, depth + 1, names, f))
+        }
+        ItemKind::Effect { operations, .. } => {
+            for (name, schema) in operations {
+                writeln!(f, "{}{} : {}", indent, name, show_schema(schema, names).0)?;
+            }
+            Ok(())
+        }
+        ItemKind::DataType { .. } => Ok(()),
+    }
+}
+
+/// Only looks for the definitions nested in `expr`.
+fn fmt_expr(
+    expr: &Expr,
+    depth: usize,
+    names: &Names,
+    f: &mut std::fmt::Formatter<'_>,
+) -> std::fmt::Result {
+    let body = |body: &[Stmt], f: &mut std::fmt::Formatter<'_>| {
+        body.iter().try_for_each(|stmt| match stmt {
+            Stmt::Item(item) => fmt_item(item, depth, names, f),
+            Stmt::Expr(expr) => fmt_expr(expr, depth, names, f),
+        })
+    };
+    match &expr.kind {
+        ExprKind::Block { body: stmts } => body(stmts, f),
+        ExprKind::Branch { paths } => paths.iter().try_for_each(|(cond, stmts)| {
+            fmt_expr(cond, depth, names, f)?;
+            body(stmts, f)
+        }),
+        ExprKind::List(elems) | ExprKind::Array(elems) => {
+            elems.iter().try_for_each(|e| fmt_expr(e, depth, names, f))
+        }
+        ExprKind::Lambda { expr, .. }
+        | ExprKind::Negate { expr }
+        | ExprKind::Field { expr, .. } => fmt_expr(expr, depth, names, f),
+        ExprKind::Apply { left, right }
+        | ExprKind::Assign { left, right }
+        | ExprKind::Try { expr: left, handler: right, .. } => {
+            fmt_expr(left, depth, names, f)?;
+            fmt_expr(right, depth, names, f)
+        }
+        ExprKind::Handle { expr, clauses } => {
+            fmt_expr(expr, depth, names, f)?;
+            clauses
+                .iter()
+                .try_for_each(|clause| fmt_expr(&clause.body, depth, names, f))
+        }
+        ExprKind::Match { expr, arms } => {
+            fmt_expr(expr, depth, names, f)?;
+            arms.iter().try_for_each(|arm| fmt_expr(&arm.body, depth, names, f))
+        }
+        ExprKind::Hole(_)
+        | ExprKind::Void
+        | ExprKind::Int(_)
+        | ExprKind::Bool(_)
+        | ExprKind::Char(_)
+        | ExprKind::Name(_) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixity::Fixities;
+    use crate::parser::parse;
+    use crate::typechecker::Lexicon;
+
+    use super::*;
+
+    #[test]
+    fn nested_bindings() {
+        let source = "\
+let f = |x| do
+    let g = |y| y
+    g x
+end
+let n = f 1
+";
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        let typed = Lexicon::default().typed_items(&items).unwrap();
+        assert_eq!(
+            Bindings(&typed).to_string(),
+            "f : forall a. a -> a\n  g : forall a. a -> a\nn : Int\n"
+        );
+        match &typed[1].kind {
+            ItemKind::Definition { expr, .. } => assert_eq!(expr.ty, polytype::tp!(Int)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn enclosing_names() {
+        let source = "\
+let k = |x| do
+    let y = x
+    let g = |z| y
+    g
+end
+let id : a -> a = |x| do
+    let y = x
+    y
+end
+";
+        let items = parse(source, &mut Fixities::default()).unwrap();
+        let typed = Lexicon::default().typed_items(&items).unwrap();
+        assert_eq!(
+            Bindings(&typed).to_string(),
+            "k : forall a. forall b. a -> b -> a\n  y : a\n  g : forall b. b -> a\n\
+             id : forall a. a -> a\n  y : a\n"
+        );
+    }
+}
@@ -2,12 +2,16 @@ use std::cell::RefCell;
 use std::fmt::Display;
 use std::rc::Rc;
 
-use crate::code::{CompiledCode, WoEnv};
+use crate::code::{CompiledCode, Continuation, WoEnv};
+use crate::effect::Resume;
+use crate::sandbox;
+use crate::tree::Tree;
 
 pub type WoValue = Rc<RefCell<Value>>;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Default)]
 pub enum Value {
+    #[default]
     Void,
     Int(i64),
     Bool(bool),
@@ -18,16 +22,36 @@ pub enum Value {
         body: Rc<CompiledCode>,
         closure: WoEnv,
     },
-}
-
-impl Default for Value {
-    fn default() -> Self {
-        Value::Void
-    }
+    // An effect operation, which is performed when applied, or left to its
+    // intrinsic `default` implementation if there is no handler for it.
+    Operation {
+        name: String,
+        default: Option<WoValue>,
+    },
+    // The `resume` function of a handler's clause.
+    Continuation(Resume),
+    // A value of a data type, built by one of its constructors.
+    Data {
+        constructor: String,
+        fields: Vec<WoValue>,
+    },
+    // A constructor waiting for the rest of its `arity` fields.
+    Constructor {
+        name: String,
+        arity: usize,
+        fields: Vec<WoValue>,
+    },
+    // A contiguous and immutable sequence, which is copied when updated.
+    Array(Rc<[WoValue]>),
+    // A persistent map, ordered by its keys.
+    Map(Tree),
+    // A persistent set, as the map of its elements to `()`.
+    Set(Tree),
 }
 
 impl From<Value> for Rc<RefCell<Value>> {
     fn from(item: Value) -> Self {
+        sandbox::allocated();
         Rc::new(RefCell::new(item))
     }
 }
@@ -40,6 +64,12 @@ impl PartialEq for CompiledCode {
     }
 }
 
+impl PartialEq for Continuation {
+    fn eq(&self, _other: &Self) -> bool {
+        false
+    }
+}
+
 /// The representation of a "Cons List" within the interpreter,
 /// as the language isn't mature enough to have custom data types yet.
 /// This is a temporary way of having aggregate data types in Chimera.
@@ -50,16 +80,11 @@ pub enum List {
 }
 
 impl From<Vec<WoValue>> for List {
-    fn from(mut item: Vec<WoValue>) -> Self {
-        // FIXME: this is too slow!
-        if item.is_empty() {
-            List::Nil
-        } else {
-            // Could be better written, probably.
-            let tail = item.drain(1..).collect::<Vec<_>>();
-            let head = item.pop().unwrap();
-            List::Cons(head, Box::new(tail.into()))
-        }
+    fn from(item: Vec<WoValue>) -> Self {
+        // The list is built from its last cell up.
+        item.into_iter()
+            .rev()
+            .fold(List::Nil, |tail, head| List::Cons(head, Box::new(tail)))
     }
 }
 
@@ -86,7 +111,42 @@ impl Display for Value {
             Value::Bool(b) => write!(f, "{}", b),
             Value::Char(c) => write!(f, "{}", c),
             Value::List(l) => write!(f, "{}", l),
-            Value::Lambda { .. } => write!(f, "{:#?}", self),
+            Value::Lambda { .. } | Value::Continuation(_) => write!(f, "{:#?}", self),
+            Value::Operation { name, .. } => write!(f, "{}", name),
+            Value::Data {
+                constructor,
+                fields,
+            } => {
+                write!(f, "{}", constructor)?;
+                for field in fields {
+                    match &*field.borrow() {
+                        Value::Data { fields, .. } if !fields.is_empty() => {
+                            write!(f, " ({})", field.borrow())?
+                        }
+                        value => write!(f, " {}", value)?,
+                    }
+                }
+                Ok(())
+            }
+            Value::Constructor { name, .. } => write!(f, "{}", name),
+            Value::Array(elems) => {
+                let elems = elems.iter().map(|v| v.borrow().to_string()).collect::<Vec<_>>();
+                write!(f, "#[{}]", elems.join(", "))
+            }
+            Value::Map(tree) => {
+                let bindings = tree
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k.borrow(), v.borrow()))
+                    .collect::<Vec<_>>();
+                write!(f, "{{{}}}", bindings.join(", "))
+            }
+            Value::Set(tree) => {
+                let elements = tree
+                    .iter()
+                    .map(|(k, _)| k.borrow().to_string())
+                    .collect::<Vec<_>>();
+                write!(f, "{{{}}}", elements.join(", "))
+            }
         }
     }
 }
@@ -114,4 +174,43 @@ mod tests {
         let list: Vec<WoValue> = List::Cons(Value::Int(1).into(), Box::new(List::Nil)).into();
         assert_eq!(list, vec![Value::Int(1).into()])
     }
+
+    #[test]
+    fn vec_into_list() {
+        let ints = vec![Value::Int(1).into(), Value::Int(2).into()];
+        let list = List::Cons(
+            Value::Int(1).into(),
+            Box::new(List::Cons(Value::Int(2).into(), Box::new(List::Nil))),
+        );
+        assert_eq!(List::from(ints), list);
+    }
+
+    #[test]
+    fn display_data() {
+        let none = Value::Data {
+            constructor: "None".to_string(),
+            fields: Vec::new(),
+        };
+        let some = Value::Data {
+            constructor: "Some".to_string(),
+            fields: vec![none.into()],
+        };
+        let pair = Value::Data {
+            constructor: "Pair".to_string(),
+            fields: vec![some.into(), Value::Int(1).into()],
+        };
+        assert_eq!(pair.to_string(), "Pair (Some None) 1");
+    }
+
+    #[test]
+    fn display_collections() {
+        let tree = Tree::default()
+            .insert(Value::Int(2).into(), Value::Bool(false).into())
+            .insert(Value::Int(1).into(), Value::Bool(true).into());
+        assert_eq!(Value::Map(tree.clone()).to_string(), "{1: true, 2: false}");
+        assert_eq!(Value::Set(tree).to_string(), "{1, 2}");
+        assert_eq!(Value::Map(Tree::default()).to_string(), "{}");
+        let array = Value::Array(vec![Value::Int(1).into(), Value::Int(2).into()].into());
+        assert_eq!(array.to_string(), "#[1, 2]");
+    }
 }